@@ -0,0 +1,996 @@
+//! In-process fake of the Google Cloud Storage JSON API
+//!
+//! Serves the subset of the API that `GcsStore` uses, so the GCS backend can
+//! be tested hermetically without fake-gcs-server or a real bucket:
+//!
+//! - `multipart`, `media` and `resumable` uploads
+//! - object downloads (`alt=media`) with `Range`, `generation` and `x-goog-*`
+//!   headers
+//! - object resources, metadata PATCH and DELETE
+//! - rewrites, continued with rewrite tokens under `maxBytesRewrittenPerCall`
+//! - listing with prefix, delimiter, startOffset and pagination
+//! - `ifGenerationMatch` (0 meaning the object must not exist),
+//!   `ifGenerationNotMatch`, `ifMetagenerationMatch` and
+//!   `ifMetagenerationNotMatch` preconditions, and their `ifSource*`
+//!   counterparts on rewrites
+//!
+//! Objects live in an `InMemoryStore` under `{bucket}/{name}`. Uploads and
+//! rewrites create a new generation, numbered by the store's version, while
+//! patches keep the generation and bump the metageneration, as on GCS. Past
+//! generations can be read when the store is in history mode, as in a bucket
+//! with object versioning.
+//!
+//! Every request is recorded for inspection with `requests`, responses can be
+//! delayed with `set_delay` to test timeouts, `reject_token` refuses requests
+//! bearing a token as expired, `set_chunked_downloads` sends whole objects
+//! without their size, and `fail_upload_chunks` interrupts resumable uploads.
+//!
+//! `FakeTokenServer` fakes the metadata server's access token endpoint, for
+//! testing how `GcsStore` fetches and caches tokens.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::extract::{self, DefaultBodyLimit, Query, Request, State};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use bytes::Bytes;
+use kanso_client::{
+    Condition, DeleteRequest, Error, GetRange, GetRequest, HeadRequest, ListRequest, Metadata,
+    ObjectStore, PatchRequest, Path, PutRequest, Version,
+};
+use kanso_inmemory::{InMemoryStore, Revision};
+use serde_json::json;
+use tokio::task::JoinHandle;
+
+mod token;
+
+pub use token::FakeTokenServer;
+
+/// A fake GCS server listening on a local port
+///
+/// The server is stopped when the FakeGcs is dropped.
+pub struct FakeGcs {
+    endpoint: String,
+    fake: Fake,
+    server: JoinHandle<()>,
+}
+
+/// A request received by a FakeGcs
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: Method,
+    /// Path of the request URL, percent-encoded as sent
+    pub path: String,
+    /// Decoded query parameters
+    pub query: HashMap<String, String>,
+    pub headers: HeaderMap,
+}
+
+impl FakeGcs {
+    /// Start a server backed by an empty store
+    pub async fn start() -> Self {
+        Self::with_store(InMemoryStore::new()).await
+    }
+
+    /// Start a server backed by the given store
+    ///
+    /// Objects in the store are visible through the API under their first
+    /// path segment as the bucket.
+    pub async fn with_store(store: InMemoryStore) -> Self {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind fake GCS listener");
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let state = Fake {
+            store,
+            patched: Arc::default(),
+            objects: Arc::default(),
+            sessions: Arc::default(),
+            requests: Arc::default(),
+            delay: Arc::default(),
+            rejected_tokens: Arc::default(),
+            chunked_downloads: Arc::default(),
+            chunk_failures: Arc::default(),
+        };
+        let app = Router::new()
+            .route("/storage/v1/b/{bucket}/o", get(list))
+            .route(
+                "/storage/v1/b/{bucket}/o/{object}",
+                get(get_object).patch(patch_object).delete(delete_object),
+            )
+            .route(
+                "/storage/v1/b/{bucket}/o/{object}/rewriteTo/b/{destination_bucket}/o/{destination}",
+                post(rewrite_object),
+            )
+            .route(
+                "/upload/storage/v1/b/{bucket}/o",
+                post(start_upload).put(upload_chunk),
+            )
+            // GCS takes uploads of any size in a single request
+            .layer(DefaultBodyLimit::disable())
+            .layer(middleware::from_fn_with_state(state.clone(), record))
+            .with_state(state.clone());
+        let server = tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+        Self {
+            endpoint,
+            fake: state,
+            server,
+        }
+    }
+
+    /// Base URL of the server, to pass to `GcsStore::with_endpoint`
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    /// The store holding the server's objects
+    pub fn store(&self) -> &InMemoryStore {
+        &self.fake.store
+    }
+
+    /// Requests received so far, oldest first
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.fake.requests.lock().unwrap().clone()
+    }
+
+    /// Delay every request received from now on, as a slow server would
+    pub fn set_delay(&self, delay: Duration) {
+        *self.fake.delay.lock().unwrap() = delay;
+    }
+
+    /// Answer requests bearing this access token with 401, as for a token
+    /// that expired or was revoked
+    pub fn reject_token(&self, token: impl Into<String>) {
+        self.fake
+            .rejected_tokens
+            .lock()
+            .unwrap()
+            .insert(token.into());
+    }
+
+    /// Send whole-object downloads chunked and without size headers, as GCS
+    /// does for objects it decompresses on the fly
+    pub fn set_chunked_downloads(&self, chunked: bool) {
+        *self.fake.chunked_downloads.lock().unwrap() = chunked;
+    }
+
+    /// Make the next `count` resumable upload chunks persist half of their
+    /// new data and then fail with 503, as an interrupted upload would
+    pub fn fail_upload_chunks(&self, count: usize) {
+        *self.fake.chunk_failures.lock().unwrap() = count;
+    }
+}
+
+impl Drop for FakeGcs {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+#[derive(Clone)]
+struct Fake {
+    store: InMemoryStore,
+    /// Generations of store versions written by patches, which keep the
+    /// generation; any other store version is a generation of its own
+    patched: Arc<Mutex<HashMap<Version, Generation>>>,
+    /// Serializes object requests, so that preconditions are checked and
+    /// writes made against the same state
+    objects: Arc<tokio::sync::Mutex<()>>,
+    sessions: Arc<Mutex<Sessions>>,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+    delay: Arc<Mutex<Duration>>,
+    rejected_tokens: Arc<Mutex<HashSet<String>>>,
+    chunked_downloads: Arc<Mutex<bool>>,
+    chunk_failures: Arc<Mutex<usize>>,
+}
+
+/// The generation and metageneration of an object
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Generation {
+    generation: u64,
+    metageneration: u64,
+}
+
+#[derive(Default)]
+struct Sessions {
+    next_id: u64,
+    open: HashMap<String, Session>,
+}
+
+/// A resumable upload that has not been finalized yet
+struct Session {
+    key: Path,
+    metadata: Option<Metadata>,
+    preconditions: Preconditions,
+    data: Vec<u8>,
+}
+
+/// An error rendered as a GCS JSON error response
+struct Failure(Error);
+
+impl From<Error> for Failure {
+    fn from(error: Error) -> Self {
+        Failure(error)
+    }
+}
+
+impl IntoResponse for Failure {
+    fn into_response(self) -> Response {
+        let status = match self.0 {
+            Error::NotModified => return StatusCode::NOT_MODIFIED.into_response(),
+            Error::NotFound => StatusCode::NOT_FOUND,
+            Error::ConditionFailed { .. } => StatusCode::PRECONDITION_FAILED,
+            Error::RangeNotSatisfiable => StatusCode::RANGE_NOT_SATISFIABLE,
+            Error::InvalidRequest { .. } => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        let body = json!({
+            "error": { "code": status.as_u16(), "message": self.0.to_string() }
+        });
+        (status, Json(body)).into_response()
+    }
+}
+
+type Result<T> = std::result::Result<T, Failure>;
+
+type Params = Query<HashMap<String, String>>;
+
+fn invalid(message: impl Into<String>) -> Failure {
+    Failure(Error::InvalidRequest {
+        message: message.into(),
+    })
+}
+
+/// Record a request, hold it for the delay set with `set_delay`, and refuse
+/// it if its token was rejected with `reject_token`
+async fn record(State(fake): State<Fake>, request: Request, next: Next) -> Response {
+    let query = Query::<HashMap<String, String>>::try_from_uri(request.uri())
+        .map(|Query(query)| query)
+        .unwrap_or_default();
+    fake.requests.lock().unwrap().push(RecordedRequest {
+        method: request.method().clone(),
+        path: request.uri().path().to_string(),
+        query,
+        headers: request.headers().clone(),
+    });
+    let delay = *fake.delay.lock().unwrap();
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+    let token = request
+        .headers()
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    if token.is_some_and(|token| fake.rejected_tokens.lock().unwrap().contains(token)) {
+        let body = json!({ "error": { "code": 401, "message": "Invalid Credentials" } });
+        return (StatusCode::UNAUTHORIZED, Json(body)).into_response();
+    }
+    next.run(request).await
+}
+
+/// The store key of an object in a bucket
+fn object_key(bucket: &str, name: &str) -> Result<Path> {
+    Path::new(format!("{bucket}/{name}"))
+        .map_err(|e| invalid(format!("invalid object name '{name}': {e}")))
+}
+
+/// A generation or metageneration passed in a query parameter
+fn number_param(params: &HashMap<String, String>, name: &str) -> Result<Option<u64>> {
+    params
+        .get(name)
+        .map(|n| {
+            n.parse()
+                .map_err(|_| invalid(format!("invalid {name} '{n}'")))
+        })
+        .transpose()
+}
+
+/// Generation and metageneration preconditions of a request, on the object
+/// itself or (for rewrites) on the source object
+#[derive(Debug, Default)]
+struct Preconditions {
+    generation_match: Option<u64>,
+    generation_not_match: Option<u64>,
+    metageneration_match: Option<u64>,
+    metageneration_not_match: Option<u64>,
+}
+
+impl Preconditions {
+    /// Parse `if{target}GenerationMatch` and its siblings, with `target`
+    /// empty or `Source`
+    fn parse(params: &HashMap<String, String>, target: &str) -> Result<Self> {
+        Ok(Self {
+            generation_match: number_param(params, &format!("if{target}GenerationMatch"))?,
+            generation_not_match: number_param(params, &format!("if{target}GenerationNotMatch"))?,
+            metageneration_match: number_param(params, &format!("if{target}MetagenerationMatch"))?,
+            metageneration_not_match: number_param(
+                params,
+                &format!("if{target}MetagenerationNotMatch"),
+            )?,
+        })
+    }
+
+    /// Check the preconditions against an object, or `None` if it does not
+    /// exist
+    ///
+    /// Failed `NotMatch` preconditions are `NotModified` on reads; any other
+    /// failure is `ConditionFailed`, whose condition is the nearest kanso
+    /// equivalent.
+    fn check(&self, current: Option<Generation>, read: bool) -> Result<()> {
+        let version = |n: u64| Version::new(n.to_string());
+        let Some(current) = current else {
+            return match self {
+                Self {
+                    generation_match: None | Some(0),
+                    generation_not_match: None,
+                    metageneration_match: None,
+                    metageneration_not_match: None,
+                } => Ok(()),
+                _ => Err(Failure(Error::ConditionFailed {
+                    condition: Condition::IfExists,
+                })),
+            };
+        };
+        let failed = match (
+            self.generation_match,
+            self.metageneration_match,
+            self.generation_not_match,
+            self.metageneration_not_match,
+        ) {
+            (Some(0), ..) => Condition::IfAbsent,
+            (Some(generation), ..) if generation != current.generation => {
+                Condition::IfVersionMatches(version(generation))
+            }
+            (_, Some(metageneration), ..) if metageneration != current.metageneration => {
+                Condition::IfVersionMatches(version(metageneration))
+            }
+            (_, _, Some(generation), _) if generation == current.generation => {
+                if read {
+                    return Err(Failure(Error::NotModified));
+                }
+                Condition::IfVersionNotMatches(version(generation))
+            }
+            (.., Some(metageneration)) if metageneration == current.metageneration => {
+                if read {
+                    return Err(Failure(Error::NotModified));
+                }
+                Condition::IfVersionNotMatches(version(metageneration))
+            }
+            _ => return Ok(()),
+        };
+        Err(Failure(Error::ConditionFailed { condition: failed }))
+    }
+}
+
+impl Fake {
+    /// The generation of the object at a store version
+    fn generation(&self, version: &Version) -> Generation {
+        if let Some(generation) = self.patched.lock().unwrap().get(version) {
+            return *generation;
+        }
+        Generation {
+            generation: version
+                .as_str()
+                .parse()
+                .expect("store versions are numbers"),
+            metageneration: 1,
+        }
+    }
+
+    /// The store version and generation of the current object at a key
+    async fn current(&self, key: &Path) -> Result<Option<(Version, Generation)>> {
+        let head = self.store.head(HeadRequest { key: key.clone() }).await?;
+        Ok(head.map(|head| {
+            let generation = self.generation(&head.version);
+            (head.version, generation)
+        }))
+    }
+
+    /// The store version holding the latest state of a generation, current
+    /// or (in history mode) past
+    async fn find_generation(&self, key: &Path, generation: u64) -> Option<Version> {
+        self.store
+            .history(key)
+            .await
+            .into_iter()
+            .rev()
+            .find_map(|revision| match revision {
+                Revision::Written { version, .. }
+                    if self.generation(&version).generation == generation =>
+                {
+                    Some(version)
+                }
+                _ => None,
+            })
+    }
+}
+
+/// Parse the custom metadata of an object resource
+fn parse_metadata(resource: &serde_json::Value) -> Option<Metadata> {
+    let fields = resource["metadata"].as_object()?;
+    let mut metadata = Metadata::new();
+    for (k, v) in fields {
+        if let Some(v) = v.as_str() {
+            metadata.insert(k, v);
+        }
+    }
+    Some(metadata)
+}
+
+/// Build the JSON object resource for an object in a bucket
+fn object_resource(key: &Path, generation: Generation, metadata: &Metadata) -> serde_json::Value {
+    let (bucket, name) = key.as_str().split_once('/').unwrap();
+    let mut resource = json!({
+        "kind": "storage#object",
+        "bucket": bucket,
+        "name": name,
+        "generation": generation.generation.to_string(),
+        "metageneration": generation.metageneration.to_string(),
+    });
+    if !metadata.headers.is_empty() {
+        resource["metadata"] = json!(metadata.headers);
+    }
+    resource
+}
+
+fn header_value(value: impl AsRef<str>) -> Result<HeaderValue> {
+    HeaderValue::from_str(value.as_ref()).map_err(|e| invalid(format!("invalid header: {e}")))
+}
+
+/// Parse a `Range` header value
+fn parse_range(value: &str) -> Option<GetRange> {
+    let (start, end) = value.strip_prefix("bytes=")?.split_once('-')?;
+    match (start, end) {
+        ("", suffix) => Some(GetRange::Suffix(suffix.parse().ok()?)),
+        (start, "") => Some(GetRange::From(start.parse().ok()?)),
+        (start, end) => {
+            let (start, end): (u64, u64) = (start.parse().ok()?, end.parse().ok()?);
+            Some(GetRange::Bounded {
+                offset: start,
+                length: end.checked_sub(start)? + 1,
+            })
+        }
+    }
+}
+
+/// objects.get: the object resource, or its data with `alt=media`
+async fn get_object(
+    State(fake): State<Fake>,
+    extract::Path((bucket, name)): extract::Path<(String, String)>,
+    Query(params): Params,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let key = object_key(&bucket, &name)?;
+    let _objects = fake.objects.lock().await;
+    let preconditions = Preconditions::parse(&params, "")?;
+    if params.get("alt").map(String::as_str) != Some("media") {
+        let head = fake
+            .store
+            .head(HeadRequest { key: key.clone() })
+            .await?
+            .ok_or(Error::NotFound)?;
+        let generation = fake.generation(&head.version);
+        preconditions.check(Some(generation), true)?;
+        let mut resource = object_resource(&key, generation, &head.metadata);
+        resource["size"] = json!(head.size.to_string());
+        if let Some(updated) = head.last_modified {
+            resource["updated"] = json!(humantime::format_rfc3339_millis(updated).to_string());
+        }
+        if let Some(content_type) = head.content_type {
+            resource["contentType"] = json!(content_type);
+        }
+        return Ok(Json(resource).into_response());
+    }
+
+    let version = match number_param(&params, "generation")? {
+        Some(generation) => fake.find_generation(&key, generation).await,
+        None => fake.current(&key).await?.map(|(version, _)| version),
+    }
+    .ok_or(Error::NotFound)?;
+    let generation = fake.generation(&version);
+    preconditions.check(Some(generation), true)?;
+    let object = fake
+        .store
+        .get(GetRequest {
+            key,
+            range: None,
+            version: Some(version),
+            if_version_not_matches: None,
+        })
+        .await?
+        .ok_or(Error::NotFound)?;
+    let range = headers
+        .get("range")
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_range)
+        .map(|range| range.resolve(object.size))
+        .transpose()?;
+
+    let chunked = range.is_none() && *fake.chunked_downloads.lock().unwrap();
+    let mut response = match &range {
+        Some(range) => (
+            StatusCode::PARTIAL_CONTENT,
+            object.value.slice(range.start as usize..range.end as usize),
+        )
+            .into_response(),
+        None if chunked => {
+            let chunk = Ok::<_, std::convert::Infallible>(object.value);
+            let body = Body::from_stream(futures::stream::once(async move { chunk }));
+            (StatusCode::OK, body).into_response()
+        }
+        None => (StatusCode::OK, object.value).into_response(),
+    };
+    let response_headers = response.headers_mut();
+    if let Some(range) = &range {
+        response_headers.insert(
+            "content-range",
+            header_value(format!(
+                "bytes {}-{}/{}",
+                range.start,
+                range.end - 1,
+                object.size
+            ))?,
+        );
+    }
+    response_headers.insert("content-type", "application/octet-stream".parse().unwrap());
+    response_headers.insert(
+        "x-goog-generation",
+        header_value(generation.generation.to_string())?,
+    );
+    response_headers.insert(
+        "x-goog-metageneration",
+        header_value(generation.metageneration.to_string())?,
+    );
+    if !chunked {
+        response_headers.insert(
+            "x-goog-stored-content-length",
+            header_value(object.size.to_string())?,
+        );
+    }
+    for (k, v) in &object.metadata.headers {
+        let name = HeaderName::try_from(format!("x-goog-meta-{k}"))
+            .map_err(|e| invalid(format!("invalid metadata key '{k}': {e}")))?;
+        response_headers.insert(name, header_value(v)?);
+    }
+    Ok(response)
+}
+
+/// objects.patch: replace the custom metadata of an object, keeping its
+/// generation and bumping its metageneration
+async fn patch_object(
+    State(fake): State<Fake>,
+    extract::Path((bucket, name)): extract::Path<(String, String)>,
+    Query(params): Params,
+    body: Bytes,
+) -> Result<Response> {
+    let key = object_key(&bucket, &name)?;
+    let resource: serde_json::Value =
+        serde_json::from_slice(&body).map_err(|e| invalid(format!("invalid resource: {e}")))?;
+    let metadata = parse_metadata(&resource).unwrap_or_default();
+
+    let _objects = fake.objects.lock().await;
+    let (_, current) = fake.current(&key).await?.ok_or(Error::NotFound)?;
+    Preconditions::parse(&params, "")?.check(Some(current), false)?;
+    let response = fake
+        .store
+        .patch(PatchRequest {
+            key: key.clone(),
+            metadata: metadata.clone(),
+            condition: None,
+        })
+        .await?;
+    let generation = Generation {
+        metageneration: current.metageneration + 1,
+        ..current
+    };
+    fake.patched
+        .lock()
+        .unwrap()
+        .insert(response.version, generation);
+    Ok(Json(object_resource(&key, generation, &metadata)).into_response())
+}
+
+/// objects.delete
+async fn delete_object(
+    State(fake): State<Fake>,
+    extract::Path((bucket, name)): extract::Path<(String, String)>,
+    Query(params): Params,
+) -> Result<Response> {
+    let key = object_key(&bucket, &name)?;
+    let _objects = fake.objects.lock().await;
+    let (_, current) = fake.current(&key).await?.ok_or(Error::NotFound)?;
+    Preconditions::parse(&params, "")?.check(Some(current), false)?;
+    fake.store
+        .delete(DeleteRequest {
+            key,
+            condition: None,
+        })
+        .await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/// objects.rewrite: copy an object to another name
+///
+/// With `maxBytesRewrittenPerCall`, each call only advances the copy by that
+/// many bytes and returns a token (the source's store version and the bytes
+/// copied so far) to continue with; the destination is written by the final
+/// call.
+async fn rewrite_object(
+    State(fake): State<Fake>,
+    extract::Path((bucket, name, destination_bucket, destination)): extract::Path<(
+        String,
+        String,
+        String,
+        String,
+    )>,
+    Query(params): Params,
+    body: Bytes,
+) -> Result<Response> {
+    let source = object_key(&bucket, &name)?;
+    let destination = object_key(&destination_bucket, &destination)?;
+    let _objects = fake.objects.lock().await;
+    let (version, rewritten) = match params.get("rewriteToken") {
+        Some(token) => token
+            .split_once(':')
+            .and_then(|(version, rewritten)| {
+                Some((Some(Version::new(version)), rewritten.parse().ok()?))
+            })
+            .ok_or_else(|| invalid(format!("invalid rewrite token '{token}'")))?,
+        None => match number_param(&params, "sourceGeneration")? {
+            Some(generation) => (
+                Some(
+                    fake.find_generation(&source, generation)
+                        .await
+                        .ok_or(Error::NotFound)?,
+                ),
+                0u64,
+            ),
+            None => (None, 0),
+        },
+    };
+
+    let object = fake
+        .store
+        .get(GetRequest {
+            key: source,
+            range: None,
+            version,
+            if_version_not_matches: None,
+        })
+        .await?
+        .ok_or(Error::NotFound)?;
+    Preconditions::parse(&params, "Source")?
+        .check(Some(fake.generation(&object.version)), false)?;
+    let rewritten = match params.get("maxBytesRewrittenPerCall") {
+        Some(max) => rewritten.saturating_add(
+            max.parse::<u64>()
+                .map_err(|_| invalid(format!("invalid maxBytesRewrittenPerCall '{max}'")))?,
+        ),
+        None => object.size,
+    }
+    .min(object.size);
+    if rewritten < object.size {
+        return Ok(Json(json!({
+            "kind": "storage#rewriteResponse",
+            "totalBytesRewritten": rewritten.to_string(),
+            "objectSize": object.size.to_string(),
+            "done": false,
+            "rewriteToken": format!("{}:{rewritten}", object.version.as_str()),
+        }))
+        .into_response());
+    }
+
+    let current = fake.current(&destination).await?;
+    Preconditions::parse(&params, "")?.check(current.map(|(_, generation)| generation), false)?;
+    let resource: serde_json::Value = if body.is_empty() {
+        json!({})
+    } else {
+        serde_json::from_slice(&body).map_err(|e| invalid(format!("invalid resource: {e}")))?
+    };
+    let metadata = parse_metadata(&resource).unwrap_or(object.metadata);
+    let response = fake
+        .store
+        .put(PutRequest {
+            key: destination.clone(),
+            value: object.value,
+            condition: None,
+            metadata: Some(metadata.clone()),
+        })
+        .await?;
+    let generation = fake.generation(&response.version);
+    let mut resource = object_resource(&destination, generation, &metadata);
+    resource["size"] = json!(object.size.to_string());
+    Ok(Json(json!({
+        "kind": "storage#rewriteResponse",
+        "totalBytesRewritten": object.size.to_string(),
+        "objectSize": object.size.to_string(),
+        "done": true,
+        "resource": resource,
+    }))
+    .into_response())
+}
+
+/// objects.list
+///
+/// `startOffset` is inclusive, as on GCS.
+async fn list(
+    State(fake): State<Fake>,
+    extract::Path(bucket): extract::Path<String>,
+    Query(params): Params,
+) -> Result<Response> {
+    let prefix = params.get("prefix").map_or("", String::as_str);
+    let delimiter = match params.get("delimiter") {
+        Some(delimiter) => {
+            let mut chars = delimiter.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Some(c),
+                _ => return Err(invalid(format!("unsupported delimiter '{delimiter}'"))),
+            }
+        }
+        None => None,
+    };
+    let max_results = params
+        .get("maxResults")
+        .map(|n| {
+            n.parse()
+                .map_err(|_| invalid(format!("invalid maxResults '{n}'")))
+        })
+        .transpose()?;
+    let start_offset = params
+        .get("startOffset")
+        .map(|offset| format!("{bucket}/{offset}"));
+
+    // Every object under the prefix from startOffset on, paginated here
+    let mut objects = Vec::new();
+    let mut all = ListRequest {
+        prefix: format!("{bucket}/{prefix}"),
+        delimiter: None,
+        start_after: None,
+        page_token: None,
+        max_results: None,
+    };
+    loop {
+        let page = fake.store.list(all.clone()).await?;
+        objects.extend(page.objects);
+        match page.next_page_token {
+            Some(token) => all = all.page_token(token),
+            None => break,
+        }
+    }
+    objects.retain(|object| start_offset.as_deref() <= Some(object.key.as_str()));
+    let request = ListRequest {
+        prefix: all.prefix,
+        delimiter,
+        start_after: None,
+        page_token: params.get("pageToken").cloned(),
+        max_results,
+    };
+    let response = request.paginate(
+        objects
+            .into_iter()
+            .map(|object| (object.key.to_string(), object)),
+        |_, object| Ok(Some(object)),
+    )?;
+
+    let strip = |key: &str| key[bucket.len() + 1..].to_string();
+    let items: Vec<_> = response
+        .objects
+        .iter()
+        .map(|object| {
+            let generation = fake.generation(&object.version);
+            let mut resource = object_resource(&object.key, generation, &object.metadata);
+            resource["size"] = json!(object.size.to_string());
+            resource
+        })
+        .collect();
+    let prefixes: Vec<_> = response.common_prefixes.iter().map(|p| strip(p)).collect();
+
+    let mut body = json!({ "kind": "storage#objects" });
+    if !items.is_empty() {
+        body["items"] = json!(items);
+    }
+    if !prefixes.is_empty() {
+        body["prefixes"] = json!(prefixes);
+    }
+    if let Some(token) = response.next_page_token {
+        body["nextPageToken"] = json!(token);
+    }
+    Ok(Json(body).into_response())
+}
+
+/// Store an uploaded object and respond with its resource
+async fn commit(
+    fake: &Fake,
+    key: Path,
+    preconditions: Preconditions,
+    metadata: Option<Metadata>,
+    value: Bytes,
+) -> Result<Response> {
+    let _objects = fake.objects.lock().await;
+    let current = fake.current(&key).await?;
+    preconditions.check(current.map(|(_, generation)| generation), false)?;
+    let size = value.len();
+    let response = fake
+        .store
+        .put(PutRequest {
+            key: key.clone(),
+            value,
+            condition: None,
+            metadata: metadata.clone(),
+        })
+        .await?;
+    let generation = fake.generation(&response.version);
+    let mut resource = object_resource(&key, generation, &metadata.unwrap_or_default());
+    resource["size"] = json!(size.to_string());
+    Ok(Json(resource).into_response())
+}
+
+/// Find `needle` in `haystack`, starting at `from`
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|pos| pos + from)
+}
+
+/// Split a `multipart/related` upload body into its resource and data parts
+fn parse_multipart(headers: &HeaderMap, body: &Bytes) -> Result<(serde_json::Value, Bytes)> {
+    let boundary = headers
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split("boundary=").nth(1))
+        .ok_or_else(|| invalid("missing multipart boundary"))?;
+    let delimiter = format!("\r\n--{boundary}");
+
+    // Each part is "--boundary\r\n<headers>\r\n\r\n<content>", and content
+    // runs until the next "\r\n--boundary"
+    let part = |from: usize| -> Option<(usize, usize)> {
+        let start = find(body, b"\r\n\r\n", from)? + 4;
+        let end = find(body, delimiter.as_bytes(), start)?;
+        Some((start, end))
+    };
+    let (resource, data) = part(0)
+        .and_then(|(start, end)| Some(((start, end), part(end + delimiter.len())?)))
+        .ok_or_else(|| invalid("malformed multipart body"))?;
+
+    let resource = serde_json::from_slice(&body[resource.0..resource.1])
+        .map_err(|e| invalid(format!("invalid resource: {e}")))?;
+    Ok((resource, body.slice(data.0..data.1)))
+}
+
+/// objects.insert: a single-request upload, or the start of a resumable one
+async fn start_upload(
+    State(fake): State<Fake>,
+    extract::Path(bucket): extract::Path<String>,
+    Query(params): Params,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response> {
+    let preconditions = Preconditions::parse(&params, "")?;
+    match params.get("uploadType").map(String::as_str) {
+        Some("media") => {
+            let name = params
+                .get("name")
+                .ok_or_else(|| invalid("missing object name"))?;
+            commit(&fake, object_key(&bucket, name)?, preconditions, None, body).await
+        }
+        Some("multipart") => {
+            let (resource, data) = parse_multipart(&headers, &body)?;
+            let name = resource["name"]
+                .as_str()
+                .ok_or_else(|| invalid("missing object name"))?;
+            let key = object_key(&bucket, name)?;
+            commit(&fake, key, preconditions, parse_metadata(&resource), data).await
+        }
+        Some("resumable") => {
+            let resource: serde_json::Value = serde_json::from_slice(&body)
+                .map_err(|e| invalid(format!("invalid resource: {e}")))?;
+            let name = resource["name"]
+                .as_str()
+                .ok_or_else(|| invalid("missing object name"))?;
+            let session = Session {
+                key: object_key(&bucket, name)?,
+                metadata: parse_metadata(&resource),
+                preconditions,
+                data: Vec::new(),
+            };
+
+            let mut sessions = fake.sessions.lock().unwrap();
+            sessions.next_id += 1;
+            let id = sessions.next_id.to_string();
+            sessions.open.insert(id.clone(), session);
+
+            let host = headers
+                .get("host")
+                .and_then(|v| v.to_str().ok())
+                .ok_or_else(|| invalid("missing host"))?;
+            let location = format!(
+                "http://{host}/upload/storage/v1/b/{bucket}/o?uploadType=resumable&upload_id={id}"
+            );
+            Ok((StatusCode::OK, [("location", header_value(location)?)]).into_response())
+        }
+        other => Err(invalid(format!("unsupported upload type {other:?}"))),
+    }
+}
+
+/// Send data to, or query the status of, a resumable upload session
+async fn upload_chunk(
+    State(fake): State<Fake>,
+    Query(params): Params,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response> {
+    let id = params
+        .get("upload_id")
+        .ok_or_else(|| invalid("missing upload_id"))?;
+
+    // Content-Range: "bytes start-end/total" or "bytes */total", where the
+    // total is "*" until the final chunk
+    let (range, total) = headers
+        .get("content-range")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("bytes "))
+        .and_then(|v| v.split_once('/'))
+        .ok_or_else(|| invalid("missing or malformed Content-Range"))?;
+    let total: Option<usize> = match total {
+        "*" => None,
+        total => Some(total.parse().map_err(|_| invalid("invalid upload size"))?),
+    };
+
+    let finished = {
+        let mut sessions = fake.sessions.lock().unwrap();
+        let session = sessions.open.get_mut(id).ok_or(Error::NotFound)?;
+        if range != "*" {
+            let start: usize = range
+                .split_once('-')
+                .and_then(|(start, _)| start.parse().ok())
+                .ok_or_else(|| invalid("malformed Content-Range"))?;
+            // Resent bytes that were already persisted are skipped
+            let persisted = session.data.len();
+            if start > persisted {
+                return Err(invalid(format!(
+                    "chunk starts at {start} but only {persisted} bytes are persisted"
+                )));
+            }
+            let data = body.get(persisted - start..).unwrap_or_default();
+            let mut failures = fake.chunk_failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                session.data.extend_from_slice(&data[..data.len() / 2]);
+                return Ok(StatusCode::SERVICE_UNAVAILABLE.into_response());
+            }
+            session.data.extend_from_slice(data);
+        }
+
+        if total == Some(session.data.len()) {
+            sessions.open.remove(id)
+        } else {
+            let mut response = StatusCode::PERMANENT_REDIRECT.into_response();
+            if !session.data.is_empty() {
+                let range = format!("bytes=0-{}", session.data.len() - 1);
+                response.headers_mut().insert("range", header_value(range)?);
+            }
+            return Ok(response);
+        }
+    };
+
+    let session = finished.unwrap();
+    commit(
+        &fake,
+        session.key,
+        session.preconditions,
+        session.metadata,
+        Bytes::from(session.data),
+    )
+    .await
+}
@@ -0,0 +1,109 @@
+//! Fake of the metadata server's access token endpoint
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde_json::json;
+use tokio::task::JoinHandle;
+
+/// A fake metadata server issuing access tokens on a local port
+///
+/// Serves `/computeMetadata/v1/instance/service-accounts/default/token`,
+/// issuing `token-1`, `token-2` and so on, one per request. Requests without
+/// the `Metadata-Flavor: Google` header are refused, as by the real server.
+/// The server is stopped when the FakeTokenServer is dropped.
+pub struct FakeTokenServer {
+    endpoint: String,
+    state: Arc<Mutex<TokenState>>,
+    server: JoinHandle<()>,
+}
+
+struct TokenState {
+    issued: usize,
+    expires_in: Duration,
+    failing: bool,
+}
+
+impl FakeTokenServer {
+    /// Start a server issuing tokens that expire in an hour
+    pub async fn start() -> Self {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind fake token listener");
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let state = Arc::new(Mutex::new(TokenState {
+            issued: 0,
+            expires_in: Duration::from_secs(3600),
+            failing: false,
+        }));
+        let app = Router::new()
+            .route(
+                "/computeMetadata/v1/instance/service-accounts/default/token",
+                get(issue_token),
+            )
+            .with_state(state.clone());
+        let server = tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+        Self {
+            endpoint,
+            state,
+            server,
+        }
+    }
+
+    /// Base URL of the server, to use as the metadata server endpoint
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    /// Set the lifetime of tokens issued from now on
+    pub fn set_expires_in(&self, expires_in: Duration) {
+        self.state.lock().unwrap().expires_in = expires_in;
+    }
+
+    /// Fail token requests with 500 until unset
+    pub fn set_failing(&self, failing: bool) {
+        self.state.lock().unwrap().failing = failing;
+    }
+
+    /// Number of tokens issued so far
+    pub fn issued(&self) -> usize {
+        self.state.lock().unwrap().issued
+    }
+}
+
+impl Drop for FakeTokenServer {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+async fn issue_token(State(state): State<Arc<Mutex<TokenState>>>, headers: HeaderMap) -> Response {
+    if headers
+        .get("metadata-flavor")
+        .is_none_or(|flavor| flavor != "Google")
+    {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    let mut state = state.lock().unwrap();
+    if state.failing {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "token service unavailable",
+        )
+            .into_response();
+    }
+    state.issued += 1;
+    Json(json!({
+        "access_token": format!("token-{}", state.issued),
+        "expires_in": state.expires_in.as_secs(),
+        "token_type": "Bearer",
+    }))
+    .into_response()
+}
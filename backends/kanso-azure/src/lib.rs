@@ -0,0 +1,690 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::TryStreamExt;
+use kanso_client::http::{parse_path, parse_prefix, request_error, retry_after};
+use kanso_client::{
+    Condition, DeleteRequest, DeleteResponse, Error, GetRange, GetRequest, GetResponse,
+    GetStreamResponse, HeadRequest, HeadResponse, ListRequest, ListResponse, Metadata, ObjectStore,
+    ObjectSummary, PatchRequest, PatchResponse, Path, PinnedCondition, PutRequest, PutResponse,
+    Version,
+};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Method, Url};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+mod auth;
+#[cfg(test)]
+mod mock;
+
+pub use auth::Credentials;
+
+/// Blob service REST API version sent with every request
+const API_VERSION: &str = "2021-08-06";
+
+/// Reads of a suffix range before giving up on a blob that keeps changing
+const MAX_PINNED_READS: u32 = 3;
+
+/// Azure Blob Storage implementation of ObjectStore using the Blob REST API
+///
+/// Path format: "container-name/path/to/blob"
+/// The container is parsed from the first path component.
+///
+/// Versions are ETags, which change on every write including metadata
+/// updates. Blobs are written as block blobs in a single request. Conditions
+/// map onto `If-Match` and `If-None-Match`, except `IfMetadataMatches`, which
+/// is checked against the blob and pinned to its ETag.
+///
+/// The API has no start-after listing parameter, so `start_after` is applied
+/// to each page of results: pages may come back short or empty, and a common
+/// prefix is returned if `start_after` falls inside it.
+#[derive(Clone, Debug)]
+pub struct AzureStore {
+    client: reqwest::Client,
+    account: String,
+    credentials: Credentials,
+    endpoint: String,
+}
+
+impl AzureStore {
+    /// Create a new AzureStore for a storage account
+    pub fn new(account: impl Into<String>, credentials: Credentials) -> Self {
+        let account = account.into();
+        Self {
+            client: reqwest::Client::new(),
+            endpoint: format!("https://{account}.blob.core.windows.net"),
+            account,
+            credentials,
+        }
+    }
+
+    /// Use a custom endpoint, including the account for path-style endpoints,
+    /// e.g. "http://127.0.0.1:10000/devstoreaccount1" for Azurite
+    pub fn endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = endpoint.into();
+        self
+    }
+
+    /// Build the URL of a container, or of a blob if a name is given
+    fn url(
+        &self,
+        container: &str,
+        blob: Option<&str>,
+        query: &[(&str, &str)],
+    ) -> Result<Url, Error> {
+        let mut url = format!(
+            "{}/{}",
+            self.endpoint.trim_end_matches('/'),
+            urlencoding::encode(container)
+        );
+        if let Some(blob) = blob {
+            for segment in blob.split('/') {
+                url.push('/');
+                url.push_str(&urlencoding::encode(segment));
+            }
+        }
+
+        let mut params: Vec<String> = query
+            .iter()
+            .map(|(k, v)| format!("{k}={}", urlencoding::encode(v)))
+            .collect();
+        if let Credentials::Sas(token) = &self.credentials {
+            params.push(token.clone());
+        }
+        if !params.is_empty() {
+            url.push('?');
+            url.push_str(&params.join("&"));
+        }
+
+        Url::parse(&url).map_err(|e| Error::InvalidRequest {
+            message: format!("invalid url '{url}': {e}"),
+        })
+    }
+
+    /// Authorize and send a request
+    async fn send(
+        &self,
+        method: Method,
+        url: Url,
+        mut headers: HeaderMap,
+        body: Bytes,
+    ) -> Result<reqwest::Response, Error> {
+        headers.insert("x-ms-version", HeaderValue::from_static(API_VERSION));
+        let content_length = body.len();
+        let mut request = self
+            .client
+            .request(method, url)
+            .headers(headers)
+            .body(body)
+            .build()
+            .map_err(request_error)?;
+        if let Credentials::SharedKey(key) = &self.credentials {
+            auth::sign(
+                &mut request,
+                &self.account,
+                key,
+                content_length,
+                SystemTime::now(),
+            );
+        }
+        self.client.execute(request).await.map_err(request_error)
+    }
+
+    /// The preconditions of a write, pinning a metadata condition to the
+    /// blob's current ETag after checking the metadata
+    ///
+    /// ETags change on metadata updates too, so the pinned write fails if the
+    /// blob changes after the check. `must_exist` is set for writes that fail
+    /// with `NotFound` on a missing blob.
+    async fn pin_metadata_condition(
+        &self,
+        key: &Path,
+        condition: &Option<Condition>,
+        must_exist: bool,
+    ) -> Result<Option<Precondition>, Error> {
+        let condition = match condition {
+            None => return Ok(None),
+            Some(Condition::IfAbsent) => return Ok(Some(Precondition::Absent)),
+            Some(Condition::IfExists) => return Ok(Some(Precondition::Exists)),
+            Some(Condition::IfVersionMatches(v)) => {
+                return Ok(Some(Precondition::VersionMatches(v.clone())));
+            }
+            Some(Condition::IfVersionNotMatches(v)) => {
+                return Ok(Some(Precondition::VersionNotMatches(v.clone())));
+            }
+            Some(condition @ Condition::IfMetadataMatches(_)) => condition,
+        };
+        let head = self.head(HeadRequest { key: key.clone() }).await?;
+        if must_exist && head.is_none() {
+            return Err(Error::NotFound);
+        }
+        let pinned = condition.pinned(head.as_ref().map(|h| (&h.version, &h.metadata)))?;
+        Ok(Some(pinned.into()))
+    }
+
+    /// Read a blob, making up to `attempts` reads if a suffix range is
+    /// resolved against a version that changes before it is read
+    async fn read(
+        &self,
+        request: GetRequest,
+        attempts: u32,
+    ) -> Result<Option<GetStreamResponse>, Error> {
+        let (container, blob) = parse_path(&request.key, "container")?;
+        let url = self.url(container, Some(blob), &[])?;
+
+        let mut headers = HeaderMap::new();
+        // Versions are ETags, so only the current version can be read
+        let requested = request.version.clone().map(Precondition::VersionMatches);
+        condition_headers(&requested, &mut headers)?;
+        if let Some(version) = &request.if_version_not_matches {
+            let etag =
+                HeaderValue::from_str(version.as_str()).map_err(|_| Error::InvalidRequest {
+                    message: format!("version '{}' is not a valid ETag", version.as_str()),
+                })?;
+            headers.insert("if-none-match", etag);
+        }
+        match request.range {
+            // Suffix ranges are not supported, so resolve them against the
+            // current size and pin the read to that version
+            Some(range @ GetRange::Suffix(_)) => {
+                let head = self
+                    .head(HeadRequest {
+                        key: request.key.clone(),
+                    })
+                    .await?;
+                let Some(head) = head else {
+                    return Ok(None);
+                };
+                if request.version.as_ref().is_some_and(|v| *v != head.version) {
+                    return Ok(None);
+                }
+                if request.if_version_not_matches.as_ref() == Some(&head.version) {
+                    return Err(Error::NotModified);
+                }
+                let range = range.resolve(head.size)?;
+                let range = GetRange::Bounded {
+                    offset: range.start,
+                    length: range.end - range.start,
+                };
+                headers.insert(
+                    "x-ms-range",
+                    HeaderValue::from_str(&range.header_value()?).unwrap(),
+                );
+                condition_headers(
+                    &Some(Precondition::VersionMatches(head.version)),
+                    &mut headers,
+                )?;
+            }
+            Some(range) => {
+                headers.insert(
+                    "x-ms-range",
+                    HeaderValue::from_str(&range.header_value()?).unwrap(),
+                );
+            }
+            None => {}
+        }
+        let pinned = headers.contains_key("if-match");
+
+        let resp = self.send(Method::GET, url, headers, Bytes::new()).await?;
+
+        match resp.status().as_u16() {
+            304 => Err(Error::NotModified),
+            404 => Ok(None),
+            416 => Err(Error::RangeNotSatisfiable),
+            412 if requested.is_some() => Ok(None),
+            // The blob changed since its size was read
+            412 if pinned && attempts > 1 => Box::pin(self.read(request, attempts - 1)).await,
+            412 if pinned => Err(Error::Transient {
+                message: format!("blob changed during {MAX_PINNED_READS} suffix reads"),
+                source: None,
+            }),
+            status @ (200 | 206) => {
+                // Partial responses carry the total size in Content-Range
+                // ("bytes start-end/size"), full responses in Content-Length
+                let size = if status == 206 {
+                    header(resp.headers(), "content-range").and_then(|v| v.rsplit('/').next())
+                } else {
+                    header(resp.headers(), "content-length")
+                };
+                let size = size
+                    .and_then(|v| v.parse().ok())
+                    .ok_or_else(|| Error::Other("missing blob size header".into()))?;
+                let version = parse_etag(resp.headers())?;
+                let metadata = parse_metadata(resp.headers());
+                let stream = resp.bytes_stream().map_err(request_error);
+
+                Ok(Some(GetStreamResponse {
+                    stream: Box::pin(stream),
+                    size,
+                    version,
+                    metadata,
+                }))
+            }
+            _ => Err(status_error(resp).await),
+        }
+    }
+}
+
+/// Error document returned by the Blob service
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ErrorBody {
+    code: String,
+}
+
+/// Map an unexpected response status onto the error taxonomy
+async fn status_error(resp: reqwest::Response) -> Error {
+    let status = resp.status().as_u16();
+    let retry_after = retry_after(resp.headers());
+    // Bodiless (HEAD) responses carry the code in a header
+    let header_code = resp
+        .headers()
+        .get("x-ms-error-code")
+        .and_then(|v| v.to_str().ok())
+        .map(String::from);
+    let body = resp.text().await.unwrap_or_default();
+    let code = header_code
+        .or_else(|| {
+            quick_xml::de::from_str::<ErrorBody>(&body)
+                .ok()
+                .map(|e| e.code)
+        })
+        .unwrap_or_default();
+
+    match (status, code.as_str()) {
+        (503, "ServerBusy") => Error::RateLimited { retry_after },
+        (500, "OperationTimedOut") => Error::Timeout { source: None },
+        (403, "AuthenticationFailed") => Error::Unauthorized {
+            message: body,
+            source: None,
+        },
+        _ => Error::from_status(status, body, retry_after),
+    }
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+/// Extract the version from the ETag header
+fn parse_etag(headers: &HeaderMap) -> Result<Version, Error> {
+    header(headers, "etag")
+        .map(Version::new)
+        .ok_or_else(|| Error::Other("missing ETag header".into()))
+}
+
+/// Extract custom metadata from x-ms-meta-* headers
+fn parse_metadata(headers: &HeaderMap) -> Metadata {
+    let mut metadata = Metadata::new();
+    for (name, value) in headers {
+        if let Some(key) = name.as_str().strip_prefix("x-ms-meta-")
+            && let Ok(v) = value.to_str()
+        {
+            metadata.insert(key, v);
+        }
+    }
+    metadata
+}
+
+/// Encode custom metadata as x-ms-meta-* headers
+fn metadata_headers(metadata: &Metadata, headers: &mut HeaderMap) -> Result<(), Error> {
+    for (key, value) in &metadata.headers {
+        let name = HeaderName::from_bytes(format!("x-ms-meta-{key}").as_bytes());
+        let value = HeaderValue::from_str(value);
+        match (name, value) {
+            (Ok(name), Ok(value)) => {
+                headers.insert(name, value);
+            }
+            _ => {
+                return Err(Error::InvalidRequest {
+                    message: format!("metadata '{key}' is not a valid header"),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Encode a version as an ETag header value
+fn etag_header(version: &Version) -> Result<HeaderValue, Error> {
+    HeaderValue::from_str(version.as_str()).map_err(|_| Error::InvalidRequest {
+        message: format!("version '{}' is not a valid ETag", version.as_str()),
+    })
+}
+
+/// A condition with conditional headers
+///
+/// Metadata conditions have no header, so `AzureStore::pin_metadata_condition`
+/// pins them to a version.
+enum Precondition {
+    Absent,
+    Exists,
+    VersionMatches(Version),
+    VersionNotMatches(Version),
+}
+
+impl From<PinnedCondition> for Precondition {
+    fn from(condition: PinnedCondition) -> Self {
+        match condition {
+            PinnedCondition::IfAbsent => Precondition::Absent,
+            PinnedCondition::IfVersionMatches(v) => Precondition::VersionMatches(v),
+        }
+    }
+}
+
+/// Add the conditional headers of a request
+fn condition_headers(
+    condition: &Option<Precondition>,
+    headers: &mut HeaderMap,
+) -> Result<(), Error> {
+    match condition {
+        Some(Precondition::Absent) => {
+            headers.insert("if-none-match", HeaderValue::from_static("*"));
+        }
+        Some(Precondition::Exists) => {
+            headers.insert("if-match", HeaderValue::from_static("*"));
+        }
+        Some(Precondition::VersionMatches(v)) => {
+            headers.insert("if-match", etag_header(v)?);
+        }
+        Some(Precondition::VersionNotMatches(v)) => {
+            headers.insert("if-none-match", etag_header(v)?);
+        }
+        None => {}
+    }
+    Ok(())
+}
+
+/// Result document of List Blobs
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct EnumerationResults {
+    #[serde(default)]
+    blobs: Blobs,
+    next_marker: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Blobs {
+    #[serde(rename = "Blob", default)]
+    blobs: Vec<Blob>,
+    #[serde(rename = "BlobPrefix", default)]
+    prefixes: Vec<BlobPrefix>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Blob {
+    name: String,
+    properties: BlobProperties,
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BlobProperties {
+    #[serde(rename = "Etag")]
+    etag: String,
+    #[serde(rename = "Content-Length")]
+    size: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct BlobPrefix {
+    name: String,
+}
+
+#[async_trait]
+impl ObjectStore for AzureStore {
+    async fn get(&self, request: GetRequest) -> Result<Option<GetResponse>, Error> {
+        match self.get_stream(request).await? {
+            Some(response) => Ok(Some(response.collect().await?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_stream(&self, request: GetRequest) -> Result<Option<GetStreamResponse>, Error> {
+        self.read(request, MAX_PINNED_READS).await
+    }
+
+    async fn head(&self, request: HeadRequest) -> Result<Option<HeadResponse>, Error> {
+        let (container, blob) = parse_path(&request.key, "container")?;
+        let url = self.url(container, Some(blob), &[])?;
+
+        let resp = self
+            .send(Method::HEAD, url, HeaderMap::new(), Bytes::new())
+            .await?;
+
+        match resp.status().as_u16() {
+            404 => Ok(None),
+            200 => {
+                let headers = resp.headers();
+                let size = header(headers, "content-length")
+                    .and_then(|v| v.parse().ok())
+                    .ok_or_else(|| Error::Other("missing content length".into()))?;
+                let last_modified = header(headers, "last-modified")
+                    .map(|v| {
+                        httpdate::parse_http_date(v)
+                            .map_err(|e| Error::Other(format!("invalid last modified time: {e}")))
+                    })
+                    .transpose()?;
+
+                Ok(Some(HeadResponse {
+                    version: parse_etag(headers)?,
+                    metadata: parse_metadata(headers),
+                    size,
+                    content_type: header(headers, "content-type").map(String::from),
+                    last_modified,
+                }))
+            }
+            _ => Err(status_error(resp).await),
+        }
+    }
+
+    async fn put(&self, request: PutRequest) -> Result<PutResponse, Error> {
+        let (container, blob) = parse_path(&request.key, "container")?;
+        let url = self.url(container, Some(blob), &[])?;
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ms-blob-type", HeaderValue::from_static("BlockBlob"));
+        if let Some(metadata) = &request.metadata {
+            metadata_headers(metadata, &mut headers)?;
+        }
+        let condition = self
+            .pin_metadata_condition(&request.key, &request.condition, false)
+            .await?;
+        condition_headers(&condition, &mut headers)?;
+
+        let resp = self.send(Method::PUT, url, headers, request.value).await?;
+
+        match resp.status().as_u16() {
+            201 => Ok(PutResponse {
+                version: parse_etag(resp.headers())?,
+            }),
+            // If-None-Match fails with 409 BlobAlreadyExists, If-Match on a
+            // missing blob with 404
+            404 | 409 | 412 if request.condition.is_some() => Err(Error::ConditionFailed {
+                condition: request.condition.unwrap(),
+            }),
+            _ => Err(status_error(resp).await),
+        }
+    }
+
+    async fn patch(&self, request: PatchRequest) -> Result<PatchResponse, Error> {
+        let (container, blob) = parse_path(&request.key, "container")?;
+        let url = self.url(container, Some(blob), &[("comp", "metadata")])?;
+
+        let mut headers = HeaderMap::new();
+        metadata_headers(&request.metadata, &mut headers)?;
+        let condition = self
+            .pin_metadata_condition(&request.key, &request.condition, true)
+            .await?;
+        condition_headers(&condition, &mut headers)?;
+
+        let resp = self.send(Method::PUT, url, headers, Bytes::new()).await?;
+
+        match resp.status().as_u16() {
+            200 => Ok(PatchResponse {
+                version: parse_etag(resp.headers())?,
+            }),
+            404 => Err(Error::NotFound),
+            412 if request.condition.is_some() => Err(Error::ConditionFailed {
+                condition: request.condition.unwrap(),
+            }),
+            _ => Err(status_error(resp).await),
+        }
+    }
+
+    async fn delete(&self, request: DeleteRequest) -> Result<DeleteResponse, Error> {
+        let (container, blob) = parse_path(&request.key, "container")?;
+        let url = self.url(container, Some(blob), &[])?;
+
+        let mut headers = HeaderMap::new();
+        let condition = self
+            .pin_metadata_condition(&request.key, &request.condition, true)
+            .await?;
+        condition_headers(&condition, &mut headers)?;
+
+        let resp = self
+            .send(Method::DELETE, url, headers, Bytes::new())
+            .await?;
+
+        match resp.status().as_u16() {
+            202 => Ok(DeleteResponse),
+            404 => Err(Error::NotFound),
+            412 if request.condition.is_some() => Err(Error::ConditionFailed {
+                condition: request.condition.unwrap(),
+            }),
+            _ => Err(status_error(resp).await),
+        }
+    }
+
+    async fn list(&self, request: ListRequest) -> Result<ListResponse, Error> {
+        let (container, prefix) = parse_prefix(&request.prefix, "container")?;
+
+        let mut delimiter = [0; 4];
+        let max_results = request.max_results.map(|n| n.to_string());
+        let mut query = vec![
+            ("restype", "container"),
+            ("comp", "list"),
+            ("include", "metadata"),
+            ("prefix", prefix),
+        ];
+        if let Some(d) = request.delimiter {
+            query.push(("delimiter", d.encode_utf8(&mut delimiter)));
+        }
+        if let Some(marker) = &request.page_token {
+            query.push(("marker", marker));
+        }
+        if let Some(max_results) = &max_results {
+            query.push(("maxresults", max_results));
+        }
+
+        let start_after = match &request.start_after {
+            Some(start_after) => Some(
+                start_after
+                    .strip_prefix(container)
+                    .and_then(|s| s.strip_prefix('/'))
+                    .ok_or_else(|| Error::InvalidRequest {
+                        message: format!("start_after '{start_after}' is not in container"),
+                    })?,
+            ),
+            None => None,
+        };
+
+        let url = self.url(container, None, &query)?;
+        let resp = self
+            .send(Method::GET, url, HeaderMap::new(), Bytes::new())
+            .await?;
+
+        match resp.status().as_u16() {
+            200 => {
+                let body = resp.text().await.map_err(request_error)?;
+                let result: EnumerationResults = quick_xml::de::from_str(&body)
+                    .map_err(|e| Error::Other(format!("invalid list response: {e}")))?;
+
+                let mut objects = Vec::new();
+                for blob in result.blobs.blobs {
+                    if start_after.is_some_and(|s| blob.name.as_str() <= s) {
+                        continue;
+                    }
+                    let key = Path::new(format!("{container}/{}", blob.name)).map_err(|e| {
+                        Error::Other(format!("invalid blob name '{}': {e}", blob.name))
+                    })?;
+                    // Listings carry bare ETags, headers quoted ones
+                    let etag = match blob.properties.etag.starts_with('"') {
+                        true => blob.properties.etag,
+                        false => format!("\"{}\"", blob.properties.etag),
+                    };
+                    let mut metadata = Metadata::new();
+                    for (k, v) in blob.metadata {
+                        metadata.insert(k, v);
+                    }
+                    objects.push(ObjectSummary {
+                        key,
+                        version: Version::new(etag),
+                        size: blob.properties.size,
+                        metadata,
+                    });
+                }
+                let common_prefixes = result
+                    .blobs
+                    .prefixes
+                    .into_iter()
+                    .filter(|p| {
+                        start_after.is_none_or(|s| p.name.as_str() > s || s.starts_with(&p.name))
+                    })
+                    .map(|p| format!("{container}/{}", p.name))
+                    .collect();
+
+                Ok(ListResponse {
+                    objects,
+                    common_prefixes,
+                    next_page_token: result.next_marker.filter(|m| !m.is_empty()),
+                })
+            }
+            _ => Err(status_error(resp).await),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    /// Well-known Azurite development account key
+    const DEV_KEY: &str =
+        "Eby8vdM02xNOcqFlqUwJPLlmEtlCDXJ1OUzFT50uSRZ6IFsuFq2UVErCz4I6tq/K1SZFPTOtr/KBHBeksoGMGw==";
+
+    #[tokio::test]
+    async fn test_compliance_shared_key() {
+        let credentials = Credentials::shared_key(DEV_KEY).unwrap();
+        let endpoint = mock::start("devstoreaccount1", credentials.clone()).await;
+        let store = AzureStore::new("devstoreaccount1", credentials).endpoint(endpoint);
+        let client: kanso_client::Client = Arc::new(store);
+        kanso_backends_test_suite::run_compliance_tests(&client, "container/").await;
+    }
+
+    #[tokio::test]
+    async fn test_compliance_sas() {
+        let credentials = Credentials::sas("?sv=2021-08-06&sig=c2lnbmF0dXJl");
+        let endpoint = mock::start("devstoreaccount1", credentials.clone()).await;
+        let store = AzureStore::new("devstoreaccount1", credentials).endpoint(endpoint);
+        let client: kanso_client::Client = Arc::new(store);
+        kanso_backends_test_suite::run_compliance_tests(&client, "container/").await;
+    }
+
+    #[tokio::test]
+    async fn test_rejects_bad_credentials() {
+        let credentials = Credentials::shared_key(DEV_KEY).unwrap();
+        let endpoint = mock::start("devstoreaccount1", credentials).await;
+        let wrong = Credentials::shared_key("d3Jvbmc=").unwrap();
+        let store = AzureStore::new("devstoreaccount1", wrong).endpoint(endpoint);
+        let result = store.get(GetRequest::new("container/blob").unwrap()).await;
+        assert!(matches!(result, Err(Error::Unauthorized { .. })));
+    }
+}
@@ -0,0 +1,371 @@
+//! Minimal in-process stand-in for the Blob service
+//!
+//! Supports path-style URLs ("endpoint/account/container/blob") for Put Blob,
+//! Get Blob, Get Blob Properties, Set Blob Metadata, Delete Blob and List
+//! Blobs, checks Shared Key signatures or the SAS token, and honors the
+//! conditional headers kanso sends.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use axum::Router;
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{HeaderMap, Method, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use kanso_client::GetRange;
+
+use crate::auth::{self, Credentials};
+
+struct Blob {
+    data: Bytes,
+    etag: String,
+    metadata: Vec<(String, String)>,
+    last_modified: SystemTime,
+}
+
+struct Mock {
+    account: String,
+    credentials: Credentials,
+    /// Blobs by "container/blob"
+    blobs: Mutex<BTreeMap<String, Blob>>,
+    writes: Mutex<u64>,
+}
+
+/// Start a mock server and return its endpoint
+pub(crate) async fn start(account: &str, credentials: Credentials) -> String {
+    let mock = Arc::new(Mock {
+        account: account.into(),
+        credentials,
+        blobs: Mutex::new(BTreeMap::new()),
+        writes: Mutex::new(0),
+    });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let endpoint = format!("http://{}/{account}", listener.local_addr().unwrap());
+    let app = Router::new().fallback(handle).with_state(mock);
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    endpoint
+}
+
+fn error(status: StatusCode, code: &str) -> Response {
+    let body = format!("<Error><Code>{code}</Code><Message>{code}</Message></Error>");
+    (status, [("x-ms-error-code", code)], body).into_response()
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+fn decode(s: &str) -> String {
+    urlencoding::decode(s).unwrap().into_owned()
+}
+
+/// Parse an x-ms-range header (no suffix ranges)
+fn parse_range(value: &str) -> Option<GetRange> {
+    let (start, end) = value.strip_prefix("bytes=")?.split_once('-')?;
+    let start = start.parse().ok()?;
+    match end {
+        "" => Some(GetRange::From(start)),
+        end => Some(GetRange::Bounded {
+            offset: start,
+            length: end.parse::<u64>().ok()?.checked_sub(start)? + 1,
+        }),
+    }
+}
+
+fn metadata(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .filter_map(|(name, value)| {
+            let key = name.as_str().strip_prefix("x-ms-meta-")?;
+            Some((key.to_string(), value.to_str().ok()?.to_string()))
+        })
+        .collect()
+}
+
+/// Whether If-Match/If-None-Match hold for the current blob
+fn precondition_ok(headers: &HeaderMap, current: Option<&Blob>) -> bool {
+    let if_match = header(headers, "if-match").is_none_or(|expected| {
+        current.is_some_and(|blob| expected == "*" || blob.etag == expected)
+    });
+    let if_none_match = header(headers, "if-none-match").is_none_or(|unexpected| {
+        current.is_none_or(|blob| unexpected != "*" && blob.etag != unexpected)
+    });
+    if_match && if_none_match
+}
+
+impl Mock {
+    fn authorized(&self, method: &Method, uri: &Uri, headers: &HeaderMap) -> bool {
+        match &self.credentials {
+            Credentials::Sas(token) => uri.query().is_some_and(|q| q.contains(token.as_str())),
+            Credentials::SharedKey(key) => {
+                let (Some(authorization), Some(host), Some(date)) = (
+                    header(headers, "authorization"),
+                    header(headers, "host"),
+                    header(headers, "x-ms-date"),
+                ) else {
+                    return false;
+                };
+                let Ok(now) = httpdate::parse_http_date(date) else {
+                    return false;
+                };
+                let content_length = header(headers, "content-length")
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(0);
+
+                let mut request = reqwest::Request::new(
+                    method.clone(),
+                    format!("http://{host}{uri}").parse().unwrap(),
+                );
+                for (name, value) in headers {
+                    if name != "authorization" && name != "content-length" {
+                        request.headers_mut().append(name.clone(), value.clone());
+                    }
+                }
+                auth::sign(&mut request, &self.account, key, content_length, now);
+                request.headers()["authorization"] == authorization
+            }
+        }
+    }
+
+    fn next_etag(&self) -> String {
+        let mut writes = self.writes.lock().unwrap();
+        *writes += 1;
+        format!("\"0x8D{:012X}\"", *writes)
+    }
+
+    fn get(&self, name: &str, headers: &HeaderMap) -> Response {
+        let blobs = self.blobs.lock().unwrap();
+        let Some(blob) = blobs.get(name) else {
+            return error(StatusCode::NOT_FOUND, "BlobNotFound");
+        };
+        // Reads answer a matching If-None-Match with 304, not 412
+        if header(headers, "if-none-match") == Some(blob.etag.as_str()) {
+            return StatusCode::NOT_MODIFIED.into_response();
+        }
+        if !precondition_ok(headers, Some(blob)) {
+            return error(StatusCode::PRECONDITION_FAILED, "ConditionNotMet");
+        }
+        let size = blob.data.len() as u64;
+
+        let mut response = match header(headers, "x-ms-range") {
+            Some(range) => {
+                let Some(range) = parse_range(range).and_then(|r| r.resolve(size).ok()) else {
+                    return error(StatusCode::RANGE_NOT_SATISFIABLE, "InvalidRange");
+                };
+                let content_range = format!("bytes {}-{}/{size}", range.start, range.end - 1);
+                let data = blob.data.slice(range.start as usize..range.end as usize);
+                let mut response = (StatusCode::PARTIAL_CONTENT, data).into_response();
+                response
+                    .headers_mut()
+                    .insert("content-range", content_range.parse().unwrap());
+                response
+            }
+            None => blob.data.clone().into_response(),
+        };
+
+        let response_headers = response.headers_mut();
+        response_headers.insert("etag", blob.etag.parse().unwrap());
+        response_headers.insert(
+            "last-modified",
+            httpdate::fmt_http_date(blob.last_modified).parse().unwrap(),
+        );
+        for (key, value) in &blob.metadata {
+            response_headers.insert(
+                reqwest::header::HeaderName::from_bytes(format!("x-ms-meta-{key}").as_bytes())
+                    .unwrap(),
+                value.parse().unwrap(),
+            );
+        }
+        response
+    }
+
+    fn put(&self, name: String, headers: &HeaderMap, body: Bytes) -> Response {
+        if header(headers, "x-ms-blob-type") != Some("BlockBlob") {
+            return error(StatusCode::BAD_REQUEST, "InvalidHeaderValue");
+        }
+        let mut blobs = self.blobs.lock().unwrap();
+        let current = blobs.get(&name);
+        if header(headers, "if-none-match") == Some("*") && current.is_some() {
+            return error(StatusCode::CONFLICT, "BlobAlreadyExists");
+        }
+        if !precondition_ok(headers, current) {
+            return error(StatusCode::PRECONDITION_FAILED, "ConditionNotMet");
+        }
+
+        let etag = self.next_etag();
+        let blob = Blob {
+            data: body,
+            etag: etag.clone(),
+            metadata: metadata(headers),
+            last_modified: SystemTime::now(),
+        };
+        blobs.insert(name, blob);
+        (StatusCode::CREATED, [("etag", etag)]).into_response()
+    }
+
+    fn set_metadata(&self, name: &str, headers: &HeaderMap) -> Response {
+        let mut blobs = self.blobs.lock().unwrap();
+        let Some(blob) = blobs.get(name) else {
+            return error(StatusCode::NOT_FOUND, "BlobNotFound");
+        };
+        if !precondition_ok(headers, Some(blob)) {
+            return error(StatusCode::PRECONDITION_FAILED, "ConditionNotMet");
+        }
+
+        let etag = self.next_etag();
+        let blob = blobs.get_mut(name).unwrap();
+        blob.etag = etag.clone();
+        blob.metadata = metadata(headers);
+        blob.last_modified = SystemTime::now();
+        (StatusCode::OK, [("etag", etag)]).into_response()
+    }
+
+    fn delete(&self, name: &str, headers: &HeaderMap) -> Response {
+        let mut blobs = self.blobs.lock().unwrap();
+        let Some(blob) = blobs.get(name) else {
+            return error(StatusCode::NOT_FOUND, "BlobNotFound");
+        };
+        if !precondition_ok(headers, Some(blob)) {
+            return error(StatusCode::PRECONDITION_FAILED, "ConditionNotMet");
+        }
+        blobs.remove(name);
+        StatusCode::ACCEPTED.into_response()
+    }
+
+    fn list(&self, container: &str, query: &HashMap<String, String>) -> Response {
+        let param = |name: &str| query.get(name).map(String::as_str).unwrap_or_default();
+        let prefix = param("prefix");
+        let delimiter = param("delimiter");
+        let marker = param("marker");
+        let include_metadata = param("include") == "metadata";
+        let max_results: usize = query
+            .get("maxresults")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(5000);
+
+        let blobs = self.blobs.lock().unwrap();
+        let mut body = String::from("<EnumerationResults><Blobs>");
+        let mut count = 0;
+        let mut last = None;
+        let mut next_marker = None;
+        for (name, blob) in blobs.range(format!("{container}/")..) {
+            let Some(name) = name
+                .strip_prefix(container)
+                .and_then(|n| n.strip_prefix('/'))
+            else {
+                break;
+            };
+            // The marker is the last blob or prefix returned
+            if !name.starts_with(prefix)
+                || name <= marker
+                || (!marker.is_empty() && marker.ends_with(delimiter) && name.starts_with(marker))
+            {
+                continue;
+            }
+            let blob_prefix = match delimiter {
+                "" => None,
+                d => name[prefix.len()..]
+                    .find(d)
+                    .map(|pos| &name[..prefix.len() + pos + d.len()]),
+            };
+            if blob_prefix.is_some() && blob_prefix == last {
+                continue;
+            }
+            if count == max_results {
+                next_marker = last;
+                break;
+            }
+            count += 1;
+            match blob_prefix {
+                Some(p) => {
+                    body.push_str(&format!(
+                        "<BlobPrefix><Name>{}</Name></BlobPrefix>",
+                        xml_escape(p)
+                    ));
+                    last = Some(p);
+                }
+                None => {
+                    let mut metadata = String::new();
+                    if include_metadata {
+                        for (k, v) in &blob.metadata {
+                            metadata.push_str(&format!("<{k}>{}</{k}>", xml_escape(v)));
+                        }
+                    }
+                    body.push_str(&format!(
+                        "<Blob><Name>{}</Name><Properties><Etag>{}</Etag>\
+                         <Content-Length>{}</Content-Length></Properties>\
+                         <Metadata>{metadata}</Metadata></Blob>",
+                        xml_escape(name),
+                        blob.etag.trim_matches('"'),
+                        blob.data.len()
+                    ));
+                    last = Some(name);
+                }
+            }
+        }
+        body.push_str(&format!(
+            "</Blobs><NextMarker>{}</NextMarker></EnumerationResults>",
+            xml_escape(next_marker.unwrap_or_default())
+        ));
+        body.into_response()
+    }
+}
+
+async fn handle(
+    State(mock): State<Arc<Mock>>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    if !mock.authorized(&method, &uri, &headers) {
+        return error(StatusCode::FORBIDDEN, "AuthenticationFailed");
+    }
+    if header(&headers, "x-ms-version").is_none() {
+        return error(StatusCode::BAD_REQUEST, "MissingRequiredHeader");
+    }
+
+    let path = uri.path().trim_start_matches('/');
+    let Some(path) = path
+        .strip_prefix(mock.account.as_str())
+        .and_then(|p| p.strip_prefix('/'))
+    else {
+        return error(StatusCode::BAD_REQUEST, "InvalidUri");
+    };
+    let (container, blob) = match path.split_once('/') {
+        Some((container, blob)) if !blob.is_empty() => (container, Some(decode(blob))),
+        Some((container, _)) => (container, None),
+        None => (path, None),
+    };
+    let query: HashMap<String, String> = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(k, v)| (decode(k), decode(v)))
+        .collect();
+    let comp = query.get("comp").map(String::as_str);
+
+    match (method, blob) {
+        (Method::GET, None) if comp == Some("list") => mock.list(container, &query),
+        (Method::GET | Method::HEAD, Some(blob)) => {
+            mock.get(&format!("{container}/{blob}"), &headers)
+        }
+        (Method::PUT, Some(blob)) if comp == Some("metadata") => {
+            mock.set_metadata(&format!("{container}/{blob}"), &headers)
+        }
+        (Method::PUT, Some(blob)) if comp.is_none() => {
+            mock.put(format!("{container}/{blob}"), &headers, body)
+        }
+        (Method::DELETE, Some(blob)) => mock.delete(&format!("{container}/{blob}"), &headers),
+        _ => error(StatusCode::METHOD_NOT_ALLOWED, "UnsupportedHttpVerb"),
+    }
+}
@@ -0,0 +1,445 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use kanso_client::{
+    Condition, DeleteRequest, DeleteResponse, Error, GetRequest, GetResponse, HeadRequest,
+    HeadResponse, ListRequest, ListResponse, Metadata, ObjectStore, ObjectSummary, PatchRequest,
+    PatchResponse, Path, PutRequest, PutResponse, Version,
+};
+use serde::{Deserialize, Serialize};
+
+const OBJECTS_DIR: &str = "objects";
+const TMP_DIR: &str = "tmp";
+const META_FILE: &str = ".meta";
+const LOCK_FILE: &str = ".lock";
+const DATA_PREFIX: &str = ".data-";
+
+/// Sidecar file describing the current state of an object
+#[derive(Debug, Serialize, Deserialize)]
+struct Meta {
+    version: String,
+    /// Name of the data file within the object directory
+    data: String,
+    size: u64,
+    metadata: HashMap<String, String>,
+}
+
+/// Filesystem implementation of ObjectStore
+///
+/// Layout under the root directory:
+/// - `objects/<segment>/.../<segment>/` holds one object per key: `.meta`
+///   (version, size and metadata as JSON), `.data-<version>` with the value
+///   and `.lock`. Segments starting with `.` or `~` are prefixed with `~`, so
+///   they never collide with these files.
+/// - `tmp/` holds files being written, which are renamed into place.
+///
+/// Writers hold an exclusive lock on the object's `.lock` file and readers a
+/// shared one, so conditions hold across threads and processes sharing the
+/// root. Renaming a new `.meta` into place is the commit point of every write.
+#[derive(Debug, Clone)]
+pub struct FsStore {
+    root: Arc<PathBuf>,
+}
+
+impl FsStore {
+    /// Open a store rooted at the given directory, creating it if needed
+    pub fn new(root: impl Into<PathBuf>) -> Result<Self, Error> {
+        let root = root.into();
+        fs::create_dir_all(root.join(OBJECTS_DIR)).map_err(io_error)?;
+        fs::create_dir_all(root.join(TMP_DIR)).map_err(io_error)?;
+        Ok(Self {
+            root: Arc::new(root),
+        })
+    }
+
+    /// Run blocking filesystem work off the async runtime
+    async fn blocking<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&FsStore) -> Result<T, Error> + Send + 'static,
+    ) -> Result<T, Error> {
+        let store = self.clone();
+        tokio::task::spawn_blocking(move || f(&store))
+            .await
+            .map_err(|e| Error::Other(format!("filesystem task failed: {e}")))?
+    }
+
+    fn object_dir(&self, key: &str) -> PathBuf {
+        let mut dir = self.root.join(OBJECTS_DIR);
+        for segment in key.split('/') {
+            dir.push(encode_segment(segment));
+        }
+        dir
+    }
+
+    /// Take a shared lock on an object, or `None` if it was never written
+    fn lock_shared(&self, dir: &FsPath) -> Result<Option<File>, Error> {
+        match File::open(dir.join(LOCK_FILE)) {
+            Ok(lock) => {
+                lock.lock_shared().map_err(io_error)?;
+                Ok(Some(lock))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(io_error(e)),
+        }
+    }
+
+    /// Take an exclusive lock on an object, creating its directory if needed
+    fn lock_exclusive(&self, dir: &FsPath) -> Result<File, Error> {
+        fs::create_dir_all(dir).map_err(io_error)?;
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(dir.join(LOCK_FILE))
+            .map_err(io_error)?;
+        lock.lock().map_err(io_error)?;
+        Ok(lock)
+    }
+
+    /// Durably write a file by renaming a fully written temporary file over it
+    fn write_atomic(&self, dest: &FsPath, contents: &[u8]) -> Result<(), Error> {
+        let tmp = self
+            .root
+            .join(TMP_DIR)
+            .join(uuid::Uuid::new_v4().simple().to_string());
+        let mut file = File::create(&tmp).map_err(io_error)?;
+        file.write_all(contents).map_err(io_error)?;
+        file.sync_all().map_err(io_error)?;
+        fs::rename(&tmp, dest).map_err(io_error)?;
+        sync_dir(dest)
+    }
+
+    fn get_sync(&self, request: GetRequest) -> Result<Option<GetResponse>, Error> {
+        let dir = self.object_dir(request.key.as_str());
+        let Some(_lock) = self.lock_shared(&dir)? else {
+            return Ok(None);
+        };
+        let Some(meta) = read_meta(&dir)? else {
+            return Ok(None);
+        };
+        // Only the current version is retained
+        if request
+            .version
+            .as_ref()
+            .is_some_and(|v| v.as_str() != meta.version)
+        {
+            return Ok(None);
+        }
+        if request
+            .if_version_not_matches
+            .is_some_and(|v| v.as_str() == meta.version)
+        {
+            return Err(Error::NotModified);
+        }
+
+        let mut file = File::open(dir.join(&meta.data)).map_err(io_error)?;
+        let value = match request.range {
+            Some(range) => {
+                let range = range.resolve(meta.size)?;
+                let mut buf = vec![0; (range.end - range.start) as usize];
+                file.seek(SeekFrom::Start(range.start)).map_err(io_error)?;
+                file.read_exact(&mut buf).map_err(io_error)?;
+                buf
+            }
+            None => {
+                let mut buf = Vec::with_capacity(meta.size as usize);
+                file.read_to_end(&mut buf).map_err(io_error)?;
+                buf
+            }
+        };
+
+        Ok(Some(GetResponse {
+            value: Bytes::from(value),
+            size: meta.size,
+            version: Version::new(meta.version),
+            metadata: Metadata {
+                headers: meta.metadata,
+            },
+        }))
+    }
+
+    fn head_sync(&self, request: HeadRequest) -> Result<Option<HeadResponse>, Error> {
+        let dir = self.object_dir(request.key.as_str());
+        let Some(_lock) = self.lock_shared(&dir)? else {
+            return Ok(None);
+        };
+        let Some(meta) = read_meta(&dir)? else {
+            return Ok(None);
+        };
+        let last_modified = fs::metadata(dir.join(META_FILE))
+            .and_then(|m| m.modified())
+            .ok();
+
+        Ok(Some(HeadResponse {
+            version: Version::new(meta.version),
+            metadata: Metadata {
+                headers: meta.metadata,
+            },
+            size: meta.size,
+            content_type: None,
+            last_modified,
+        }))
+    }
+
+    fn put_sync(&self, request: PutRequest) -> Result<PutResponse, Error> {
+        let dir = self.object_dir(request.key.as_str());
+        let _lock = self.lock_exclusive(&dir)?;
+        let current = read_meta(&dir)?;
+        check_condition(request.condition.as_ref(), current.as_ref())?;
+
+        let version = new_version();
+        let meta = Meta {
+            data: format!("{DATA_PREFIX}{version}"),
+            version: version.clone(),
+            size: request.value.len() as u64,
+            metadata: request.metadata.unwrap_or_default().headers,
+        };
+        self.write_atomic(&dir.join(&meta.data), &request.value)?;
+        self.write_atomic(&dir.join(META_FILE), &encode_meta(&meta)?)?;
+
+        if let Some(old) = current {
+            remove_if_exists(&dir.join(old.data))?;
+        }
+        Ok(PutResponse {
+            version: Version::new(version),
+        })
+    }
+
+    fn patch_sync(&self, request: PatchRequest) -> Result<PatchResponse, Error> {
+        let dir = self.object_dir(request.key.as_str());
+        let _lock = self.lock_exclusive(&dir)?;
+        let current = read_meta(&dir)?.ok_or(Error::NotFound)?;
+        check_condition(request.condition.as_ref(), Some(&current))?;
+
+        // Keep the data file, only the version and metadata change
+        let version = new_version();
+        let meta = Meta {
+            version: version.clone(),
+            metadata: request.metadata.headers,
+            ..current
+        };
+        self.write_atomic(&dir.join(META_FILE), &encode_meta(&meta)?)?;
+
+        Ok(PatchResponse {
+            version: Version::new(version),
+        })
+    }
+
+    fn delete_sync(&self, request: DeleteRequest) -> Result<DeleteResponse, Error> {
+        let dir = self.object_dir(request.key.as_str());
+        let _lock = self.lock_exclusive(&dir)?;
+        let current = read_meta(&dir)?.ok_or(Error::NotFound)?;
+        check_condition(request.condition.as_ref(), Some(&current))?;
+
+        // Removing the sidecar is the commit point; the directory and lock
+        // file stay, since other writers may be waiting on the lock
+        fs::remove_file(dir.join(META_FILE)).map_err(io_error)?;
+        sync_dir(&dir.join(META_FILE))?;
+        remove_if_exists(&dir.join(current.data))?;
+        Ok(DeleteResponse)
+    }
+
+    fn list_sync(&self, request: ListRequest) -> Result<ListResponse, Error> {
+        // Only walk the directory of the prefix's complete segments
+        let (base, base_dir) = match request.prefix.rfind('/') {
+            Some(pos) => (
+                &request.prefix[..pos],
+                self.object_dir(&request.prefix[..pos]),
+            ),
+            None => ("", self.root.join(OBJECTS_DIR)),
+        };
+        let mut keys = Vec::new();
+        match collect_keys(&base_dir, base, &mut keys) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(io_error(e)),
+        }
+        keys.sort();
+
+        request.paginate(keys.iter().map(|key| (key, ())), |key, ()| {
+            let dir = self.object_dir(key);
+            let Some(_lock) = self.lock_shared(&dir)? else {
+                return Ok(None);
+            };
+            // Objects deleted since the walk are skipped
+            let Some(meta) = read_meta(&dir)? else {
+                return Ok(None);
+            };
+            Ok(Some(ObjectSummary {
+                key: Path::new(key).map_err(|e| Error::Other(format!("invalid key: {e}")))?,
+                version: Version::new(meta.version),
+                size: meta.size,
+                metadata: Metadata {
+                    headers: meta.metadata,
+                },
+            }))
+        })
+    }
+}
+
+#[async_trait]
+impl ObjectStore for FsStore {
+    async fn get(&self, request: GetRequest) -> Result<Option<GetResponse>, Error> {
+        self.blocking(move |store| store.get_sync(request)).await
+    }
+
+    async fn head(&self, request: HeadRequest) -> Result<Option<HeadResponse>, Error> {
+        self.blocking(move |store| store.head_sync(request)).await
+    }
+
+    async fn put(&self, request: PutRequest) -> Result<PutResponse, Error> {
+        self.blocking(move |store| store.put_sync(request)).await
+    }
+
+    async fn patch(&self, request: PatchRequest) -> Result<PatchResponse, Error> {
+        self.blocking(move |store| store.patch_sync(request)).await
+    }
+
+    async fn delete(&self, request: DeleteRequest) -> Result<DeleteResponse, Error> {
+        self.blocking(move |store| store.delete_sync(request)).await
+    }
+
+    async fn list(&self, request: ListRequest) -> Result<ListResponse, Error> {
+        self.blocking(move |store| store.list_sync(request)).await
+    }
+}
+
+fn io_error(e: io::Error) -> Error {
+    Error::Other(format!("io error: {e}"))
+}
+
+fn new_version() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+/// Escape a path segment so it never starts with `.` (reserved for the
+/// object's own files)
+fn encode_segment(segment: &str) -> String {
+    if segment.starts_with(['.', '~']) {
+        format!("~{segment}")
+    } else {
+        segment.to_string()
+    }
+}
+
+fn decode_segment(name: &str) -> &str {
+    name.strip_prefix('~').unwrap_or(name)
+}
+
+fn check_condition(condition: Option<&Condition>, current: Option<&Meta>) -> Result<(), Error> {
+    let Some(condition) = condition else {
+        return Ok(());
+    };
+    let current = current.map(|meta| {
+        (
+            Version::new(meta.version.clone()),
+            Metadata {
+                headers: meta.metadata.clone(),
+            },
+        )
+    });
+    if condition.is_satisfied_by(
+        current
+            .as_ref()
+            .map(|(version, metadata)| (version, metadata)),
+    ) {
+        Ok(())
+    } else {
+        Err(Error::ConditionFailed {
+            condition: condition.clone(),
+        })
+    }
+}
+
+fn read_meta(dir: &FsPath) -> Result<Option<Meta>, Error> {
+    match fs::read(dir.join(META_FILE)) {
+        Ok(contents) => serde_json::from_slice(&contents)
+            .map(Some)
+            .map_err(|e| Error::Other(format!("corrupt metadata in {}: {e}", dir.display()))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(io_error(e)),
+    }
+}
+
+fn encode_meta(meta: &Meta) -> Result<Vec<u8>, Error> {
+    serde_json::to_vec(meta).map_err(|e| Error::Other(format!("failed to encode metadata: {e}")))
+}
+
+fn remove_if_exists(path: &FsPath) -> Result<(), Error> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(io_error(e)),
+    }
+}
+
+/// Persist a rename or removal of `path` by syncing its parent directory
+fn sync_dir(path: &FsPath) -> Result<(), Error> {
+    match path.parent() {
+        Some(parent) => File::open(parent)
+            .and_then(|dir| dir.sync_all())
+            .map_err(io_error),
+        None => Ok(()),
+    }
+}
+
+/// Recursively collect the keys of all objects under `dir`
+fn collect_keys(dir: &FsPath, key: &str, keys: &mut Vec<String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        if name == META_FILE && !key.is_empty() {
+            keys.push(key.to_string());
+        } else if !name.starts_with('.') && entry.file_type()?.is_dir() {
+            let child = match key {
+                "" => decode_segment(name).to_string(),
+                _ => format!("{key}/{}", decode_segment(name)),
+            };
+            collect_keys(&entry.path(), &child, keys)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_compliance() {
+        let dir = tempfile::tempdir().unwrap();
+        let store: kanso_client::Client = Arc::new(FsStore::new(dir.path()).unwrap());
+        kanso_backends_test_suite::run_compliance_tests(&store, "").await;
+    }
+
+    #[tokio::test]
+    async fn test_reserved_names_and_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let store: kanso_client::Client = Arc::new(FsStore::new(dir.path()).unwrap());
+        for key in ["a", "a/.meta", "a/~b", ".c"] {
+            PutRequest::new(key, Bytes::from(key))
+                .unwrap()
+                .execute(&store)
+                .await
+                .unwrap();
+        }
+
+        // A second store on the same root sees the same objects
+        let store: kanso_client::Client = Arc::new(FsStore::new(dir.path()).unwrap());
+        let page = ListRequest::new("").unwrap().execute(&store).await.unwrap();
+        let keys: Vec<_> = page.objects.iter().map(|o| o.key.as_str()).collect();
+        assert_eq!(keys, [".c", "a", "a/.meta", "a/~b"]);
+        for key in keys {
+            let resp = GetRequest::new(key).unwrap().execute(&store).await.unwrap();
+            assert_eq!(resp.unwrap().value, key);
+        }
+    }
+}
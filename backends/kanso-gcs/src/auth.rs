@@ -0,0 +1,597 @@
+//! Credentials used to authorize requests to GCS, and the cache of the access
+//! tokens they yield
+//!
+//! Every source of tokens goes through a `TokenCache`, which reuses a token
+//! until it is within the refresh margin of its expiry and then fetches a new
+//! one, once for all concurrent requests. A token GCS rejects is dropped from
+//! the cache, so the next request fetches a new one.
+//!
+//! Failures to load credentials or to fetch a token are reported with an
+//! `AuthError` source: as `Error::Transient` (or `Error::Timeout`) when a
+//! token fetch cannot reach its endpoint, or a metadata server answers that it
+//! is unavailable, so that requests are retried, and as `Error::Unauthorized`
+//! otherwise. Providers (including application default credentials) report
+//! error statuses without the status, so those are always `Unauthorized`.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use futures::future::BoxFuture;
+use gcp_auth::TokenProvider;
+use kanso_client::{BoxError, Error};
+use thiserror::Error;
+
+/// OAuth scope of the tokens requested from providers
+const SCOPES: &[&str] = &["https://www.googleapis.com/auth/devstorage.read_write"];
+
+/// Path of the default service account's token on the metadata server
+const METADATA_TOKEN_PATH: &str = "/computeMetadata/v1/instance/service-accounts/default/token";
+
+/// How long before expiry a cached token is refreshed, as in Google's client
+/// libraries
+pub(crate) const DEFAULT_REFRESH_MARGIN: Duration = Duration::from_secs(225);
+
+/// A function fetching access tokens, for `Credentials::from_fn`
+pub type TokenFn = Arc<dyn Fn() -> BoxFuture<'static, Result<AccessToken, BoxError>> + Send + Sync>;
+
+/// Credentials used to authorize requests to GCS
+#[derive(Clone, Default)]
+pub enum Credentials {
+    /// Application default credentials, as discovered by `gcp_auth::provider`
+    #[default]
+    Default,
+    /// No authorization, for emulators and public buckets
+    Anonymous,
+    /// A fixed access token, used as is
+    Token(String),
+    /// Tokens of the default service account from a metadata server at this
+    /// endpoint
+    MetadataServer(String),
+    /// A custom token provider
+    Provider(Arc<dyn TokenProvider>),
+    /// Tokens from a custom function
+    Function(TokenFn),
+}
+
+impl Credentials {
+    /// Load service account credentials from a JSON key file
+    pub fn service_account_file(path: impl AsRef<std::path::Path>) -> Result<Self, Error> {
+        let account = gcp_auth::CustomServiceAccount::from_file(path)
+            .map_err(|e| AuthError::InvalidCredentials(e.into()).into_error())?;
+        Ok(Credentials::Provider(Arc::new(account)))
+    }
+
+    /// Load service account credentials from the contents of a JSON key file
+    pub fn service_account_json(json: impl AsRef<[u8]>) -> Result<Self, Error> {
+        let account = std::str::from_utf8(json.as_ref())
+            .map_err(BoxError::from)
+            .and_then(|json| gcp_auth::CustomServiceAccount::from_json(json).map_err(Into::into))
+            .map_err(|e| AuthError::InvalidCredentials(e).into_error())?;
+        Ok(Credentials::Provider(Arc::new(account)))
+    }
+
+    /// Use a fixed access token
+    pub fn static_token(token: impl Into<String>) -> Self {
+        Credentials::Token(token.into())
+    }
+
+    /// Fetch tokens from the metadata server at an endpoint, such as
+    /// "http://metadata.google.internal" on GCP or a local fake in tests
+    pub fn metadata_server(endpoint: impl Into<String>) -> Self {
+        Credentials::MetadataServer(endpoint.into())
+    }
+
+    /// Fetch tokens with a custom function, called whenever the cached token
+    /// needs a refresh
+    pub fn from_fn<F, Fut>(f: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<AccessToken, BoxError>> + Send + 'static,
+    {
+        Credentials::Function(Arc::new(move || Box::pin(f())))
+    }
+
+    /// Resolve application default credentials to the provider they name
+    pub(crate) async fn resolve(self) -> Result<Self, Error> {
+        match self {
+            Credentials::Default => gcp_auth::provider()
+                .await
+                .map(Credentials::Provider)
+                .map_err(|e| AuthError::InvalidCredentials(e.into()).into_error()),
+            credentials => Ok(credentials),
+        }
+    }
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Credentials::Default => f.write_str("Default"),
+            Credentials::Anonymous => f.write_str("Anonymous"),
+            Credentials::Token(_) => f.write_str("Token(..)"),
+            Credentials::MetadataServer(endpoint) => {
+                f.debug_tuple("MetadataServer").field(endpoint).finish()
+            }
+            Credentials::Provider(_) => f.write_str("Provider(..)"),
+            Credentials::Function(_) => f.write_str("Function(..)"),
+        }
+    }
+}
+
+/// An access token and when it expires
+#[derive(Clone)]
+pub struct AccessToken {
+    /// The bearer token sent with requests
+    pub token: String,
+    /// When the token expires, or `None` if it never does
+    pub expires_at: Option<SystemTime>,
+}
+
+impl AccessToken {
+    /// Create a token that never expires
+    pub fn new(token: impl Into<String>) -> Self {
+        Self {
+            token: token.into(),
+            expires_at: None,
+        }
+    }
+
+    /// Set the token to expire this long from now
+    pub fn expires_in(mut self, lifetime: Duration) -> Self {
+        self.expires_at = Some(SystemTime::now() + lifetime);
+        self
+    }
+
+    /// Whether the token expires within the margin
+    fn expires_within(&self, margin: Duration) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| SystemTime::now() + margin >= expires_at)
+    }
+}
+
+impl std::fmt::Debug for AccessToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AccessToken")
+            .field("token", &"..")
+            .field("expires_at", &self.expires_at)
+            .finish()
+    }
+}
+
+/// Failure to authorize, the source of `Error::Unauthorized` from GcsStore
+#[derive(Debug, Error)]
+pub enum AuthError {
+    /// Credentials could not be found or loaded
+    #[error("invalid credentials: {0}")]
+    InvalidCredentials(#[source] BoxError),
+    /// The token endpoint answered with an error status
+    #[error("token request failed with status {status}: {body}")]
+    TokenRejected { status: u16, body: String },
+    /// A token could not be fetched
+    #[error("token request failed: {0}")]
+    TokenRequest(#[source] BoxError),
+    /// The token endpoint answered with something other than a token
+    #[error("invalid token response: {0}")]
+    InvalidResponse(String),
+}
+
+impl AuthError {
+    fn into_error(self) -> Error {
+        let message = self.to_string();
+        match &self {
+            AuthError::TokenRejected { status, .. } if *status == 429 || *status >= 500 => {
+                Error::Transient {
+                    message,
+                    source: Some(self.into()),
+                }
+            }
+            AuthError::TokenRequest(e)
+                if e.downcast_ref::<reqwest::Error>()
+                    .is_some_and(reqwest::Error::is_timeout) =>
+            {
+                Error::Timeout {
+                    source: Some(self.into()),
+                }
+            }
+            AuthError::TokenRequest(e)
+                if e.is::<reqwest::Error>()
+                    || e.downcast_ref::<gcp_auth::Error>()
+                        .is_some_and(is_transport_failure) =>
+            {
+                Error::Transient {
+                    message,
+                    source: Some(self.into()),
+                }
+            }
+            _ => Error::Unauthorized {
+                message,
+                source: Some(self.into()),
+            },
+        }
+    }
+}
+
+/// Whether a provider failed to reach its token endpoint: gcp_auth reports
+/// failed requests as `Other` and failed response reads as `Http`
+fn is_transport_failure(error: &gcp_auth::Error) -> bool {
+    matches!(
+        error,
+        gcp_auth::Error::Http(..) | gcp_auth::Error::Other(..)
+    )
+}
+
+/// Access tokens from a source of credentials, cached until they are about
+/// to expire
+pub(crate) struct TokenCache {
+    credentials: Credentials,
+    client: reqwest::Client,
+    refresh_margin: Duration,
+    timeout: Option<Duration>,
+    cached: tokio::sync::Mutex<Option<AccessToken>>,
+}
+
+impl TokenCache {
+    /// Create a cache of tokens from resolved credentials other than
+    /// `Anonymous`, fetching tokens from endpoints with `client` and giving up
+    /// on a fetch after `timeout`
+    pub(crate) fn new(
+        credentials: Credentials,
+        client: reqwest::Client,
+        refresh_margin: Duration,
+        timeout: Option<Duration>,
+    ) -> Self {
+        Self {
+            credentials,
+            client,
+            refresh_margin,
+            timeout,
+            cached: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    /// A token that is not within the refresh margin of its expiry
+    pub(crate) async fn token(&self) -> Result<String, Error> {
+        // Holding the lock while fetching makes concurrent requests share a
+        // single refresh
+        let mut cached = self.cached.lock().await;
+        if let Some(token) = cached.as_ref()
+            && !token.expires_within(self.refresh_margin)
+        {
+            return Ok(token.token.clone());
+        }
+        let token = match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.fetch())
+                .await
+                .map_err(|_| Error::Timeout { source: None })?,
+            None => self.fetch().await,
+        };
+        let token = token.map_err(AuthError::into_error)?;
+        Ok(cached.insert(token).token.clone())
+    }
+
+    /// Drop a token GCS rejected, unless it was already replaced
+    pub(crate) async fn invalidate(&self, token: &str) {
+        let mut cached = self.cached.lock().await;
+        if cached.as_ref().is_some_and(|cached| cached.token == token) {
+            *cached = None;
+        }
+    }
+
+    async fn fetch(&self) -> Result<AccessToken, AuthError> {
+        match &self.credentials {
+            Credentials::Default | Credentials::Anonymous => Err(AuthError::InvalidCredentials(
+                "credentials yield no tokens".into(),
+            )),
+            Credentials::Token(token) => Ok(AccessToken::new(token)),
+            Credentials::MetadataServer(endpoint) => self.fetch_from_metadata(endpoint).await,
+            Credentials::Provider(provider) => {
+                let token = provider
+                    .token(SCOPES)
+                    .await
+                    .map_err(|e| AuthError::TokenRequest(e.into()))?;
+                Ok(AccessToken {
+                    token: token.as_str().to_string(),
+                    expires_at: Some(token.expires_at().into()),
+                })
+            }
+            Credentials::Function(f) => f().await.map_err(AuthError::TokenRequest),
+        }
+    }
+
+    /// Fetch the default service account's token from a metadata server
+    async fn fetch_from_metadata(&self, endpoint: &str) -> Result<AccessToken, AuthError> {
+        let url = format!("{}{METADATA_TOKEN_PATH}", endpoint.trim_end_matches('/'));
+        let resp = self
+            .client
+            .get(&url)
+            .header("Metadata-Flavor", "Google")
+            .send()
+            .await
+            .map_err(|e| AuthError::TokenRequest(e.into()))?;
+
+        let status = resp.status().as_u16();
+        if status != 200 {
+            let body = resp.text().await.unwrap_or_default();
+            return Err(AuthError::TokenRejected { status, body });
+        }
+        let body: serde_json::Value = resp
+            .json()
+            .await
+            .map_err(|e| AuthError::InvalidResponse(e.to_string()))?;
+        let token = body["access_token"]
+            .as_str()
+            .ok_or_else(|| AuthError::InvalidResponse("missing access_token".into()))?;
+        let expires_in = body["expires_in"]
+            .as_u64()
+            .ok_or_else(|| AuthError::InvalidResponse("missing expires_in".into()))?;
+        Ok(AccessToken::new(token).expires_in(Duration::from_secs(expires_in)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use kanso_client::{Client, HeadRequest};
+    use kanso_fake_gcs::{FakeGcs, FakeTokenServer};
+
+    use super::*;
+    use crate::GcsStore;
+
+    /// The authorization header of every request the server received
+    fn authorizations(server: &FakeGcs) -> Vec<String> {
+        server
+            .requests()
+            .iter()
+            .map(|request| {
+                let authorization = request.headers.get("authorization");
+                authorization
+                    .map_or("", |v| v.to_str().unwrap())
+                    .to_string()
+            })
+            .collect()
+    }
+
+    async fn store(endpoint: &str, credentials: Credentials) -> Client {
+        let store = GcsStore::builder()
+            .bucket("bucket")
+            .endpoint(endpoint)
+            .credentials(credentials)
+            .build()
+            .await
+            .unwrap();
+        Arc::new(store)
+    }
+
+    async fn head(client: &Client) -> Result<(), Error> {
+        HeadRequest::new("key")
+            .unwrap()
+            .execute(client)
+            .await
+            .map(|_| ())
+    }
+
+    #[tokio::test]
+    async fn test_metadata_tokens_are_cached_until_near_expiry() {
+        let tokens = FakeTokenServer::start().await;
+        let server = FakeGcs::start().await;
+        let endpoint = server.endpoint();
+        let client = store(endpoint, Credentials::metadata_server(tokens.endpoint())).await;
+
+        head(&client).await.unwrap();
+        head(&client).await.unwrap();
+        assert_eq!(tokens.issued(), 1);
+
+        // A token expiring within the refresh margin is replaced on next use
+        tokens.set_expires_in(Duration::from_secs(60));
+        let client = store(endpoint, Credentials::metadata_server(tokens.endpoint())).await;
+        head(&client).await.unwrap();
+        head(&client).await.unwrap();
+        assert_eq!(tokens.issued(), 3);
+        assert_eq!(
+            authorizations(&server),
+            [
+                "Bearer token-1",
+                "Bearer token-1",
+                "Bearer token-2",
+                "Bearer token-3"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_requests_share_a_refresh() {
+        let tokens = FakeTokenServer::start().await;
+        let server = FakeGcs::start().await;
+        let client = store(
+            server.endpoint(),
+            Credentials::metadata_server(tokens.endpoint()),
+        )
+        .await;
+
+        let heads = (0..8).map(|_| head(&client));
+        for result in futures::future::join_all(heads).await {
+            result.unwrap();
+        }
+        assert_eq!(tokens.issued(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_token_failures() {
+        let tokens = FakeTokenServer::start().await;
+        let server = FakeGcs::start().await;
+        tokens.set_failing(true);
+        let client = store(
+            server.endpoint(),
+            Credentials::metadata_server(tokens.endpoint()),
+        )
+        .await;
+
+        // An unavailable metadata server is worth retrying
+        let error = head(&client).await.unwrap_err();
+        assert!(error.is_retryable());
+        let Error::Transient {
+            source: Some(source),
+            ..
+        } = error
+        else {
+            panic!("expected a transient error");
+        };
+        assert!(matches!(
+            source.downcast_ref::<AuthError>(),
+            Some(AuthError::TokenRejected { status: 500, .. })
+        ));
+
+        // The next request tries again
+        tokens.set_failing(false);
+        head(&client).await.unwrap();
+        assert_eq!(tokens.issued(), 1);
+
+        // So is one that cannot be reached
+        drop(tokens);
+        let client = store(
+            server.endpoint(),
+            Credentials::metadata_server("http://127.0.0.1:1"),
+        )
+        .await;
+        assert!(matches!(head(&client).await, Err(Error::Transient { .. })));
+
+        let invalid = Credentials::service_account_json("{}");
+        let Err(Error::Unauthorized {
+            source: Some(source),
+            ..
+        }) = invalid
+        else {
+            panic!("expected an auth error");
+        };
+        assert!(matches!(
+            source.downcast_ref::<AuthError>(),
+            Some(AuthError::InvalidCredentials(_))
+        ));
+    }
+
+    /// A provider whose token endpoint cannot be reached
+    struct Unreachable;
+
+    #[async_trait::async_trait]
+    impl TokenProvider for Unreachable {
+        async fn token(&self, _scopes: &[&str]) -> Result<Arc<gcp_auth::Token>, gcp_auth::Error> {
+            let refused = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+            Err(gcp_auth::Error::Other(
+                "HTTP request failed",
+                refused.into(),
+            ))
+        }
+
+        async fn project_id(&self) -> Result<Arc<str>, gcp_auth::Error> {
+            Err(gcp_auth::Error::Str("no project ID"))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_providers_are_retried() {
+        let server = FakeGcs::start().await;
+        let client = store(
+            server.endpoint(),
+            Credentials::Provider(Arc::new(Unreachable)),
+        )
+        .await;
+
+        let error = head(&client).await.unwrap_err();
+        assert!(error.is_retryable());
+        let Error::Transient {
+            source: Some(source),
+            ..
+        } = error
+        else {
+            panic!("expected a transient error");
+        };
+        assert!(matches!(
+            source.downcast_ref::<AuthError>(),
+            Some(AuthError::TokenRequest(_))
+        ));
+        assert!(server.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_token_fetches_time_out() {
+        let server = FakeGcs::start().await;
+        let credentials = Credentials::from_fn(|| async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Ok(AccessToken::new("late"))
+        });
+        let store = GcsStore::builder()
+            .bucket("bucket")
+            .endpoint(server.endpoint())
+            .credentials(credentials)
+            .timeout(Duration::from_millis(100))
+            .build()
+            .await
+            .unwrap();
+        let client: Client = Arc::new(store);
+
+        assert!(matches!(head(&client).await, Err(Error::Timeout { .. })));
+        assert!(server.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_rejected_tokens_are_dropped() {
+        let tokens = FakeTokenServer::start().await;
+        let server = FakeGcs::start().await;
+        let client = store(
+            server.endpoint(),
+            Credentials::metadata_server(tokens.endpoint()),
+        )
+        .await;
+
+        head(&client).await.unwrap();
+        server.reject_token("token-1");
+        assert!(matches!(
+            head(&client).await,
+            Err(Error::Unauthorized { .. })
+        ));
+
+        // The next request fetches a new token instead of reusing the cached one
+        head(&client).await.unwrap();
+        assert_eq!(tokens.issued(), 2);
+        assert_eq!(
+            authorizations(&server),
+            ["Bearer token-1", "Bearer token-1", "Bearer token-2"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_static_tokens_and_functions() {
+        let server = FakeGcs::start().await;
+        let endpoint = server.endpoint();
+
+        let client = store(endpoint, Credentials::static_token("fixed")).await;
+        head(&client).await.unwrap();
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let credentials = Credentials::from_fn(move || {
+            let n = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            async move { Ok(AccessToken::new(format!("fn-{n}")).expires_in(Duration::from_secs(3600))) }
+        });
+        let client = store(endpoint, credentials).await;
+        head(&client).await.unwrap();
+        head(&client).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let client = store(endpoint, Credentials::Anonymous).await;
+        head(&client).await.unwrap();
+
+        let seen = authorizations(&server);
+        let counts = seen.iter().fold(HashMap::new(), |mut counts, auth| {
+            *counts.entry(auth.as_str()).or_insert(0) += 1;
+            counts
+        });
+        assert_eq!(
+            counts,
+            HashMap::from([("Bearer fixed", 1), ("Bearer fn-1", 2), ("", 1)])
+        );
+    }
+}
@@ -1,99 +1,606 @@
 use async_trait::async_trait;
+use futures::TryStreamExt;
+use kanso_client::http::{parse_path, parse_prefix, request_error, status_error};
 use kanso_client::{
-    Condition, Error, GetRequest, GetResponse, Metadata, ObjectStore, PatchRequest, PatchResponse,
-    Path, PutRequest, PutResponse, Version,
+    ByteStream, Condition, CopyRequest, CopyResponse, DeleteRequest, DeleteResponse, Error,
+    GetRequest, GetResponse, GetStreamResponse, HeadRequest, HeadResponse, ListRequest,
+    ListResponse, Metadata, ObjectStore, ObjectSummary, PatchRequest, PatchResponse, Path,
+    PutRequest, PutResponse, PutStreamRequest, Version,
 };
+use reqwest::Method;
 use std::sync::Arc;
+use std::time::Duration;
+
+mod auth;
+mod upload;
+
+use auth::TokenCache;
+
+pub use auth::{AccessToken, AuthError, Credentials, TokenFn};
+
+/// Per-call rewrite limits must be a multiple of 1 MiB
+const REWRITE_ALIGNMENT: usize = 1024 * 1024;
+
+/// Endpoint of the GCS JSON API
+const DEFAULT_ENDPOINT: &str = "https://storage.googleapis.com";
 
 /// GCS implementation of ObjectStore using direct JSON API calls
 ///
-/// Path format: "bucket-name/path/to/object"
-/// The bucket is parsed from the first path component.
+/// A store is either bound to one bucket, in which case keys are object names
+/// (as in "path/to/object"), or finds the bucket in the first component of
+/// every key (as in "bucket-name/path/to/object"). See `GcsStore::builder`.
+///
+/// Object names are percent-encoded whole (including `/` and `%`) in request
+/// URLs, so paths from `Path::from_segments_encoded` are stored with their
+/// escapes intact and decode back to the original segments.
+///
+/// Versions are an object's generation and metageneration, as in
+/// "1700000000000000:2": uploads and copies create a new generation, while
+/// metadata patches keep it and bump the metageneration. Version conditions
+/// compare both, with `ifGenerationMatch` and `ifMetagenerationMatch`.
+/// Versions naming only a generation are accepted, and compare it alone.
+///
+/// Objects smaller than the resumable threshold are uploaded in a single
+/// multipart request; larger objects use chunked resumable upload sessions.
+/// Copies use the `rewriteTo` API, which copies server-side over as many calls
+/// as GCS needs.
+///
+/// Listings map `start_after` onto `startOffset`, which is inclusive, and
+/// drop an object named exactly `start_after` from the results. The first
+/// page then comes back one short of `max_results`, without ending the
+/// listing (see `ListRequest::max_results`); later pages are full.
 #[derive(Clone)]
 pub struct GcsStore {
     client: reqwest::Client,
-    auth: Option<Arc<dyn gcp_auth::TokenProvider>>,
+    auth: Option<Arc<TokenCache>>,
     endpoint: String,
+    bucket: Bucket,
+    user_project: Option<String>,
+    timeout: Option<Duration>,
+    resumable_threshold: usize,
+    chunk_size: usize,
+    rewrite_chunk_size: Option<usize>,
+}
+
+/// Where a GcsStore finds the bucket of a key
+#[derive(Debug, Clone)]
+enum Bucket {
+    /// Every key is an object name in this bucket
+    Bound(String),
+    /// Every key starts with its bucket
+    InPath,
+}
+
+/// Configuration for a GcsStore
+///
+/// A bucket must be chosen, either with `bucket` or, for keys that start
+/// with their bucket, with `bucket_in_path`.
+#[derive(Debug, Default)]
+pub struct GcsStoreBuilder {
+    bucket: Option<Bucket>,
+    endpoint: Option<String>,
+    credentials: Credentials,
+    user_project: Option<String>,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    client: Option<reqwest::Client>,
+    token_refresh_margin: Option<Duration>,
+}
+
+impl GcsStoreBuilder {
+    /// Bind the store to a bucket, so that keys are object names
+    pub fn bucket(mut self, bucket: impl Into<String>) -> Self {
+        self.bucket = Some(Bucket::Bound(bucket.into()));
+        self
+    }
+
+    /// Find the bucket in the first component of every key, as in
+    /// "bucket-name/path/to/object"
+    pub fn bucket_in_path(mut self) -> Self {
+        self.bucket = Some(Bucket::InPath);
+        self
+    }
+
+    /// Use a custom endpoint instead of `https://storage.googleapis.com`
+    pub fn endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = Some(endpoint.into());
+        self
+    }
+
+    /// Set the credentials (application default credentials by default)
+    pub fn credentials(mut self, credentials: Credentials) -> Self {
+        self.credentials = credentials;
+        self
+    }
+
+    /// Refresh cached access tokens this long before they expire (by default
+    /// 3 minutes 45 seconds)
+    pub fn token_refresh_margin(mut self, margin: Duration) -> Self {
+        self.token_refresh_margin = Some(margin);
+        self
+    }
+
+    /// Bill requests to this project, as requester-pays buckets require
+    pub fn user_project(mut self, project: impl Into<String>) -> Self {
+        self.user_project = Some(project.into());
+        self
+    }
+
+    /// Limit the time of each request, including reading its response body
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Limit the time to establish a connection
+    ///
+    /// Cannot be combined with `http_client`, which has its own settings.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Send requests with a custom HTTP client
+    pub fn http_client(mut self, client: reqwest::Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Build the store, resolving its credentials
+    ///
+    /// Returns `InvalidRequest` if no bucket was chosen or the connect timeout
+    /// is set along with a custom client, and `Unauthorized` if default
+    /// credentials cannot be found.
+    pub async fn build(mut self) -> Result<GcsStore, Error> {
+        if self.bucket.is_none() {
+            return Err(Error::InvalidRequest {
+                message: "GcsStore needs a bucket, or bucket_in_path() for keys \
+                          that start with their bucket"
+                    .into(),
+            });
+        }
+        let client = match (self.client.take(), self.connect_timeout) {
+            (Some(_), Some(_)) => {
+                return Err(Error::InvalidRequest {
+                    message: "connect_timeout cannot be combined with a custom http_client".into(),
+                });
+            }
+            (Some(client), None) => client,
+            (None, connect_timeout) => {
+                let mut builder = reqwest::Client::builder();
+                if let Some(timeout) = connect_timeout {
+                    builder = builder.connect_timeout(timeout);
+                }
+                builder.build().map_err(request_error)?
+            }
+        };
+        let auth = match std::mem::take(&mut self.credentials).resolve().await? {
+            Credentials::Anonymous => None,
+            credentials => Some(Arc::new(TokenCache::new(
+                credentials,
+                client.clone(),
+                self.token_refresh_margin
+                    .unwrap_or(auth::DEFAULT_REFRESH_MARGIN),
+                self.timeout,
+            ))),
+        };
+        Ok(self.finish(client, auth))
+    }
+
+    /// Assemble the store from its client and token provider
+    fn finish(self, client: reqwest::Client, auth: Option<Arc<TokenCache>>) -> GcsStore {
+        GcsStore {
+            client,
+            auth,
+            endpoint: self.endpoint.unwrap_or_else(|| DEFAULT_ENDPOINT.into()),
+            bucket: self.bucket.unwrap_or(Bucket::InPath),
+            user_project: self.user_project,
+            timeout: self.timeout,
+            resumable_threshold: upload::DEFAULT_RESUMABLE_THRESHOLD,
+            chunk_size: upload::DEFAULT_CHUNK_SIZE,
+            rewrite_chunk_size: None,
+        }
+    }
 }
 
 impl GcsStore {
-    /// Create a new GcsStore with default credentials
+    /// Start configuring a GcsStore
+    pub fn builder() -> GcsStoreBuilder {
+        GcsStoreBuilder::default()
+    }
+
+    /// Create a new GcsStore with default credentials, finding the bucket in
+    /// the first component of every key
     pub async fn new() -> Result<Self, Error> {
-        let auth = gcp_auth::provider()
-            .await
-            .map_err(|e| Error::Other(format!("failed to create auth provider: {e}")))?;
-        Ok(Self {
-            client: reqwest::Client::new(),
-            auth: Some(auth),
-            endpoint: "https://storage.googleapis.com".into(),
-        })
+        Self::builder().bucket_in_path().build().await
     }
 
-    /// Create a new GcsStore with a custom endpoint (for testing with fake-gcs-server)
+    /// Create a new GcsStore with a custom endpoint and no credentials (for
+    /// testing with fake-gcs-server), finding the bucket in every key
     pub fn with_endpoint(endpoint: impl Into<String>) -> Self {
-        Self {
-            client: reqwest::Client::new(),
-            auth: None, // No auth needed for fake-gcs-server
-            endpoint: endpoint.into(),
-        }
-    }
-
-    async fn get_token(&self) -> Result<Option<String>, Error> {
-        match &self.auth {
-            Some(provider) => {
-                let scopes = &["https://www.googleapis.com/auth/devstorage.read_write"];
-                let token = provider
-                    .token(scopes)
-                    .await
-                    .map_err(|e| Error::Other(format!("token error: {e}")))?;
-                Ok(Some(token.as_str().to_string()))
-            }
-            None => Ok(None),
+        Self::builder()
+            .bucket_in_path()
+            .endpoint(endpoint)
+            .finish(reqwest::Client::new(), None)
+    }
+
+    /// Set the object size at and above which uploads use resumable sessions
+    pub fn resumable_threshold(mut self, bytes: usize) -> Self {
+        self.resumable_threshold = bytes;
+        self
+    }
+
+    /// Set the chunk size of resumable uploads
+    ///
+    /// GCS requires chunks to be a multiple of 256 KiB, so the size is rounded
+    /// up accordingly.
+    pub fn upload_chunk_size(mut self, bytes: usize) -> Self {
+        self.chunk_size = bytes
+            .div_ceil(upload::CHUNK_ALIGNMENT)
+            .max(1)
+            .saturating_mul(upload::CHUNK_ALIGNMENT);
+        self
+    }
+
+    /// Limit the bytes copied by each call of a rewrite
+    ///
+    /// By default GCS decides how much each call copies. The limit must be a
+    /// multiple of 1 MiB, so it is rounded up accordingly.
+    pub fn rewrite_chunk_size(mut self, bytes: usize) -> Self {
+        self.rewrite_chunk_size = Some(
+            bytes
+                .div_ceil(REWRITE_ALIGNMENT)
+                .max(1)
+                .saturating_mul(REWRITE_ALIGNMENT),
+        );
+        self
+    }
+
+    /// Start an authorized request, billed to the user project if one is set
+    pub(crate) async fn request(
+        &self,
+        method: reqwest::Method,
+        url: &str,
+    ) -> Result<reqwest::RequestBuilder, Error> {
+        let mut req = self.client.request(method, url);
+        if let Some(project) = &self.user_project {
+            req = req.query(&[("userProject", project)]);
+        }
+        if let Some(timeout) = self.timeout {
+            req = req.timeout(timeout);
         }
+        if let Some(auth) = &self.auth {
+            req = req.bearer_auth(auth.token().await?);
+        }
+        Ok(req)
     }
-}
 
-/// Parse bucket and key from a path
-/// Path format: "bucket/key/path"
-fn parse_path(path: &Path) -> Result<(&str, &str), Error> {
-    let s = path.as_str();
-    let slash_pos = s
-        .find('/')
-        .ok_or_else(|| Error::Other("path must include bucket: expected 'bucket/key'".into()))?;
-    Ok((&s[..slash_pos], &s[slash_pos + 1..]))
-}
+    /// Send a request, dropping the cached token if GCS rejects it
+    pub(crate) async fn send(
+        &self,
+        req: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, Error> {
+        let req = req.build().map_err(request_error)?;
+        let token = req
+            .headers()
+            .get(reqwest::header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(str::to_string);
+        let resp = self.client.execute(req).await.map_err(request_error)?;
+        if resp.status() == reqwest::StatusCode::UNAUTHORIZED
+            && let (Some(auth), Some(token)) = (&self.auth, token)
+        {
+            auth.invalidate(&token).await;
+        }
+        Ok(resp)
+    }
 
-#[async_trait]
-impl ObjectStore for GcsStore {
-    async fn get(&self, request: GetRequest) -> Result<Option<GetResponse>, Error> {
-        let (bucket, key) = parse_path(&request.key)?;
+    /// The bucket and object name of a key
+    pub(crate) fn locate<'a>(&'a self, key: &'a Path) -> Result<(&'a str, &'a str), Error> {
+        match &self.bucket {
+            Bucket::Bound(bucket) => Ok((bucket, key.as_str())),
+            Bucket::InPath => parse_path(key, "bucket"),
+        }
+    }
+
+    /// The bucket and object name prefix of a list prefix
+    fn locate_prefix<'a>(&'a self, prefix: &'a str) -> Result<(&'a str, &'a str), Error> {
+        match &self.bucket {
+            Bucket::Bound(bucket) => Ok((bucket, prefix)),
+            Bucket::InPath => parse_prefix(prefix, "bucket"),
+        }
+    }
+
+    /// What keys add to the object names of a bucket
+    fn key_prefix(&self, bucket: &str) -> String {
+        match &self.bucket {
+            Bucket::Bound(_) => String::new(),
+            Bucket::InPath => format!("{bucket}/"),
+        }
+    }
+
+    /// Fetch the JSON object resource of a key, if it exists
+    async fn resource(&self, key: &Path) -> Result<Option<serde_json::Value>, Error> {
+        let (bucket, key) = self.locate(key)?;
         let url = format!(
+            "{}/storage/v1/b/{}/o/{}",
+            self.endpoint,
+            urlencoding::encode(bucket),
+            urlencoding::encode(key)
+        );
+
+        let req = self.request(Method::GET, &url).await?;
+
+        let resp = self.send(req).await?;
+
+        match resp.status().as_u16() {
+            404 => Ok(None),
+            200 => Ok(Some(resp.json().await.map_err(request_error)?)),
+            _ => Err(status_error(resp).await),
+        }
+    }
+
+    /// Express a write condition as precondition query parameters
+    ///
+    /// Generation preconditions express most conditions directly. The others
+    /// are checked against the current object here, and the write is pinned
+    /// to the generation and metageneration (which patches bump) that were
+    /// checked, so it fails with `ConditionFailed` if the object changes in
+    /// between. `IfVersionNotMatches` is one of them because GCS fails
+    /// `ifGenerationNotMatch` for missing objects.
+    ///
+    /// `must_exist` is set for writes that fail with `NotFound` on a missing
+    /// object (patches and deletes).
+    pub(crate) async fn preconditions(
+        &self,
+        key: &Path,
+        condition: Option<&Condition>,
+        must_exist: bool,
+    ) -> Result<Vec<String>, Error> {
+        let condition = match condition {
+            None => return Ok(vec![]),
+            Some(Condition::IfAbsent) => return Ok(vec!["ifGenerationMatch=0".into()]),
+            Some(Condition::IfExists) => return Ok(vec!["ifGenerationNotMatch=0".into()]),
+            Some(Condition::IfVersionMatches(v)) => return Ok(version_preconditions(v)),
+            Some(condition) => condition,
+        };
+        let failed = || Error::ConditionFailed {
+            condition: condition.clone(),
+        };
+
+        let Some(resource) = self.resource(key).await? else {
+            return if must_exist {
+                Err(Error::NotFound)
+            } else if condition.is_satisfied_by(None) {
+                Ok(vec!["ifGenerationMatch=0".into()])
+            } else {
+                Err(failed())
+            };
+        };
+        let (version, _, metadata) = parse_attributes(&resource)?;
+        if !condition.is_satisfied_by(Some((&version, &metadata))) {
+            return Err(failed());
+        }
+        Ok(version_preconditions(&version))
+    }
+
+    /// Attribute a failed precondition of a copy to the destination
+    /// condition, or to the source version, which then no longer exists
+    async fn copy_precondition_failed(
+        &self,
+        request: &CopyRequest,
+        resp: reqwest::Response,
+    ) -> Error {
+        let source_version = request
+            .source_version
+            .as_ref()
+            .filter(|v| split_version(v).1.is_some());
+        let source_changed = match (&request.destination_condition, source_version) {
+            (None, None) => return status_error(resp).await,
+            (None, Some(_)) => true,
+            (Some(_), None) => false,
+            // Either may have failed: look at the source to tell which
+            (Some(_), Some(version)) => match self.resource(&request.from).await {
+                Ok(Some(resource)) => parse_version(&resource).ok().as_ref() != Some(version),
+                Ok(None) => true,
+                Err(e) => return e,
+            },
+        };
+        match &request.destination_condition {
+            Some(condition) if !source_changed => Error::ConditionFailed {
+                condition: condition.clone(),
+            },
+            _ => Error::NotFound,
+        }
+    }
+
+    /// Send a download request with the given precondition parameters
+    async fn download(
+        &self,
+        request: &GetRequest,
+        preconditions: &[String],
+    ) -> Result<reqwest::Response, Error> {
+        let (bucket, key) = self.locate(&request.key)?;
+        let mut url = format!(
             "{}/storage/v1/b/{}/o/{}?alt=media",
             self.endpoint,
             urlencoding::encode(bucket),
             urlencoding::encode(key)
         );
+        push_query(&mut url, preconditions);
 
-        let mut req = self.client.get(&url);
-        if let Some(token) = self.get_token().await? {
-            req = req.bearer_auth(token);
+        let mut req = self.request(Method::GET, &url).await?;
+        if let Some(range) = &request.range {
+            req = req.header("Range", range.header_value()?);
         }
+        self.send(req).await
+    }
+}
 
-        let resp = req
-            .send()
-            .await
-            .map_err(|e| Error::Other(format!("request error: {e}")))?;
+/// Combine a generation and metageneration into a version
+fn object_version(generation: &str, metageneration: &str) -> Version {
+    Version::new(format!("{generation}:{metageneration}"))
+}
+
+/// Split a version into its generation and, unless the version only names a
+/// generation, its metageneration
+fn split_version(version: &Version) -> (String, Option<String>) {
+    let encode = |s| urlencoding::encode(s).into_owned();
+    match version.as_str().split_once(':') {
+        Some((generation, metageneration)) => (encode(generation), Some(encode(metageneration))),
+        None => (encode(version.as_str()), None),
+    }
+}
+
+/// Precondition parameters requiring an object to be at a version
+fn version_preconditions(version: &Version) -> Vec<String> {
+    let (generation, metageneration) = split_version(version);
+    let mut params = vec![format!("ifGenerationMatch={generation}")];
+    if let Some(metageneration) = metageneration {
+        params.push(format!("ifMetagenerationMatch={metageneration}"));
+    }
+    params
+}
+
+/// Append query parameters (`name=value`, already encoded) to a URL
+fn push_query(url: &mut String, params: &[String]) {
+    for param in params {
+        url.push(if url.contains('?') { '&' } else { '?' });
+        url.push_str(param);
+    }
+}
+
+/// Parse an object resource from the JSON API into an ObjectSummary, whose
+/// key is the object name after `key_prefix`
+fn parse_object(key_prefix: &str, item: &serde_json::Value) -> Result<ObjectSummary, Error> {
+    let name = item["name"]
+        .as_str()
+        .ok_or_else(|| Error::Other("missing name".into()))?;
+    let key = Path::new(format!("{key_prefix}{name}"))
+        .map_err(|e| Error::Other(format!("invalid object name '{name}': {e}")))?;
+    let (version, size, metadata) = parse_attributes(item)?;
+    Ok(ObjectSummary {
+        key,
+        version,
+        size,
+        metadata,
+    })
+}
+
+/// Parse the version of an object resource
+pub(crate) fn parse_version(item: &serde_json::Value) -> Result<Version, Error> {
+    let generation = item["generation"]
+        .as_str()
+        .ok_or_else(|| Error::Other("missing generation".into()))?;
+    let metageneration = item["metageneration"]
+        .as_str()
+        .ok_or_else(|| Error::Other("missing metageneration".into()))?;
+    Ok(object_version(generation, metageneration))
+}
+
+/// Parse the version, size and custom metadata of an object resource
+fn parse_attributes(item: &serde_json::Value) -> Result<(Version, u64, Metadata), Error> {
+    let version = parse_version(item)?;
+    let size = item["size"]
+        .as_str()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| Error::Other("missing size".into()))?;
+
+    let mut metadata = Metadata::new();
+    if let Some(fields) = item["metadata"].as_object() {
+        for (k, v) in fields {
+            if let Some(v) = v.as_str() {
+                metadata.insert(k, v);
+            }
+        }
+    }
+
+    Ok((version, size, metadata))
+}
+
+#[async_trait]
+impl ObjectStore for GcsStore {
+    async fn get(&self, request: GetRequest) -> Result<Option<GetResponse>, Error> {
+        match self.get_stream(request).await? {
+            Some(response) => Ok(Some(response.collect().await?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_stream(&self, request: GetRequest) -> Result<Option<GetStreamResponse>, Error> {
+        // Past generations are only retained in buckets with object versioning
+        let mut preconditions = Vec::new();
+        if let Some(version) = &request.version {
+            let (generation, metageneration) = split_version(version);
+            preconditions.push(format!("generation={generation}"));
+            if let Some(metageneration) = metageneration {
+                preconditions.push(format!("ifMetagenerationMatch={metageneration}"));
+            }
+        }
+
+        // GCS fails a request if any of its preconditions fails, so a version
+        // mismatch takes two: the generation differs, or else the
+        // metageneration does
+        let resp = match request.if_version_not_matches.as_ref().map(split_version) {
+            None => self.download(&request, &preconditions).await?,
+            Some((generation, metageneration)) => {
+                let mut not_matches = preconditions.clone();
+                not_matches.push(format!("ifGenerationNotMatch={generation}"));
+                let resp = self.download(&request, &not_matches).await?;
+                match metageneration {
+                    Some(metageneration) if resp.status() == 304 => {
+                        let mut not_matches = preconditions.clone();
+                        not_matches.push(format!("ifGenerationMatch={generation}"));
+                        not_matches.push(format!("ifMetagenerationNotMatch={metageneration}"));
+                        let resp = self.download(&request, &not_matches).await?;
+                        // A 412 means a new generation replaced the object
+                        // in between, which cannot match the version
+                        if resp.status() == 412 && request.version.is_none() {
+                            self.download(&request, &preconditions).await?
+                        } else {
+                            resp
+                        }
+                    }
+                    _ => resp,
+                }
+            }
+        };
 
         match resp.status().as_u16() {
+            304 => Err(Error::NotModified),
             404 => Ok(None),
-            200 => {
-                // Extract version from header
-                let generation = resp
-                    .headers()
-                    .get("x-goog-generation")
-                    .and_then(|v| v.to_str().ok())
-                    .ok_or_else(|| Error::Other("missing generation header".into()))?;
-                let version = Version::new(generation);
+            // The requested generation has moved on to another metageneration
+            412 if request.version.is_some() => Ok(None),
+            416 => Err(Error::RangeNotSatisfiable),
+            status @ (200 | 206) => {
+                // Extract the total object size: partial responses carry it in
+                // Content-Range ("bytes start-end/size"), full responses in
+                // Content-Length
+                let size_header = if status == 206 {
+                    resp.headers()
+                        .get("content-range")
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(|v| v.rsplit('/').next())
+                } else {
+                    resp.headers()
+                        .get("x-goog-stored-content-length")
+                        .or_else(|| resp.headers().get("content-length"))
+                        .and_then(|v| v.to_str().ok())
+                };
+                let size: Option<u64> = size_header.and_then(|v| v.parse().ok());
+
+                // Extract version from headers
+                let header = |name| {
+                    resp.headers()
+                        .get(name)
+                        .and_then(|v| v.to_str().ok())
+                        .ok_or_else(|| Error::Other(format!("missing {name} header")))
+                };
+                let version = object_version(
+                    header("x-goog-generation")?,
+                    header("x-goog-metageneration")?,
+                );
 
                 // Extract custom metadata from x-goog-meta-* headers
                 let mut metadata = Metadata::new();
@@ -105,85 +612,115 @@ impl ObjectStore for GcsStore {
                     }
                 }
 
-                // Read body
-                let value = resp
-                    .bytes()
-                    .await
-                    .map_err(|e| Error::Other(format!("read error: {e}")))?;
+                let (size, stream): (u64, ByteStream) = match size {
+                    Some(size) => (size, Box::pin(resp.bytes_stream().map_err(request_error))),
+                    // Without a size header (as for chunked responses), a
+                    // full read is buffered to measure it
+                    None if status == 200 => {
+                        let value = resp.bytes().await.map_err(request_error)?;
+                        let size = value.len() as u64;
+                        (size, Box::pin(futures::stream::once(async { Ok(value) })))
+                    }
+                    None => return Err(Error::Other("missing content-range header".into())),
+                };
 
-                Ok(Some(GetResponse {
-                    value,
+                Ok(Some(GetStreamResponse {
+                    stream,
+                    size,
                     version,
                     metadata,
                 }))
             }
-            status => Err(Error::Other(format!("GCS get error: status {status}"))),
+            _ => Err(status_error(resp).await),
         }
     }
 
+    async fn head(&self, request: HeadRequest) -> Result<Option<HeadResponse>, Error> {
+        let Some(body) = self.resource(&request.key).await? else {
+            return Ok(None);
+        };
+        let (version, size, metadata) = parse_attributes(&body)?;
+        let last_modified = body["updated"]
+            .as_str()
+            .map(|updated| {
+                humantime::parse_rfc3339_weak(updated)
+                    .map_err(|e| Error::Other(format!("invalid updated time: {e}")))
+            })
+            .transpose()?;
+
+        Ok(Some(HeadResponse {
+            version,
+            metadata,
+            size,
+            content_type: body["contentType"].as_str().map(String::from),
+            last_modified,
+        }))
+    }
+
     async fn put(&self, request: PutRequest) -> Result<PutResponse, Error> {
-        let (bucket, key) = parse_path(&request.key)?;
+        self.upload(
+            &request.key,
+            request.condition,
+            request.metadata,
+            request.value,
+        )
+        .await
+    }
+
+    async fn put_stream(&self, request: PutStreamRequest) -> Result<PutResponse, Error> {
+        self.upload_stream(
+            &request.key,
+            request.condition,
+            request.metadata,
+            request.stream,
+        )
+        .await
+    }
+
+    async fn patch(&self, request: PatchRequest) -> Result<PatchResponse, Error> {
+        let (bucket, key) = self.locate(&request.key)?;
         let mut url = format!(
-            "{}/upload/storage/v1/b/{}/o?uploadType=media&name={}",
+            "{}/storage/v1/b/{}/o/{}",
             self.endpoint,
             urlencoding::encode(bucket),
             urlencoding::encode(key)
         );
 
-        // Add condition query params
-        if let Some(condition) = &request.condition {
-            match condition {
-                Condition::IfAbsent => url.push_str("&ifGenerationMatch=0"),
-                Condition::IfVersionMatches(v) => {
-                    url.push_str(&format!("&ifGenerationMatch={}", v.as_str()));
-                }
-            }
-        }
+        let preconditions = self
+            .preconditions(&request.key, request.condition.as_ref(), true)
+            .await?;
+        push_query(&mut url, &preconditions);
 
-        let mut req = self
-            .client
-            .post(&url)
-            .header("Content-Type", "application/octet-stream")
-            .body(request.value.clone());
-
-        if let Some(token) = self.get_token().await? {
-            req = req.bearer_auth(token);
-        }
+        // PATCH body with metadata
+        let body = serde_json::json!({
+            "metadata": request.metadata.headers
+        });
 
-        // Add custom metadata as x-goog-meta-* headers
-        if let Some(metadata) = &request.metadata {
-            for (k, v) in &metadata.headers {
-                req = req.header(format!("x-goog-meta-{k}"), v);
-            }
-        }
+        let req = self
+            .request(Method::PATCH, &url)
+            .await?
+            .header("Content-Type", "application/json")
+            .json(&body);
 
-        let resp = req
-            .send()
-            .await
-            .map_err(|e| Error::Other(format!("request error: {e}")))?;
+        let resp = self.send(req).await?;
 
         match resp.status().as_u16() {
             200 => {
-                let body: serde_json::Value = resp
-                    .json()
-                    .await
-                    .map_err(|e| Error::Other(format!("json error: {e}")))?;
-                let generation = body["generation"]
-                    .as_str()
-                    .ok_or_else(|| Error::Other("missing generation".into()))?;
-                Ok(PutResponse {
-                    version: Version::new(generation),
+                let body: serde_json::Value = resp.json().await.map_err(request_error)?;
+                Ok(PatchResponse {
+                    version: parse_version(&body)?,
                 })
             }
-            412 => Err(Error::ConditionFailed {
+            404 => Err(Error::NotFound),
+            412 if request.condition.is_some() => Err(Error::ConditionFailed {
                 condition: request.condition.unwrap(),
             }),
-            status => Err(Error::Other(format!("GCS put error: status {status}"))),
+            _ => Err(status_error(resp).await),
         }
     }
 
-    async fn patch(&self, request: PatchRequest) -> Result<PatchResponse, Error> {
-        let (bucket, key) = parse_path(&request.key)?;
+    async fn delete(&self, request: DeleteRequest) -> Result<DeleteResponse, Error> {
+        let (bucket, key) = self.locate(&request.key)?;
         let mut url = format!(
             "{}/storage/v1/b/{}/o/{}",
             self.endpoint,
@@ -191,49 +728,489 @@ impl ObjectStore for GcsStore {
             urlencoding::encode(key)
         );
 
-        // Add condition query params
-        if let Some(Condition::IfVersionMatches(v)) = &request.condition {
-            url.push_str(&format!("?ifGenerationMatch={}", v.as_str()));
-        }
+        let preconditions = self
+            .preconditions(&request.key, request.condition.as_ref(), true)
+            .await?;
+        push_query(&mut url, &preconditions);
 
-        // PATCH body with metadata
-        let body = serde_json::json!({
-            "metadata": request.metadata.headers
-        });
+        let req = self.request(Method::DELETE, &url).await?;
 
-        let mut req = self
-            .client
-            .patch(&url)
-            .header("Content-Type", "application/json")
-            .json(&body);
+        let resp = self.send(req).await?;
 
-        if let Some(token) = self.get_token().await? {
-            req = req.bearer_auth(token);
+        match resp.status().as_u16() {
+            200 | 204 => Ok(DeleteResponse),
+            404 => Err(Error::NotFound),
+            412 if request.condition.is_some() => Err(Error::ConditionFailed {
+                condition: request.condition.unwrap(),
+            }),
+            _ => Err(status_error(resp).await),
         }
+    }
 
-        let resp = req
-            .send()
-            .await
-            .map_err(|e| Error::Other(format!("request error: {e}")))?;
+    async fn list(&self, request: ListRequest) -> Result<ListResponse, Error> {
+        let (bucket, prefix) = self.locate_prefix(&request.prefix)?;
+        let key_prefix = self.key_prefix(bucket);
+        let mut url = format!(
+            "{}/storage/v1/b/{}/o?prefix={}",
+            self.endpoint,
+            urlencoding::encode(bucket),
+            urlencoding::encode(prefix)
+        );
+
+        if let Some(delimiter) = request.delimiter {
+            url.push_str(&format!(
+                "&delimiter={}",
+                urlencoding::encode(delimiter.encode_utf8(&mut [0; 4]))
+            ));
+        }
+        // startOffset is inclusive, so an exact match is filtered out below,
+        // leaving its page one short. Asking for one more result instead would
+        // lose the extra one: page tokens resume after the last result GCS
+        // sent, not the last one returned
+        let start_after = match &request.start_after {
+            Some(start_after) => {
+                let name =
+                    start_after
+                        .strip_prefix(&key_prefix)
+                        .ok_or_else(|| Error::InvalidRequest {
+                            message: format!("start_after '{start_after}' is not in bucket"),
+                        })?;
+                url.push_str(&format!("&startOffset={}", urlencoding::encode(name)));
+                Some(start_after.as_str())
+            }
+            None => None,
+        };
+        if let Some(token) = &request.page_token {
+            url.push_str(&format!("&pageToken={}", urlencoding::encode(token)));
+        }
+        if let Some(max_results) = request.max_results {
+            url.push_str(&format!("&maxResults={max_results}"));
+        }
+
+        let req = self.request(Method::GET, &url).await?;
+
+        let resp = self.send(req).await?;
 
         match resp.status().as_u16() {
             200 => {
-                let body: serde_json::Value = resp
-                    .json()
-                    .await
-                    .map_err(|e| Error::Other(format!("json error: {e}")))?;
-                let generation = body["generation"]
-                    .as_str()
-                    .ok_or_else(|| Error::Other("missing generation".into()))?;
-                Ok(PatchResponse {
-                    version: Version::new(generation),
+                let body: serde_json::Value = resp.json().await.map_err(request_error)?;
+
+                let mut objects = Vec::new();
+                for item in body["items"].as_array().into_iter().flatten() {
+                    let object = parse_object(&key_prefix, item)?;
+                    if start_after != Some(object.key.as_str()) {
+                        objects.push(object);
+                    }
+                }
+                let common_prefixes = body["prefixes"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|p| p.as_str())
+                    .map(|p| format!("{key_prefix}{p}"))
+                    .collect();
+
+                Ok(ListResponse {
+                    objects,
+                    common_prefixes,
+                    next_page_token: body["nextPageToken"].as_str().map(String::from),
                 })
             }
-            404 => Err(Error::NotFound),
-            412 => Err(Error::ConditionFailed {
-                condition: request.condition.unwrap(),
-            }),
-            status => Err(Error::Other(format!("GCS patch error: status {status}"))),
+            _ => Err(status_error(resp).await),
         }
     }
+
+    async fn copy(&self, request: CopyRequest) -> Result<CopyResponse, Error> {
+        let (source_bucket, source_key) = self.locate(&request.from)?;
+        let (bucket, key) = self.locate(&request.to)?;
+        let url = format!(
+            "{}/storage/v1/b/{}/o/{}/rewriteTo/b/{}/o/{}",
+            self.endpoint,
+            urlencoding::encode(source_bucket),
+            urlencoding::encode(source_key),
+            urlencoding::encode(bucket),
+            urlencoding::encode(key)
+        );
+
+        let mut params = self
+            .preconditions(&request.to, request.destination_condition.as_ref(), false)
+            .await?;
+        if let Some(v) = &request.source_version {
+            let (generation, metageneration) = split_version(v);
+            params.push(format!("sourceGeneration={generation}"));
+            if let Some(metageneration) = metageneration {
+                params.push(format!("ifSourceMetagenerationMatch={metageneration}"));
+            }
+        }
+        if let Some(bytes) = self.rewrite_chunk_size {
+            params.push(format!("maxBytesRewrittenPerCall={bytes}"));
+        }
+
+        // A destination resource with metadata replaces the source's metadata
+        let body = match &request.metadata_override {
+            Some(metadata) => serde_json::json!({ "metadata": metadata.headers }),
+            None => serde_json::json!({}),
+        };
+
+        // Each call copies part of the object and returns a token to continue
+        let mut rewrite_token: Option<String> = None;
+        loop {
+            let mut url = url.clone();
+            push_query(&mut url, &params);
+            if let Some(token) = &rewrite_token {
+                push_query(
+                    &mut url,
+                    &[format!("rewriteToken={}", urlencoding::encode(token))],
+                );
+            }
+
+            let req = self.request(Method::POST, &url).await?.json(&body);
+
+            let resp = self.send(req).await?;
+
+            match resp.status().as_u16() {
+                200 => {
+                    let body: serde_json::Value = resp.json().await.map_err(request_error)?;
+                    if body["done"].as_bool() == Some(true) {
+                        let (version, _, _) = parse_attributes(&body["resource"])?;
+                        return Ok(CopyResponse { version });
+                    }
+                    let token = body["rewriteToken"]
+                        .as_str()
+                        .ok_or_else(|| Error::Other("missing rewrite token".into()))?;
+                    rewrite_token = Some(token.to_string());
+                }
+                404 => return Err(Error::NotFound),
+                412 => return Err(self.copy_precondition_failed(&request, resp).await),
+                _ => return Err(status_error(resp).await),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kanso_fake_gcs::FakeGcs;
+    use kanso_inmemory::InMemoryStore;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_compliance() {
+        let server = FakeGcs::start().await;
+        let store: kanso_client::Client = Arc::new(GcsStore::with_endpoint(server.endpoint()));
+        kanso_backends_test_suite::run_compliance_tests(&store, "bucket/").await;
+    }
+
+    #[tokio::test]
+    async fn test_compliance_bucket_bound() {
+        let server = FakeGcs::start().await;
+        let store = GcsStore::builder()
+            .bucket("bucket")
+            .endpoint(server.endpoint())
+            .credentials(Credentials::Anonymous)
+            .build()
+            .await
+            .unwrap();
+        let store: kanso_client::Client = Arc::new(store);
+        kanso_backends_test_suite::run_compliance_tests(&store, "").await;
+    }
+
+    #[tokio::test]
+    async fn test_compliance_resumable() {
+        let server = FakeGcs::start().await;
+        let store: kanso_client::Client =
+            Arc::new(GcsStore::with_endpoint(server.endpoint()).resumable_threshold(0));
+        kanso_backends_test_suite::run_compliance_tests(&store, "bucket/").await;
+    }
+
+    #[tokio::test]
+    async fn test_reads_past_generations() {
+        let server = FakeGcs::with_store(InMemoryStore::with_history()).await;
+        let store: kanso_client::Client = Arc::new(GcsStore::with_endpoint(server.endpoint()));
+
+        let v1 = PutRequest::new("bucket/key", "one".into())
+            .unwrap()
+            .execute(&store)
+            .await
+            .unwrap()
+            .version;
+        PutRequest::new("bucket/key", "two".into())
+            .unwrap()
+            .execute(&store)
+            .await
+            .unwrap();
+
+        let old = GetRequest::new("bucket/key")
+            .unwrap()
+            .version(v1.clone())
+            .execute(&store)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(old.value, "one");
+        assert_eq!(old.version, v1);
+        assert_eq!(server.store().history("bucket/key").await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_patches_keep_the_generation() {
+        let server = FakeGcs::start().await;
+        let store: kanso_client::Client = Arc::new(GcsStore::with_endpoint(server.endpoint()));
+
+        let v1 = PutRequest::new("bucket/key", "value".into())
+            .unwrap()
+            .execute(&store)
+            .await
+            .unwrap()
+            .version;
+        let v2 = PatchRequest::new("bucket/key", Metadata::with("k", "v"))
+            .unwrap()
+            .if_version_matches(v1.clone())
+            .execute(&store)
+            .await
+            .unwrap()
+            .version;
+        let (generation, metageneration) = v2.as_str().split_once(':').unwrap();
+        assert_eq!(v1.as_str(), format!("{generation}:1"));
+        assert_eq!(metageneration, "2");
+
+        // The old metageneration is gone, and differs from the current one
+        let old = GetRequest::new("bucket/key").unwrap().version(v1.clone());
+        assert!(old.execute(&store).await.unwrap().is_none());
+        let changed = GetRequest::new("bucket/key")
+            .unwrap()
+            .if_version_not_matches(v1)
+            .execute(&store)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(changed.version, v2);
+        let unchanged = GetRequest::new("bucket/key")
+            .unwrap()
+            .if_version_not_matches(v2.clone())
+            .execute(&store)
+            .await;
+        assert!(matches!(unchanged, Err(Error::NotModified)));
+
+        // A version naming only the generation still matches it
+        let generation_only = GetRequest::new("bucket/key")
+            .unwrap()
+            .if_version_not_matches(Version::new(generation))
+            .execute(&store)
+            .await;
+        assert!(matches!(generation_only, Err(Error::NotModified)));
+    }
+
+    #[tokio::test]
+    async fn test_copy_precondition_failures() {
+        let server = FakeGcs::start().await;
+        let store: kanso_client::Client = Arc::new(GcsStore::with_endpoint(server.endpoint()));
+
+        let stale = PutRequest::new("bucket/source", "value".into())
+            .unwrap()
+            .execute(&store)
+            .await
+            .unwrap()
+            .version;
+        let current = PatchRequest::new("bucket/source", Metadata::with("k", "v"))
+            .unwrap()
+            .execute(&store)
+            .await
+            .unwrap()
+            .version;
+        PutRequest::new("bucket/destination", "taken".into())
+            .unwrap()
+            .execute(&store)
+            .await
+            .unwrap();
+
+        // A source version that was patched away is not found
+        for condition in [None, Some(Condition::IfAbsent)] {
+            let mut copy = CopyRequest::new("bucket/source", "bucket/destination")
+                .unwrap()
+                .source_version(stale.clone());
+            copy.destination_condition = condition;
+            let result = copy.execute(&store).await;
+            assert!(matches!(result, Err(Error::NotFound)), "{result:?}");
+        }
+
+        // With the source at its version, the destination condition failed
+        let result = CopyRequest::new("bucket/source", "bucket/destination")
+            .unwrap()
+            .source_version(current)
+            .if_absent()
+            .execute(&store)
+            .await;
+        assert!(matches!(
+            result,
+            Err(Error::ConditionFailed {
+                condition: Condition::IfAbsent
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_copies_over_several_rewrite_calls() {
+        let server = FakeGcs::start().await;
+        let store: kanso_client::Client =
+            Arc::new(GcsStore::with_endpoint(server.endpoint()).rewrite_chunk_size(1));
+
+        let value: bytes::Bytes = (0..REWRITE_ALIGNMENT * 5 / 2)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        PutRequest::new("bucket/large", value.clone())
+            .unwrap()
+            .metadata(Metadata::with("k", "v"))
+            .execute(&store)
+            .await
+            .unwrap();
+        let copied = CopyRequest::new("bucket/large", "other/large")
+            .unwrap()
+            .if_absent()
+            .execute(&store)
+            .await
+            .unwrap();
+
+        let resp = GetRequest::new("other/large")
+            .unwrap()
+            .execute(&store)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(resp.value, value);
+        assert_eq!(resp.version, copied.version);
+        assert_eq!(resp.metadata.get("k"), Some(&"v".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_bucket_bound_keys_omit_the_bucket() {
+        let server = FakeGcs::start().await;
+        let bound = GcsStore::builder()
+            .bucket("bucket")
+            .endpoint(server.endpoint())
+            .credentials(Credentials::Anonymous)
+            .build()
+            .await
+            .unwrap();
+        let bound: kanso_client::Client = Arc::new(bound);
+        let in_path: kanso_client::Client = Arc::new(GcsStore::with_endpoint(server.endpoint()));
+
+        PutRequest::new("dir/key", "value".into())
+            .unwrap()
+            .execute(&bound)
+            .await
+            .unwrap();
+        let resp = GetRequest::new("bucket/dir/key")
+            .unwrap()
+            .execute(&in_path)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(resp.value, "value");
+
+        let page = ListRequest::new("dir/")
+            .unwrap()
+            .start_after("dir/a")
+            .execute(&bound)
+            .await
+            .unwrap();
+        assert_eq!(page.objects[0].key.as_str(), "dir/key");
+        let page = ListRequest::new("").unwrap().delimiter('/');
+        let page = page.execute(&bound).await.unwrap();
+        assert_eq!(page.common_prefixes, ["dir/"]);
+    }
+
+    #[tokio::test]
+    async fn test_start_after_shortens_the_first_page() {
+        let server = FakeGcs::start().await;
+        let client: kanso_client::Client = Arc::new(GcsStore::with_endpoint(server.endpoint()));
+        for key in ["a", "b", "c", "d"] {
+            PutRequest::new(format!("bucket/{key}"), "value".into())
+                .unwrap()
+                .execute(&client)
+                .await
+                .unwrap();
+        }
+
+        let request = ListRequest::new("bucket/")
+            .unwrap()
+            .start_after("bucket/a")
+            .max_results(2);
+        let pages: Vec<_> = request.into_stream(&client).try_collect().await.unwrap();
+        let keys: Vec<Vec<_>> = pages
+            .iter()
+            .map(|page| page.objects.iter().map(|o| o.key.as_str()).collect())
+            .collect();
+        assert_eq!(keys, [vec!["bucket/b"], vec!["bucket/c", "bucket/d"]]);
+    }
+
+    #[tokio::test]
+    async fn test_full_reads_without_size_headers() {
+        let server = FakeGcs::start().await;
+        server.set_chunked_downloads(true);
+        let client: kanso_client::Client = Arc::new(GcsStore::with_endpoint(server.endpoint()));
+        PutRequest::new("bucket/key", "value".into())
+            .unwrap()
+            .execute(&client)
+            .await
+            .unwrap();
+
+        // Without a size header, the size is taken from the body
+        let resp = GetRequest::new("bucket/key")
+            .unwrap()
+            .execute(&client)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(resp.size, 5);
+        assert_eq!(resp.value, "value");
+    }
+
+    #[tokio::test]
+    async fn test_builder_rejects_incomplete_configuration() {
+        let no_bucket = GcsStore::builder()
+            .credentials(Credentials::Anonymous)
+            .build()
+            .await;
+        assert!(matches!(no_bucket, Err(Error::InvalidRequest { .. })));
+
+        let conflicting = GcsStore::builder()
+            .bucket("bucket")
+            .credentials(Credentials::Anonymous)
+            .http_client(reqwest::Client::new())
+            .connect_timeout(Duration::from_secs(1))
+            .build()
+            .await;
+        assert!(matches!(conflicting, Err(Error::InvalidRequest { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_requests_carry_user_project_and_timeout() {
+        let server = FakeGcs::start().await;
+        let store = GcsStore::builder()
+            .bucket("bucket")
+            .endpoint(server.endpoint())
+            .credentials(Credentials::Anonymous)
+            .user_project("billed-project")
+            .timeout(Duration::from_millis(200))
+            .build()
+            .await
+            .unwrap();
+        let store: kanso_client::Client = Arc::new(store);
+
+        let missing = HeadRequest::new("key").unwrap().execute(&store).await;
+        assert!(missing.unwrap().is_none());
+        let missing = GetRequest::new("key").unwrap().execute(&store).await;
+        assert!(missing.unwrap().is_none());
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        for request in &requests {
+            assert_eq!(request.query["userProject"], "billed-project");
+        }
+
+        server.set_delay(Duration::from_secs(5));
+        let slow = HeadRequest::new("key").unwrap().execute(&store).await;
+        assert!(matches!(slow, Err(Error::Timeout { .. })));
+    }
 }
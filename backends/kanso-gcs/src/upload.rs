@@ -0,0 +1,466 @@
+//! Multipart and resumable uploads for GcsStore
+//!
+//! Objects smaller than the resumable threshold are sent in a single
+//! `uploadType=multipart` request, which carries metadata and data together.
+//! Larger objects (and streams that grow past the threshold) use a resumable
+//! upload session: data is sent in chunks, and after a transient failure the
+//! session is queried for the persisted offset and the upload resumes there.
+
+use std::time::Duration;
+
+use bytes::{Buf, Bytes, BytesMut};
+use futures::{StreamExt, TryStreamExt};
+use kanso_client::http::{request_error, status_error};
+use kanso_client::{ByteStream, Condition, Error, Metadata, Path, PutResponse};
+
+use reqwest::Method;
+
+use crate::{GcsStore, parse_version, push_query};
+
+/// Resumable upload chunks must be a multiple of 256 KiB (except the last)
+pub(crate) const CHUNK_ALIGNMENT: usize = 256 * 1024;
+
+/// Default size at and above which uploads switch to resumable sessions
+pub(crate) const DEFAULT_RESUMABLE_THRESHOLD: usize = 8 * 1024 * 1024;
+
+/// Default size of each resumable upload chunk
+pub(crate) const DEFAULT_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Attempts per chunk before a resumable upload gives up
+const MAX_CHUNK_ATTEMPTS: u32 = 5;
+
+/// Delay before the first retry of a chunk, doubled on every further attempt
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Outcome of sending a chunk (or querying the session) of a resumable upload
+enum ChunkStatus {
+    /// The server persisted all bytes before this offset
+    Persisted(u64),
+    /// The upload is complete
+    Complete(PutResponse),
+}
+
+/// Result of a single chunk request: the inner error is a retryable failure
+/// after which the session should be queried and the chunk resent
+type ChunkResult = Result<Result<ChunkStatus, Error>, Error>;
+
+impl GcsStore {
+    /// Upload a value, switching to a resumable session at the threshold
+    pub(crate) async fn upload(
+        &self,
+        key: &Path,
+        condition: Option<Condition>,
+        metadata: Option<Metadata>,
+        value: Bytes,
+    ) -> Result<PutResponse, Error> {
+        if value.len() < self.resumable_threshold {
+            self.multipart_upload(key, condition, metadata, value).await
+        } else {
+            let stream = futures::stream::once(async move { Ok(value) });
+            self.resumable_upload(key, condition, metadata, Box::pin(stream))
+                .await
+        }
+    }
+
+    /// Upload a stream, buffering it up to the resumable threshold to decide
+    /// between a multipart request and a resumable session
+    pub(crate) async fn upload_stream(
+        &self,
+        key: &Path,
+        condition: Option<Condition>,
+        metadata: Option<Metadata>,
+        mut stream: ByteStream,
+    ) -> Result<PutResponse, Error> {
+        let mut buffer = BytesMut::new();
+        while buffer.len() < self.resumable_threshold {
+            match stream.try_next().await? {
+                Some(chunk) => buffer.extend_from_slice(&chunk),
+                None => {
+                    return self
+                        .multipart_upload(key, condition, metadata, buffer.freeze())
+                        .await;
+                }
+            }
+        }
+
+        let head = futures::stream::once(async move { Ok(buffer.freeze()) });
+        self.resumable_upload(key, condition, metadata, Box::pin(head.chain(stream)))
+            .await
+    }
+
+    /// Upload metadata and data together in a single multipart request
+    async fn multipart_upload(
+        &self,
+        key: &Path,
+        condition: Option<Condition>,
+        metadata: Option<Metadata>,
+        value: Bytes,
+    ) -> Result<PutResponse, Error> {
+        let preconditions = self.preconditions(key, condition.as_ref(), false).await?;
+        let (bucket, key) = self.locate(key)?;
+        let mut url = format!(
+            "{}/upload/storage/v1/b/{}/o?uploadType=multipart",
+            self.endpoint,
+            urlencoding::encode(bucket),
+        );
+        push_query(&mut url, &preconditions);
+
+        // The boundary is random so it cannot collide with the payload
+        let boundary = format!("kanso-{}", uuid::Uuid::new_v4().simple());
+        let resource = object_resource(key, metadata.as_ref());
+        let mut body = BytesMut::new();
+        body.extend_from_slice(
+            format!(
+                "--{boundary}\r\nContent-Type: application/json; charset=UTF-8\r\n\r\n{resource}\r\n\
+                 --{boundary}\r\nContent-Type: application/octet-stream\r\n\r\n"
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(&value);
+        body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+
+        let req = self
+            .request(Method::POST, &url)
+            .await?
+            .header(
+                "Content-Type",
+                format!("multipart/related; boundary={boundary}"),
+            )
+            .body(body.freeze());
+
+        let resp = self.send(req).await?;
+
+        match resp.status().as_u16() {
+            200 => parse_put_response(resp).await,
+            412 if condition.is_some() => Err(Error::ConditionFailed {
+                condition: condition.unwrap(),
+            }),
+            _ => Err(status_error(resp).await),
+        }
+    }
+
+    /// Upload a stream in chunks through a resumable upload session
+    async fn resumable_upload(
+        &self,
+        key: &Path,
+        condition: Option<Condition>,
+        metadata: Option<Metadata>,
+        mut stream: ByteStream,
+    ) -> Result<PutResponse, Error> {
+        let session = self
+            .start_session(key, condition.as_ref(), metadata.as_ref())
+            .await?;
+
+        // `buffer` holds the bytes from `offset` on that are not yet persisted
+        let mut buffer = BytesMut::new();
+        let mut offset = 0u64;
+        let mut exhausted = false;
+        loop {
+            // Only send a chunk once more data follows it, since every chunk
+            // but the last must be exactly `chunk_size` bytes
+            while !exhausted && buffer.len() <= self.chunk_size {
+                match stream.try_next().await? {
+                    Some(chunk) => buffer.extend_from_slice(&chunk),
+                    None => exhausted = true,
+                }
+            }
+
+            let (len, total) = if exhausted {
+                (buffer.len(), Some(offset + buffer.len() as u64))
+            } else {
+                (self.chunk_size, None)
+            };
+            let chunk = Bytes::copy_from_slice(&buffer[..len]);
+            match self
+                .upload_chunk(&session, offset, chunk, total, condition.as_ref())
+                .await?
+            {
+                ChunkStatus::Complete(response) => return Ok(response),
+                ChunkStatus::Persisted(persisted) => {
+                    let persisted_len = persisted
+                        .checked_sub(offset)
+                        .filter(|n| *n <= buffer.len() as u64)
+                        .ok_or_else(|| {
+                            Error::Other(format!(
+                                "GCS upload session persisted unexpected offset {persisted}"
+                            ))
+                        })?;
+                    buffer.advance(persisted_len as usize);
+                    offset = persisted;
+                }
+            }
+        }
+    }
+
+    /// Initiate a resumable upload session, returning the session URI
+    async fn start_session(
+        &self,
+        key: &Path,
+        condition: Option<&Condition>,
+        metadata: Option<&Metadata>,
+    ) -> Result<String, Error> {
+        let preconditions = self.preconditions(key, condition, false).await?;
+        let (bucket, key) = self.locate(key)?;
+        let mut url = format!(
+            "{}/upload/storage/v1/b/{}/o?uploadType=resumable",
+            self.endpoint,
+            urlencoding::encode(bucket),
+        );
+        push_query(&mut url, &preconditions);
+
+        let req = self
+            .request(Method::POST, &url)
+            .await?
+            .header("Content-Type", "application/json; charset=UTF-8")
+            .body(object_resource(key, metadata).to_string());
+
+        let resp = self.send(req).await?;
+
+        match resp.status().as_u16() {
+            200 | 201 => resp
+                .headers()
+                .get("location")
+                .and_then(|v| v.to_str().ok())
+                .map(String::from)
+                .ok_or_else(|| Error::Other("missing upload session location".into())),
+            412 if condition.is_some() => Err(Error::ConditionFailed {
+                condition: condition.unwrap().clone(),
+            }),
+            _ => Err(status_error(resp).await),
+        }
+    }
+
+    /// Send a chunk, resuming from the persisted offset after transient failures
+    ///
+    /// `total` is the size of the whole object when this is the final chunk.
+    async fn upload_chunk(
+        &self,
+        session: &str,
+        mut offset: u64,
+        mut chunk: Bytes,
+        total: Option<u64>,
+        condition: Option<&Condition>,
+    ) -> Result<ChunkStatus, Error> {
+        let mut delay = INITIAL_RETRY_DELAY;
+        let mut attempt = 1;
+        loop {
+            let error = match self
+                .send_chunk(session, offset, chunk.clone(), total, condition)
+                .await?
+            {
+                Ok(status) => return Ok(status),
+                Err(error) => error,
+            };
+            if attempt == MAX_CHUNK_ATTEMPTS {
+                return Err(error);
+            }
+            attempt += 1;
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+
+            // Ask the session how much was persisted and resend only the rest
+            match self
+                .send_chunk(session, 0, Bytes::new(), total, condition)
+                .await?
+            {
+                Ok(ChunkStatus::Persisted(persisted)) if persisted >= offset => {
+                    let sent = (persisted - offset).min(chunk.len() as u64);
+                    chunk.advance(sent as usize);
+                    offset += sent;
+                    if chunk.is_empty() && total.is_none() {
+                        return Ok(ChunkStatus::Persisted(persisted));
+                    }
+                }
+                Ok(ChunkStatus::Complete(response)) => return Ok(ChunkStatus::Complete(response)),
+                Ok(ChunkStatus::Persisted(_)) | Err(_) => {}
+            }
+        }
+    }
+
+    /// Send one chunk of a resumable upload
+    ///
+    /// An empty chunk of a non-final upload queries the session status.
+    async fn send_chunk(
+        &self,
+        session: &str,
+        offset: u64,
+        chunk: Bytes,
+        total: Option<u64>,
+        condition: Option<&Condition>,
+    ) -> ChunkResult {
+        let total_str = total.map_or_else(|| "*".to_string(), |t| t.to_string());
+        let content_range = if chunk.is_empty() {
+            format!("bytes */{total_str}")
+        } else {
+            let end = offset + chunk.len() as u64 - 1;
+            format!("bytes {offset}-{end}/{total_str}")
+        };
+
+        let req = self
+            .request(Method::PUT, session)
+            .await?
+            .header("Content-Range", content_range)
+            .body(chunk);
+
+        let resp = match self.send(req).await {
+            Ok(resp) => resp,
+            Err(e) => return retryable(e),
+        };
+
+        match resp.status().as_u16() {
+            200 | 201 => Ok(Ok(ChunkStatus::Complete(parse_put_response(resp).await?))),
+            // 308 Resume Incomplete: Range ("bytes=0-N") covers the persisted bytes
+            308 => {
+                let persisted = resp
+                    .headers()
+                    .get("range")
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.rsplit('-').next())
+                    .and_then(|v| v.parse::<u64>().ok())
+                    .map_or(0, |last| last + 1);
+                Ok(Ok(ChunkStatus::Persisted(persisted)))
+            }
+            412 if condition.is_some() => Err(Error::ConditionFailed {
+                condition: condition.unwrap().clone(),
+            }),
+            _ => retryable(status_error(resp).await),
+        }
+    }
+}
+
+/// Report retryable chunk failures to the caller for resumption
+fn retryable(error: Error) -> ChunkResult {
+    if error.is_retryable() {
+        Ok(Err(error))
+    } else {
+        Err(error)
+    }
+}
+
+/// Build the JSON object resource sent alongside upload data
+fn object_resource(name: &str, metadata: Option<&Metadata>) -> serde_json::Value {
+    let mut resource = serde_json::json!({ "name": name });
+    if let Some(metadata) = metadata {
+        resource["metadata"] = serde_json::json!(metadata.headers);
+    }
+    resource
+}
+
+/// Parse the object resource returned by a completed upload
+async fn parse_put_response(resp: reqwest::Response) -> Result<PutResponse, Error> {
+    let body: serde_json::Value = resp.json().await.map_err(request_error)?;
+    Ok(PutResponse {
+        version: parse_version(&body)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use kanso_client::{GetRequest, HeadRequest, ObjectStore};
+    use kanso_fake_gcs::{FakeGcs, RecordedRequest};
+
+    use super::*;
+
+    fn payload(len: usize) -> Bytes {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    async fn stored(server: &FakeGcs, key: &str) -> kanso_client::GetResponse {
+        let request = GetRequest::new(key).unwrap();
+        server.store().get(request).await.unwrap().unwrap()
+    }
+
+    /// Requests starting a resumable upload session
+    fn sessions(requests: &[RecordedRequest]) -> usize {
+        requests
+            .iter()
+            .filter(|r| r.method == "POST" && r.query["uploadType"] == "resumable")
+            .count()
+    }
+
+    #[tokio::test]
+    async fn test_multipart_upload_carries_metadata() {
+        let server = FakeGcs::start().await;
+        let store = GcsStore::with_endpoint(server.endpoint());
+
+        let key = Path::new("bucket/small").unwrap();
+        let metadata = Metadata::with("k", "v");
+        let value = Bytes::from("multipart\r\n--value");
+        let response = store
+            .upload(&key, None, Some(metadata.clone()), value.clone())
+            .await
+            .unwrap();
+        assert_eq!(server.requests()[0].query["uploadType"], "multipart");
+
+        let object = stored(&server, "bucket/small").await;
+        assert_eq!(object.value, value);
+        assert_eq!(object.metadata, metadata);
+        let head = store.head(HeadRequest::new("bucket/small").unwrap()).await;
+        assert_eq!(head.unwrap().unwrap().version, response.version);
+
+        let result = store
+            .upload(&key, Some(Condition::IfAbsent), None, value)
+            .await;
+        assert!(matches!(result, Err(Error::ConditionFailed { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_resumable_upload_resumes_after_failures() {
+        let server = FakeGcs::start().await;
+        server.fail_upload_chunks(2);
+        let store = GcsStore::with_endpoint(server.endpoint())
+            .resumable_threshold(CHUNK_ALIGNMENT)
+            .upload_chunk_size(1);
+
+        let key = Path::new("bucket/large").unwrap();
+        let value = payload(3 * CHUNK_ALIGNMENT + 1000);
+        let response = store
+            .upload(&key, Some(Condition::IfAbsent), None, value.clone())
+            .await
+            .unwrap();
+        assert_eq!(stored(&server, "bucket/large").await.value, value);
+        // Each failure is followed by a query for the persisted offset
+        let requests = server.requests();
+        let queries = requests.iter().filter(|r| {
+            let range = r.headers.get("content-range");
+            range.is_some_and(|range| range.to_str().unwrap().starts_with("bytes */"))
+        });
+        assert_eq!(queries.count(), 2);
+
+        // Streams switch to a resumable session once they pass the threshold
+        let chunks: Vec<Result<Bytes, Error>> = value
+            .chunks(10_000)
+            .map(|c| Ok(Bytes::copy_from_slice(c)))
+            .collect();
+        store
+            .upload_stream(
+                &key,
+                Some(Condition::IfVersionMatches(response.version)),
+                Some(Metadata::with("k", "v")),
+                Box::pin(futures::stream::iter(chunks)),
+            )
+            .await
+            .unwrap();
+        assert_eq!(sessions(&server.requests()), 2);
+        let object = stored(&server, "bucket/large").await;
+        assert_eq!(object.value, value);
+        assert_eq!(object.metadata, Metadata::with("k", "v"));
+    }
+
+    #[tokio::test]
+    async fn test_resumable_upload_honors_condition() {
+        let server = FakeGcs::start().await;
+        let store = GcsStore::with_endpoint(server.endpoint()).resumable_threshold(0);
+
+        let key = Path::new("bucket/object").unwrap();
+        store
+            .upload(&key, Some(Condition::IfAbsent), None, payload(10))
+            .await
+            .unwrap();
+        let result = store
+            .upload(&key, Some(Condition::IfAbsent), None, payload(10))
+            .await;
+        assert!(matches!(result, Err(Error::ConditionFailed { .. })));
+        assert_eq!(sessions(&server.requests()), 2);
+    }
+}
@@ -0,0 +1,420 @@
+use async_trait::async_trait;
+use futures::TryStreamExt;
+use kanso_client::http::{
+    IF_META_PREFIX, META_PREFIX, etag, parse_etag, request_error, retry_after,
+};
+use kanso_client::{
+    Condition, DeleteRequest, DeleteResponse, Error, GetRequest, GetResponse, GetStreamResponse,
+    HeadRequest, HeadResponse, ListRequest, ListResponse, Metadata, ObjectStore, ObjectSummary,
+    PatchRequest, PatchResponse, Path, PutRequest, PutResponse, PutStreamRequest, Version,
+};
+
+/// ObjectStore client for a kanso-server gateway
+///
+/// Every operation is forwarded to the store behind the gateway, so versions,
+/// conditions and errors follow that store's semantics. Metadata is carried
+/// in headers: keys come back lowercased and values must be valid header
+/// values.
+#[derive(Debug, Clone)]
+pub struct HttpStore {
+    client: reqwest::Client,
+    endpoint: String,
+}
+
+impl HttpStore {
+    /// Create a new HttpStore for the gateway at `endpoint` (e.g. "http://localhost:8080")
+    pub fn new(endpoint: impl Into<String>) -> Self {
+        let endpoint: String = endpoint.into();
+        Self {
+            client: reqwest::Client::new(),
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+        }
+    }
+
+    fn url(&self, key: &Path) -> String {
+        let key: Vec<_> = key
+            .as_str()
+            .split('/')
+            .map(|segment| urlencoding::encode(segment))
+            .collect();
+        format!("{}/objects/{}", self.endpoint, key.join("/"))
+    }
+}
+
+/// Add the headers expressing a condition
+fn with_condition(
+    req: reqwest::RequestBuilder,
+    condition: Option<&Condition>,
+) -> reqwest::RequestBuilder {
+    match condition {
+        Some(Condition::IfAbsent) => req.header("If-None-Match", "*"),
+        Some(Condition::IfExists) => req.header("If-Match", "*"),
+        Some(Condition::IfVersionMatches(v)) => req.header("If-Match", etag(v)),
+        Some(Condition::IfVersionNotMatches(v)) => req.header("If-None-Match", etag(v)),
+        // Matching no entries only requires the object to exist
+        Some(Condition::IfMetadataMatches(m)) if m.is_empty() => req.header("If-Match", "*"),
+        Some(Condition::IfMetadataMatches(m)) => m.headers.iter().fold(req, |req, (k, v)| {
+            req.header(format!("{IF_META_PREFIX}{k}"), v)
+        }),
+        None => req,
+    }
+}
+
+/// Add `x-kanso-meta-*` headers for metadata
+fn with_metadata(
+    mut req: reqwest::RequestBuilder,
+    metadata: Option<&Metadata>,
+) -> reqwest::RequestBuilder {
+    for (k, v) in metadata.into_iter().flat_map(|m| &m.headers) {
+        req = req.header(format!("{META_PREFIX}{k}"), v);
+    }
+    req
+}
+
+/// Read the version from a response's ETag
+fn parse_version(resp: &reqwest::Response) -> Result<Version, Error> {
+    let etag = resp
+        .headers()
+        .get("etag")
+        .and_then(|v| v.to_str().ok())
+        .ok_or_else(|| Error::Other("missing ETag".into()))?;
+    parse_etag(etag).ok_or_else(|| Error::Other(format!("invalid ETag {etag}")))
+}
+
+/// Read metadata from a response's `x-kanso-meta-*` headers
+fn parse_metadata(resp: &reqwest::Response) -> Metadata {
+    let mut metadata = Metadata::new();
+    for (name, value) in resp.headers() {
+        if let Some(key) = name.as_str().strip_prefix(META_PREFIX)
+            && let Ok(v) = value.to_str()
+        {
+            metadata.insert(key, v);
+        }
+    }
+    metadata
+}
+
+fn header_u64(resp: &reqwest::Response, name: &str) -> Option<u64> {
+    resp.headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+}
+
+/// Map an unexpected response status onto the error taxonomy
+async fn status_error(resp: reqwest::Response, condition: Option<Condition>) -> Error {
+    let status = resp.status().as_u16();
+    let retry_after = retry_after(resp.headers());
+    let body = resp.text().await.unwrap_or_default();
+
+    match (status, condition) {
+        (404, _) => Error::NotFound,
+        (412, Some(condition)) => Error::ConditionFailed { condition },
+        (416, _) => Error::RangeNotSatisfiable,
+        // The gateway answers 500 for errors that are not worth retrying,
+        // and 504 for timeouts
+        (500, _) => Error::Other(body),
+        (504, _) => Error::Timeout { source: None },
+        _ => Error::from_status(status, body, retry_after),
+    }
+}
+
+#[async_trait]
+impl ObjectStore for HttpStore {
+    async fn get(&self, request: GetRequest) -> Result<Option<GetResponse>, Error> {
+        match self.get_stream(request).await? {
+            Some(response) => Ok(Some(response.collect().await?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_stream(&self, request: GetRequest) -> Result<Option<GetStreamResponse>, Error> {
+        let mut req = self.client.get(self.url(&request.key));
+        if let Some(version) = &request.version {
+            req = req.query(&[("version", version.as_str())]);
+        }
+        if let Some(version) = &request.if_version_not_matches {
+            req = req.header("If-None-Match", etag(version));
+        }
+        if let Some(range) = &request.range {
+            req = req.header("Range", range.header_value()?);
+        }
+
+        let resp = req.send().await.map_err(request_error)?;
+
+        // Partial responses carry the total size in Content-Range
+        // ("bytes start-end/size"), full responses in Content-Length
+        let size = match resp.status().as_u16() {
+            304 => return Err(Error::NotModified),
+            404 => return Ok(None),
+            200 => header_u64(&resp, "content-length"),
+            206 => resp
+                .headers()
+                .get("content-range")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.rsplit('/').next())
+                .and_then(|v| v.parse().ok()),
+            _ => return Err(status_error(resp, None).await),
+        };
+        let size = size.ok_or_else(|| Error::Other("missing object size header".into()))?;
+        let version = parse_version(&resp)?;
+        let metadata = parse_metadata(&resp);
+        let stream = resp.bytes_stream().map_err(request_error);
+
+        Ok(Some(GetStreamResponse {
+            stream: Box::pin(stream),
+            size,
+            version,
+            metadata,
+        }))
+    }
+
+    async fn head(&self, request: HeadRequest) -> Result<Option<HeadResponse>, Error> {
+        let resp = self
+            .client
+            .head(self.url(&request.key))
+            .send()
+            .await
+            .map_err(request_error)?;
+
+        match resp.status().as_u16() {
+            404 => Ok(None),
+            200 => {
+                let last_modified = resp
+                    .headers()
+                    .get("last-modified")
+                    .and_then(|v| v.to_str().ok())
+                    .map(|v| {
+                        httpdate::parse_http_date(v)
+                            .map_err(|e| Error::Other(format!("invalid Last-Modified: {e}")))
+                    })
+                    .transpose()?;
+                Ok(Some(HeadResponse {
+                    version: parse_version(&resp)?,
+                    metadata: parse_metadata(&resp),
+                    size: header_u64(&resp, "content-length")
+                        .ok_or_else(|| Error::Other("missing Content-Length".into()))?,
+                    content_type: resp
+                        .headers()
+                        .get("content-type")
+                        .and_then(|v| v.to_str().ok())
+                        .map(String::from),
+                    last_modified,
+                }))
+            }
+            _ => Err(status_error(resp, None).await),
+        }
+    }
+
+    async fn put(&self, request: PutRequest) -> Result<PutResponse, Error> {
+        let req = self.client.put(self.url(&request.key)).body(request.value);
+        let req = with_condition(req, request.condition.as_ref());
+        let req = with_metadata(req, request.metadata.as_ref());
+
+        let resp = req.send().await.map_err(request_error)?;
+
+        match resp.status().as_u16() {
+            200 => Ok(PutResponse {
+                version: parse_version(&resp)?,
+            }),
+            _ => Err(status_error(resp, request.condition).await),
+        }
+    }
+
+    async fn put_stream(&self, request: PutStreamRequest) -> Result<PutResponse, Error> {
+        let req = self
+            .client
+            .put(self.url(&request.key))
+            .body(reqwest::Body::wrap_stream(request.stream));
+        let req = with_condition(req, request.condition.as_ref());
+        let req = with_metadata(req, request.metadata.as_ref());
+
+        let resp = req.send().await.map_err(request_error)?;
+
+        match resp.status().as_u16() {
+            200 => Ok(PutResponse {
+                version: parse_version(&resp)?,
+            }),
+            _ => Err(status_error(resp, request.condition).await),
+        }
+    }
+
+    async fn patch(&self, request: PatchRequest) -> Result<PatchResponse, Error> {
+        let req = self.client.patch(self.url(&request.key));
+        let req = with_condition(req, request.condition.as_ref());
+        let req = with_metadata(req, Some(&request.metadata));
+
+        let resp = req.send().await.map_err(request_error)?;
+
+        match resp.status().as_u16() {
+            200 => Ok(PatchResponse {
+                version: parse_version(&resp)?,
+            }),
+            _ => Err(status_error(resp, request.condition).await),
+        }
+    }
+
+    async fn delete(&self, request: DeleteRequest) -> Result<DeleteResponse, Error> {
+        let req = self.client.delete(self.url(&request.key));
+        let req = with_condition(req, request.condition.as_ref());
+
+        let resp = req.send().await.map_err(request_error)?;
+
+        match resp.status().as_u16() {
+            200 | 204 => Ok(DeleteResponse),
+            _ => Err(status_error(resp, request.condition).await),
+        }
+    }
+
+    async fn list(&self, request: ListRequest) -> Result<ListResponse, Error> {
+        let mut query = vec![("prefix", request.prefix.clone())];
+        if let Some(delimiter) = request.delimiter {
+            query.push(("delimiter", delimiter.to_string()));
+        }
+        if let Some(start_after) = &request.start_after {
+            query.push(("start_after", start_after.clone()));
+        }
+        if let Some(token) = &request.page_token {
+            query.push(("page_token", token.clone()));
+        }
+        if let Some(max_results) = request.max_results {
+            query.push(("max_results", max_results.to_string()));
+        }
+        let query: Vec<_> = query
+            .iter()
+            .map(|(k, v)| format!("{k}={}", urlencoding::encode(v)))
+            .collect();
+        let url = format!("{}/objects?{}", self.endpoint, query.join("&"));
+
+        let resp = self.client.get(&url).send().await.map_err(request_error)?;
+
+        match resp.status().as_u16() {
+            200 => {
+                let body: serde_json::Value = resp.json().await.map_err(request_error)?;
+                let mut objects = Vec::new();
+                for item in body["objects"].as_array().into_iter().flatten() {
+                    objects.push(parse_object(item)?);
+                }
+                let common_prefixes = body["common_prefixes"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|p| p.as_str())
+                    .map(String::from)
+                    .collect();
+
+                Ok(ListResponse {
+                    objects,
+                    common_prefixes,
+                    next_page_token: body["next_page_token"].as_str().map(String::from),
+                })
+            }
+            _ => Err(status_error(resp, None).await),
+        }
+    }
+}
+
+/// Parse an object from a list page
+fn parse_object(item: &serde_json::Value) -> Result<ObjectSummary, Error> {
+    let key = item["key"]
+        .as_str()
+        .ok_or_else(|| Error::Other("missing key".into()))?;
+    let key = Path::new(key).map_err(|e| Error::Other(format!("invalid key '{key}': {e}")))?;
+    let version = item["version"]
+        .as_str()
+        .ok_or_else(|| Error::Other("missing version".into()))?;
+    let size = item["size"]
+        .as_u64()
+        .ok_or_else(|| Error::Other("missing size".into()))?;
+
+    let mut metadata = Metadata::new();
+    if let Some(fields) = item["metadata"].as_object() {
+        for (k, v) in fields {
+            if let Some(v) = v.as_str() {
+                metadata.insert(k, v);
+            }
+        }
+    }
+
+    Ok(ObjectSummary {
+        key,
+        version: Version::new(version),
+        size,
+        metadata,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use kanso_client::Client;
+    use kanso_inmemory::InMemoryStore;
+    use kanso_middleware::{Fault, FaultError, FaultyStore, Operation};
+
+    use super::*;
+
+    async fn start() -> HttpStore {
+        start_with(Arc::new(InMemoryStore::new())).await
+    }
+
+    async fn start_with(backend: Client) -> HttpStore {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(kanso_server::serve(listener, backend));
+        HttpStore::new(endpoint)
+    }
+
+    #[tokio::test]
+    async fn test_compliance() {
+        let store: Client = Arc::new(start().await);
+        kanso_backends_test_suite::run_compliance_tests(&store, "").await;
+    }
+
+    #[tokio::test]
+    async fn test_round_trips_awkward_keys() {
+        let store: Client = Arc::new(start().await);
+
+        let key = "dir/with space/100%/q?a=b#frag";
+        let version = PutRequest::new(key, "value".into())
+            .unwrap()
+            .metadata(Metadata::with("k", "v w"))
+            .execute(&store)
+            .await
+            .unwrap()
+            .version;
+        let object = GetRequest::new(key)
+            .unwrap()
+            .execute(&store)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(object.value, "value");
+        assert_eq!(object.version, version);
+        assert_eq!(object.metadata.get("k").unwrap(), "v w");
+
+        let page = ListRequest::new("dir/")
+            .unwrap()
+            .execute(&store)
+            .await
+            .unwrap();
+        assert_eq!(page.objects.len(), 1);
+        assert_eq!(page.objects[0].key.as_str(), key);
+    }
+
+    #[tokio::test]
+    async fn test_gateway_failures_are_not_retried() {
+        let faulty = FaultyStore::new(Arc::new(InMemoryStore::new()), 0);
+        faulty.script(
+            Operation::Get,
+            [Some(Fault::Error(FaultError::Backend(409)))],
+        );
+        let store: Client = Arc::new(start_with(Arc::new(faulty)).await);
+
+        // The gateway answers 500 for the backend's error, which is final
+        let result = GetRequest::new("key").unwrap().execute(&store).await;
+        let error = result.unwrap_err();
+        assert!(matches!(error, Error::Other(_)), "{error:?}");
+        assert!(!error.is_retryable());
+    }
+}
@@ -1,11 +1,13 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
+use std::time::SystemTime;
 
 use async_trait::async_trait;
 use bytes::Bytes;
 use kanso_client::{
-    Condition, GetRequest, GetResponse, Metadata, ObjectStore, PatchRequest, PatchResponse,
-    PutRequest, PutResponse, Version,
+    Condition, CopyRequest, CopyResponse, DeleteRequest, DeleteResponse, GetRequest, GetResponse,
+    HeadRequest, HeadResponse, ListRequest, ListResponse, Metadata, ObjectStore, ObjectSummary,
+    PatchRequest, PatchResponse, Path, PutRequest, PutResponse, Version,
 };
 use tokio::sync::RwLock;
 
@@ -14,21 +16,85 @@ struct StoredObject {
     value: Bytes,
     version: Version,
     metadata: Metadata,
+    last_modified: SystemTime,
 }
 
+impl StoredObject {
+    fn revision(&self) -> Revision {
+        Revision::Written {
+            version: self.version.clone(),
+            value: self.value.clone(),
+            metadata: self.metadata.clone(),
+            last_modified: self.last_modified,
+        }
+    }
+}
+
+/// A state of a key, as recorded by an InMemoryStore in history mode
+#[derive(Debug, Clone, PartialEq)]
+pub enum Revision {
+    /// The key was written by a put or patch
+    Written {
+        version: Version,
+        value: Bytes,
+        metadata: Metadata,
+        last_modified: SystemTime,
+    },
+    /// The key was deleted
+    Deleted { deleted_at: SystemTime },
+}
+
+/// Recorded states of every key, oldest first
+type History = HashMap<String, Vec<Revision>>;
+
 /// In-memory implementation of ObjectStore for testing
+///
+/// In history mode every put, patch and delete is recorded, so past versions
+/// can be read with `GetRequest::version` and the sequence of states of a key
+/// inspected with `history`. History is never pruned.
 #[derive(Debug, Clone)]
 pub struct InMemoryStore {
-    data: Arc<RwLock<HashMap<String, StoredObject>>>,
+    data: Arc<RwLock<BTreeMap<String, StoredObject>>>,
     version_counter: Arc<RwLock<u64>>,
+    history: Option<Arc<RwLock<History>>>,
 }
 
 impl InMemoryStore {
     /// Create a new empty in-memory store
     pub fn new() -> Self {
         Self {
-            data: Arc::new(RwLock::new(HashMap::new())),
+            data: Arc::new(RwLock::new(BTreeMap::new())),
             version_counter: Arc::new(RwLock::new(0)),
+            history: None,
+        }
+    }
+
+    /// Create a new empty in-memory store that records the history of every key
+    pub fn with_history() -> Self {
+        Self {
+            history: Some(Arc::default()),
+            ..Self::new()
+        }
+    }
+
+    /// The states of a key, oldest first
+    ///
+    /// In history mode this includes every write and delete of the key;
+    /// otherwise only the current state, if the key exists.
+    pub async fn history(&self, key: impl AsRef<str>) -> Vec<Revision> {
+        let data = self.data.read().await;
+        match &self.history {
+            Some(history) => history
+                .read()
+                .await
+                .get(key.as_ref())
+                .cloned()
+                .unwrap_or_default(),
+            None => data
+                .get(key.as_ref())
+                .map(StoredObject::revision)
+                .into_iter()
+                .collect(),
         }
     }
 
@@ -37,6 +103,61 @@ impl InMemoryStore {
         *counter += 1;
         Version::new(counter.to_string())
     }
+
+    /// Record a new state of a key; callers hold the data lock
+    async fn record(&self, key: &str, revision: Revision) {
+        if let Some(history) = &self.history {
+            let mut history = history.write().await;
+            history.entry(key.to_string()).or_default().push(revision);
+        }
+    }
+
+    /// Find the given version of a key, current or (in history mode) past
+    async fn find_version(
+        &self,
+        data: &BTreeMap<String, StoredObject>,
+        key: &str,
+        version: &Version,
+    ) -> Option<StoredObject> {
+        if let Some(obj) = data.get(key).filter(|obj| &obj.version == version) {
+            return Some(obj.clone());
+        }
+        let history = self.history.as_ref()?.read().await;
+        history
+            .get(key)?
+            .iter()
+            .find_map(|revision| match revision {
+                Revision::Written {
+                    version: v,
+                    value,
+                    metadata,
+                    last_modified,
+                } if v == version => Some(StoredObject {
+                    value: value.clone(),
+                    version: v.clone(),
+                    metadata: metadata.clone(),
+                    last_modified: *last_modified,
+                }),
+                _ => None,
+            })
+    }
+}
+
+/// Fail with `ConditionFailed` unless the condition holds for the current object
+fn check_condition(
+    condition: Option<&Condition>,
+    current: Option<&StoredObject>,
+) -> Result<(), kanso_client::Error> {
+    match condition {
+        Some(condition)
+            if !condition.is_satisfied_by(current.map(|obj| (&obj.version, &obj.metadata))) =>
+        {
+            Err(kanso_client::Error::ConditionFailed {
+                condition: condition.clone(),
+            })
+        }
+        _ => Ok(()),
+    }
 }
 
 impl Default for InMemoryStore {
@@ -49,53 +170,67 @@ impl Default for InMemoryStore {
 impl ObjectStore for InMemoryStore {
     async fn get(&self, request: GetRequest) -> Result<Option<GetResponse>, kanso_client::Error> {
         let data = self.data.read().await;
-        Ok(data.get(request.key.as_str()).map(|obj| GetResponse {
-            value: obj.value.clone(),
+        let obj = match &request.version {
+            Some(version) => {
+                self.find_version(&data, request.key.as_str(), version)
+                    .await
+            }
+            None => data.get(request.key.as_str()).cloned(),
+        };
+        let Some(obj) = obj else {
+            return Ok(None);
+        };
+        if request.if_version_not_matches.as_ref() == Some(&obj.version) {
+            return Err(kanso_client::Error::NotModified);
+        }
+
+        let size = obj.value.len() as u64;
+        let value = match request.range {
+            Some(range) => {
+                let range = range.resolve(size)?;
+                obj.value.slice(range.start as usize..range.end as usize)
+            }
+            None => obj.value.clone(),
+        };
+
+        Ok(Some(GetResponse {
+            value,
+            size,
+            version: obj.version.clone(),
+            metadata: obj.metadata.clone(),
+        }))
+    }
+
+    async fn head(
+        &self,
+        request: HeadRequest,
+    ) -> Result<Option<HeadResponse>, kanso_client::Error> {
+        let data = self.data.read().await;
+        Ok(data.get(request.key.as_str()).map(|obj| HeadResponse {
             version: obj.version.clone(),
             metadata: obj.metadata.clone(),
+            size: obj.value.len() as u64,
+            content_type: None,
+            last_modified: Some(obj.last_modified),
         }))
     }
 
     async fn put(&self, request: PutRequest) -> Result<PutResponse, kanso_client::Error> {
         let mut data = self.data.write().await;
 
-        // Check conditions
-        if let Some(condition) = &request.condition {
-            match condition {
-                Condition::IfAbsent => {
-                    if data.contains_key(request.key.as_str()) {
-                        return Err(kanso_client::Error::ConditionFailed {
-                            condition: condition.clone(),
-                        });
-                    }
-                }
-                Condition::IfVersionMatches(expected_version) => {
-                    match data.get(request.key.as_str()) {
-                        Some(obj) if &obj.version == expected_version => {
-                            // Version matches, continue
-                        }
-                        _ => {
-                            // Version mismatch or key doesn't exist
-                            return Err(kanso_client::Error::ConditionFailed {
-                                condition: condition.clone(),
-                            });
-                        }
-                    }
-                }
-            }
-        }
+        check_condition(request.condition.as_ref(), data.get(request.key.as_str()))?;
 
         let version = self.next_version().await;
         let metadata = request.metadata.unwrap_or_default();
 
-        data.insert(
-            request.key.as_str().to_string(),
-            StoredObject {
-                value: request.value,
-                version: version.clone(),
-                metadata,
-            },
-        );
+        let obj = StoredObject {
+            value: request.value,
+            version: version.clone(),
+            metadata,
+            last_modified: SystemTime::now(),
+        };
+        self.record(request.key.as_str(), obj.revision()).await;
+        data.insert(request.key.as_str().to_string(), obj);
 
         Ok(PutResponse { version })
     }
@@ -104,43 +239,82 @@ impl ObjectStore for InMemoryStore {
         let mut data = self.data.write().await;
 
         // Get the existing object and clone the value we need
-        let value = data
+        let obj = data
             .get(request.key.as_str())
-            .map(|obj| obj.value.clone())
             .ok_or(kanso_client::Error::NotFound)?;
+        check_condition(request.condition.as_ref(), Some(obj))?;
+        let value = obj.value.clone();
 
-        // Check condition if present
-        if let Some(condition) = &request.condition {
-            match condition {
-                Condition::IfAbsent => {
-                    // This doesn't make sense for patch (object must exist), but handle it
-                    return Err(kanso_client::Error::ConditionFailed {
-                        condition: condition.clone(),
-                    });
-                }
-                Condition::IfVersionMatches(expected_version) => {
-                    let obj = data.get(request.key.as_str()).unwrap(); // Safe: we just checked it exists
-                    if &obj.version != expected_version {
-                        return Err(kanso_client::Error::ConditionFailed {
-                            condition: condition.clone(),
-                        });
-                    }
-                }
+        // Create new version and update metadata, keep the value
+        let version = self.next_version().await;
+        let obj = StoredObject {
+            value,
+            version: version.clone(),
+            metadata: request.metadata,
+            last_modified: SystemTime::now(),
+        };
+        self.record(request.key.as_str(), obj.revision()).await;
+        data.insert(request.key.as_str().to_string(), obj);
+
+        Ok(PatchResponse { version })
+    }
+
+    async fn delete(&self, request: DeleteRequest) -> Result<DeleteResponse, kanso_client::Error> {
+        let mut data = self.data.write().await;
+
+        let obj = data
+            .get(request.key.as_str())
+            .ok_or(kanso_client::Error::NotFound)?;
+
+        check_condition(request.condition.as_ref(), Some(obj))?;
+
+        data.remove(request.key.as_str());
+        let deleted_at = SystemTime::now();
+        self.record(request.key.as_str(), Revision::Deleted { deleted_at })
+            .await;
+        Ok(DeleteResponse)
+    }
+
+    async fn list(&self, request: ListRequest) -> Result<ListResponse, kanso_client::Error> {
+        let data = self.data.read().await;
+        request.paginate(data.range(request.prefix.clone()..), |key, obj| {
+            Ok(Some(ObjectSummary {
+                key: Path::new(key).expect("stored keys are valid paths"),
+                version: obj.version.clone(),
+                size: obj.value.len() as u64,
+                metadata: obj.metadata.clone(),
+            }))
+        })
+    }
+
+    async fn copy(&self, request: CopyRequest) -> Result<CopyResponse, kanso_client::Error> {
+        let mut data = self.data.write().await;
+
+        // Values are reference-counted, so the copy shares the source's bytes
+        let source = match &request.source_version {
+            Some(version) => {
+                self.find_version(&data, request.from.as_str(), version)
+                    .await
             }
+            None => data.get(request.from.as_str()).cloned(),
         }
+        .ok_or(kanso_client::Error::NotFound)?;
+        check_condition(
+            request.destination_condition.as_ref(),
+            data.get(request.to.as_str()),
+        )?;
 
-        // Create new version and update metadata, keep the value
         let version = self.next_version().await;
-        data.insert(
-            request.key.as_str().to_string(),
-            StoredObject {
-                value,
-                version: version.clone(),
-                metadata: request.metadata,
-            },
-        );
+        let obj = StoredObject {
+            value: source.value,
+            version: version.clone(),
+            metadata: request.metadata_override.unwrap_or(source.metadata),
+            last_modified: SystemTime::now(),
+        };
+        self.record(request.to.as_str(), obj.revision()).await;
+        data.insert(request.to.as_str().to_string(), obj);
 
-        Ok(PatchResponse { version })
+        Ok(CopyResponse { version })
     }
 }
 
@@ -153,4 +327,128 @@ mod tests {
         let store: kanso_client::Client = Arc::new(InMemoryStore::new());
         kanso_backends_test_suite::run_compliance_tests(&store, "").await;
     }
+
+    #[tokio::test]
+    async fn test_compliance_with_history() {
+        let store: kanso_client::Client = Arc::new(InMemoryStore::with_history());
+        kanso_backends_test_suite::run_compliance_tests(&store, "").await;
+    }
+
+    #[tokio::test]
+    async fn test_history_and_time_travel() {
+        let store = InMemoryStore::with_history();
+        let client: kanso_client::Client = Arc::new(store.clone());
+
+        let v1 = PutRequest::new("key", Bytes::from("one"))
+            .unwrap()
+            .execute(&client)
+            .await
+            .unwrap()
+            .version;
+        let v2 = PatchRequest::new("key", Metadata::with("k", "v"))
+            .unwrap()
+            .execute(&client)
+            .await
+            .unwrap()
+            .version;
+        DeleteRequest::new("key")
+            .unwrap()
+            .execute(&client)
+            .await
+            .unwrap();
+        let v3 = PutRequest::new("key", Bytes::from("three"))
+            .unwrap()
+            .execute(&client)
+            .await
+            .unwrap()
+            .version;
+
+        let history = store.history("key").await;
+        assert_eq!(history.len(), 4);
+        assert!(matches!(
+            &history[0],
+            Revision::Written { version, value, .. } if *version == v1 && value == "one"
+        ));
+        assert!(matches!(
+            &history[1],
+            Revision::Written { version, value, metadata, .. }
+                if *version == v2 && value == "one" && metadata.get("k").unwrap() == "v"
+        ));
+        assert!(matches!(history[2], Revision::Deleted { .. }));
+        assert!(matches!(&history[3], Revision::Written { version, .. } if *version == v3));
+
+        // Past versions stay readable, including ranges of them
+        let old = GetRequest::new("key")
+            .unwrap()
+            .version(v1.clone())
+            .range(kanso_client::GetRange::From(1))
+            .execute(&client)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(old.value, "ne");
+        assert_eq!(old.version, v1);
+        let current = GetRequest::new("key")
+            .unwrap()
+            .version(v3)
+            .execute(&client)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(current.value, "three");
+        assert!(
+            GetRequest::new("key")
+                .unwrap()
+                .version(Version::new("999"))
+                .execute(&client)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        // Copies can restore a deleted version
+        kanso_client::CopyRequest::new("key", "restored")
+            .unwrap()
+            .source_version(v2)
+            .execute(&client)
+            .await
+            .unwrap();
+        let restored = GetRequest::new("restored")
+            .unwrap()
+            .execute(&client)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(restored.value, "one");
+        assert_eq!(restored.metadata.get("k").unwrap(), "v");
+    }
+
+    #[tokio::test]
+    async fn test_without_history_only_current_version_is_readable() {
+        let store = InMemoryStore::new();
+        let client: kanso_client::Client = Arc::new(store.clone());
+
+        let v1 = PutRequest::new("key", Bytes::from("one"))
+            .unwrap()
+            .execute(&client)
+            .await
+            .unwrap()
+            .version;
+        PutRequest::new("key", Bytes::from("two"))
+            .unwrap()
+            .execute(&client)
+            .await
+            .unwrap();
+
+        assert_eq!(store.history("key").await.len(), 1);
+        assert!(
+            GetRequest::new("key")
+                .unwrap()
+                .version(v1)
+                .execute(&client)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
 }
@@ -0,0 +1,697 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::TryStreamExt;
+use kanso_client::http::{parse_path, parse_prefix, request_error, retry_after};
+use kanso_client::{
+    Condition, DeleteRequest, DeleteResponse, Error, GetRequest, GetResponse, GetStreamResponse,
+    HeadRequest, HeadResponse, ListRequest, ListResponse, Metadata, ObjectStore, ObjectSummary,
+    PatchRequest, PatchResponse, Path, PinnedCondition, PutRequest, PutResponse, Version,
+};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Method, Url};
+use serde::Deserialize;
+use std::time::{Duration, SystemTime};
+
+#[cfg(test)]
+mod mock;
+mod signing;
+
+pub use signing::Credentials;
+
+/// S3 implementation of ObjectStore using SigV4-signed REST API calls
+///
+/// Path format: "bucket-name/path/to/object"
+/// The bucket is parsed from the first path component.
+///
+/// Versions are ETags. AWS and most S3-compatible servers derive the ETag from
+/// the object's content, so rewriting identical content or patching metadata
+/// leaves the version unchanged, and conditions cannot tell such writes apart.
+/// S3Store therefore does not meet the full ObjectStore contract: a
+/// compare-and-swap against a version taken before such a write still
+/// succeeds (the ABA problem). S3 preconditions cannot compare version IDs,
+/// so versioned buckets do not close the gap.
+///
+/// S3 preconditions only compare ETags, so `IfExists` and
+/// `IfVersionNotMatches` are checked against the current object and the write
+/// is pinned to its ETag (see `Condition::pinned`). `IfMetadataMatches` is
+/// rejected with `Error::InvalidRequest`: metadata patches keep the ETag, so
+/// the pinned write could not detect a concurrent patch.
+///
+/// Patches are conditional self-copies that replace the metadata. S3 deletes
+/// succeed whether or not the object exists, so unconditional deletes check
+/// for the object first; of two concurrent deletes, both may succeed. List
+/// results carry no metadata, since S3 does not return it.
+#[derive(Clone, Debug)]
+pub struct S3Store {
+    client: reqwest::Client,
+    credentials: Credentials,
+    region: String,
+    endpoint: String,
+    path_style: bool,
+}
+
+impl S3Store {
+    /// Create a new S3Store for an AWS region
+    pub fn new(region: impl Into<String>, credentials: Credentials) -> Self {
+        let region = region.into();
+        Self {
+            client: reqwest::Client::new(),
+            credentials,
+            endpoint: format!("https://s3.{region}.amazonaws.com"),
+            region,
+            path_style: false,
+        }
+    }
+
+    /// Create a new S3Store from the environment
+    ///
+    /// Reads credentials as `Credentials::from_env` does, the region from
+    /// `AWS_REGION` (default "us-east-1") and an optional custom endpoint from
+    /// `AWS_ENDPOINT_URL`, which also switches to path-style addressing.
+    pub fn from_env() -> Result<Self, Error> {
+        let credentials = Credentials::from_env().ok_or_else(|| Error::Unauthorized {
+            message: "AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY must be set".into(),
+            source: None,
+        })?;
+        let region = std::env::var("AWS_REGION").unwrap_or_else(|_| "us-east-1".into());
+        let store = Self::new(region, credentials);
+        Ok(match std::env::var("AWS_ENDPOINT_URL") {
+            Ok(endpoint) => store.endpoint(endpoint).path_style(true),
+            Err(_) => store,
+        })
+    }
+
+    /// Use a custom endpoint, e.g. "http://localhost:9000" for MinIO
+    pub fn endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = endpoint.into();
+        self
+    }
+
+    /// Address buckets in the path ("endpoint/bucket/key") instead of the
+    /// host name ("bucket.endpoint/key")
+    pub fn path_style(mut self, path_style: bool) -> Self {
+        self.path_style = path_style;
+        self
+    }
+
+    /// Build the URL of a bucket, or of an object if a key is given
+    fn url(&self, bucket: &str, key: Option<&str>, query: &[(&str, &str)]) -> Result<Url, Error> {
+        let mut url = Url::parse(&self.endpoint).map_err(|e| Error::InvalidRequest {
+            message: format!("invalid endpoint '{}': {e}", self.endpoint),
+        })?;
+
+        let mut path = url.path().trim_end_matches('/').to_string();
+        if self.path_style {
+            path.push('/');
+            path.push_str(&signing::uri_encode(bucket, false));
+        } else {
+            let host = format!("{bucket}.{}", url.host_str().unwrap_or_default());
+            url.set_host(Some(&host))
+                .map_err(|e| Error::InvalidRequest {
+                    message: format!("invalid bucket host '{host}': {e}"),
+                })?;
+        }
+        if let Some(key) = key {
+            path.push('/');
+            path.push_str(&signing::uri_encode(key, true));
+        }
+        if path.is_empty() {
+            path.push('/');
+        }
+        url.set_path(&path);
+
+        if !query.is_empty() {
+            let query: Vec<String> = query
+                .iter()
+                .map(|(k, v)| {
+                    format!(
+                        "{}={}",
+                        signing::uri_encode(k, false),
+                        signing::uri_encode(v, false)
+                    )
+                })
+                .collect();
+            url.set_query(Some(&query.join("&")));
+        }
+        Ok(url)
+    }
+
+    /// Sign and send a request
+    async fn send(
+        &self,
+        method: Method,
+        url: Url,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> Result<reqwest::Response, Error> {
+        let payload_hash = if body.is_empty() {
+            signing::EMPTY_PAYLOAD_HASH.to_string()
+        } else {
+            signing::payload_hash(&body)
+        };
+        let mut request = self
+            .client
+            .request(method, url)
+            .headers(headers)
+            .body(body)
+            .build()
+            .map_err(request_error)?;
+        signing::sign(
+            &mut request,
+            &self.credentials,
+            &self.region,
+            &payload_hash,
+            SystemTime::now(),
+        );
+        self.client.execute(request).await.map_err(request_error)
+    }
+
+    /// Check a patch or delete condition against the current object, returning
+    /// the ETag to pin the write to
+    async fn check_existing(&self, key: &Path, condition: &Condition) -> Result<Version, Error> {
+        let head = self
+            .head(HeadRequest { key: key.clone() })
+            .await?
+            .ok_or(Error::NotFound)?;
+        if !condition.is_satisfied_by(Some((&head.version, &head.metadata))) {
+            return Err(Error::ConditionFailed {
+                condition: condition.clone(),
+            });
+        }
+        Ok(head.version)
+    }
+}
+
+/// Reject `IfMetadataMatches`, which S3 cannot enforce (see `S3Store`)
+fn reject_metadata_condition(condition: &Option<Condition>) -> Result<(), Error> {
+    match condition {
+        Some(Condition::IfMetadataMatches(_)) => Err(Error::InvalidRequest {
+            message: "S3 cannot enforce metadata conditions, since metadata \
+                      patches keep the ETag"
+                .into(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Error document returned by S3
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ErrorBody {
+    code: String,
+}
+
+/// Map an error code and status onto the error taxonomy
+fn error_from(status: u16, body: String, retry_after: Option<Duration>) -> Error {
+    let code = quick_xml::de::from_str::<ErrorBody>(&body)
+        .map(|e| e.code)
+        .unwrap_or_default();
+
+    match (status, code.as_str()) {
+        (503, "SlowDown") => Error::RateLimited { retry_after },
+        (400, "RequestTimeout") => Error::Timeout { source: None },
+        (
+            403,
+            "InvalidAccessKeyId"
+            | "SignatureDoesNotMatch"
+            | "ExpiredToken"
+            | "RequestTimeTooSkewed",
+        ) => Error::Unauthorized {
+            message: body,
+            source: None,
+        },
+        // Conditional writes report a conflict as ConditionFailed themselves
+        (409, "ConditionalRequestConflict") => Error::Transient {
+            message: format!("status {status}: {body}"),
+            source: None,
+        },
+        _ => Error::from_status(status, body, retry_after),
+    }
+}
+
+/// Map an unexpected response status onto the error taxonomy
+async fn status_error(resp: reqwest::Response) -> Error {
+    let status = resp.status().as_u16();
+    let retry_after = retry_after(resp.headers());
+    let body = resp.text().await.unwrap_or_default();
+    error_from(status, body, retry_after)
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+/// Extract the version from the ETag header
+fn parse_etag(headers: &HeaderMap) -> Result<Version, Error> {
+    header(headers, "etag")
+        .map(Version::new)
+        .ok_or_else(|| Error::Other("missing ETag header".into()))
+}
+
+/// Extract custom metadata from x-amz-meta-* headers
+fn parse_metadata(headers: &HeaderMap) -> Metadata {
+    let mut metadata = Metadata::new();
+    for (name, value) in headers {
+        if let Some(key) = name.as_str().strip_prefix("x-amz-meta-")
+            && let Ok(v) = value.to_str()
+        {
+            metadata.insert(key, v);
+        }
+    }
+    metadata
+}
+
+/// Encode custom metadata as x-amz-meta-* headers
+fn metadata_headers(metadata: &Metadata, headers: &mut HeaderMap) -> Result<(), Error> {
+    for (key, value) in &metadata.headers {
+        let name = HeaderName::from_bytes(format!("x-amz-meta-{key}").as_bytes());
+        let value = HeaderValue::from_str(value);
+        match (name, value) {
+            (Ok(name), Ok(value)) => {
+                headers.insert(name, value);
+            }
+            _ => {
+                return Err(Error::InvalidRequest {
+                    message: format!("metadata '{key}' is not a valid header"),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+fn etag_header(version: &Version) -> Result<HeaderValue, Error> {
+    HeaderValue::from_str(version.as_str()).map_err(|_| Error::InvalidRequest {
+        message: format!("version '{}' is not a valid ETag", version.as_str()),
+    })
+}
+
+/// Result document of a copy
+#[derive(Debug, Deserialize)]
+struct CopyObjectResult {
+    #[serde(rename = "ETag")]
+    etag: String,
+}
+
+/// Result document of ListObjectsV2
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListBucketResult {
+    #[serde(default)]
+    contents: Vec<ListEntry>,
+    #[serde(default)]
+    common_prefixes: Vec<CommonPrefix>,
+    #[serde(default)]
+    is_truncated: bool,
+    next_continuation_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListEntry {
+    key: String,
+    #[serde(rename = "ETag")]
+    etag: String,
+    size: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct CommonPrefix {
+    prefix: String,
+}
+
+#[async_trait]
+impl ObjectStore for S3Store {
+    async fn get(&self, request: GetRequest) -> Result<Option<GetResponse>, Error> {
+        match self.get_stream(request).await? {
+            Some(response) => Ok(Some(response.collect().await?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_stream(&self, request: GetRequest) -> Result<Option<GetStreamResponse>, Error> {
+        let (bucket, key) = parse_path(&request.key, "bucket")?;
+        let url = self.url(bucket, Some(key), &[])?;
+
+        let mut headers = HeaderMap::new();
+        if let Some(range) = &request.range {
+            headers.insert(
+                "range",
+                HeaderValue::from_str(&range.header_value()?).unwrap(),
+            );
+        }
+        // Versions are ETags, so only the current version can be read
+        if let Some(version) = &request.version {
+            headers.insert("if-match", etag_header(version)?);
+        }
+        if let Some(version) = &request.if_version_not_matches {
+            headers.insert("if-none-match", etag_header(version)?);
+        }
+
+        let resp = self.send(Method::GET, url, headers, Bytes::new()).await?;
+
+        match resp.status().as_u16() {
+            304 => Err(Error::NotModified),
+            404 => Ok(None),
+            412 if request.version.is_some() => Ok(None),
+            416 => Err(Error::RangeNotSatisfiable),
+            status @ (200 | 206) => {
+                // Partial responses carry the total size in Content-Range
+                // ("bytes start-end/size"), full responses in Content-Length
+                let size = if status == 206 {
+                    header(resp.headers(), "content-range").and_then(|v| v.rsplit('/').next())
+                } else {
+                    header(resp.headers(), "content-length")
+                };
+                let size = size
+                    .and_then(|v| v.parse().ok())
+                    .ok_or_else(|| Error::Other("missing object size header".into()))?;
+                let version = parse_etag(resp.headers())?;
+                let metadata = parse_metadata(resp.headers());
+                let stream = resp.bytes_stream().map_err(request_error);
+
+                Ok(Some(GetStreamResponse {
+                    stream: Box::pin(stream),
+                    size,
+                    version,
+                    metadata,
+                }))
+            }
+            _ => Err(status_error(resp).await),
+        }
+    }
+
+    async fn head(&self, request: HeadRequest) -> Result<Option<HeadResponse>, Error> {
+        let (bucket, key) = parse_path(&request.key, "bucket")?;
+        let url = self.url(bucket, Some(key), &[])?;
+
+        let resp = self
+            .send(Method::HEAD, url, HeaderMap::new(), Bytes::new())
+            .await?;
+
+        match resp.status().as_u16() {
+            404 => Ok(None),
+            200 => {
+                let headers = resp.headers();
+                let size = header(headers, "content-length")
+                    .and_then(|v| v.parse().ok())
+                    .ok_or_else(|| Error::Other("missing content length".into()))?;
+                let last_modified = header(headers, "last-modified")
+                    .map(|v| {
+                        httpdate::parse_http_date(v)
+                            .map_err(|e| Error::Other(format!("invalid last modified time: {e}")))
+                    })
+                    .transpose()?;
+
+                Ok(Some(HeadResponse {
+                    version: parse_etag(headers)?,
+                    metadata: parse_metadata(headers),
+                    size,
+                    content_type: header(headers, "content-type").map(String::from),
+                    last_modified,
+                }))
+            }
+            _ => Err(status_error(resp).await),
+        }
+    }
+
+    async fn put(&self, request: PutRequest) -> Result<PutResponse, Error> {
+        let (bucket, key) = parse_path(&request.key, "bucket")?;
+        let url = self.url(bucket, Some(key), &[])?;
+
+        reject_metadata_condition(&request.condition)?;
+
+        let mut headers = HeaderMap::new();
+        if let Some(metadata) = &request.metadata {
+            metadata_headers(metadata, &mut headers)?;
+        }
+        // S3 preconditions only compare ETags, so other conditions are checked
+        // against the current object and the put is pinned to its state
+        let condition = match &request.condition {
+            None => None,
+            Some(Condition::IfAbsent) => Some(PinnedCondition::IfAbsent),
+            Some(Condition::IfVersionMatches(v)) => {
+                Some(PinnedCondition::IfVersionMatches(v.clone()))
+            }
+            Some(condition) => {
+                let head = self
+                    .head(HeadRequest {
+                        key: request.key.clone(),
+                    })
+                    .await?;
+                Some(condition.pinned(head.as_ref().map(|h| (&h.version, &h.metadata)))?)
+            }
+        };
+        match &condition {
+            Some(PinnedCondition::IfAbsent) => {
+                headers.insert("if-none-match", HeaderValue::from_static("*"));
+            }
+            Some(PinnedCondition::IfVersionMatches(v)) => {
+                headers.insert("if-match", etag_header(v)?);
+            }
+            None => {}
+        }
+
+        let resp = self.send(Method::PUT, url, headers, request.value).await?;
+
+        match resp.status().as_u16() {
+            200 => Ok(PutResponse {
+                version: parse_etag(resp.headers())?,
+            }),
+            // If-Match on a missing object fails with 404, and a conditional
+            // write racing another one for the key with 409; either way the
+            // condition did not hold for this write
+            404 | 409 | 412 if request.condition.is_some() => Err(Error::ConditionFailed {
+                condition: request.condition.unwrap(),
+            }),
+            _ => Err(status_error(resp).await),
+        }
+    }
+
+    async fn patch(&self, request: PatchRequest) -> Result<PatchResponse, Error> {
+        let (bucket, key) = parse_path(&request.key, "bucket")?;
+        reject_metadata_condition(&request.condition)?;
+
+        let mut headers = HeaderMap::new();
+        match &request.condition {
+            Some(Condition::IfVersionMatches(v)) => {
+                headers.insert("x-amz-copy-source-if-match", etag_header(v)?);
+            }
+            Some(condition) => {
+                // Other conditions are checked against the current object, and
+                // the copy is pinned to the ETag that was checked
+                let version = self.check_existing(&request.key, condition).await?;
+                headers.insert("x-amz-copy-source-if-match", etag_header(&version)?);
+            }
+            None => {}
+        }
+
+        let url = self.url(bucket, Some(key), &[])?;
+        let source = format!(
+            "/{}/{}",
+            signing::uri_encode(bucket, false),
+            signing::uri_encode(key, true)
+        );
+        headers.insert(
+            "x-amz-copy-source",
+            HeaderValue::from_str(&source).expect("encoded copy source"),
+        );
+        headers.insert(
+            "x-amz-metadata-directive",
+            HeaderValue::from_static("REPLACE"),
+        );
+        metadata_headers(&request.metadata, &mut headers)?;
+
+        let resp = self.send(Method::PUT, url, headers, Bytes::new()).await?;
+
+        match resp.status().as_u16() {
+            200 => {
+                // Copies can fail after the 200 status was sent, in which case
+                // the body is an error document
+                let body = resp.text().await.map_err(request_error)?;
+                match quick_xml::de::from_str::<CopyObjectResult>(&body) {
+                    Ok(result) => Ok(PatchResponse {
+                        version: Version::new(result.etag),
+                    }),
+                    Err(_) => Err(error_from(500, body, None)),
+                }
+            }
+            404 => Err(Error::NotFound),
+            409 | 412 if request.condition.is_some() => Err(Error::ConditionFailed {
+                condition: request.condition.unwrap(),
+            }),
+            _ => Err(status_error(resp).await),
+        }
+    }
+
+    async fn delete(&self, request: DeleteRequest) -> Result<DeleteResponse, Error> {
+        let (bucket, key) = parse_path(&request.key, "bucket")?;
+        reject_metadata_condition(&request.condition)?;
+
+        let mut headers = HeaderMap::new();
+        match &request.condition {
+            Some(Condition::IfVersionMatches(v)) => {
+                headers.insert("if-match", etag_header(v)?);
+            }
+            Some(condition) => {
+                let version = self.check_existing(&request.key, condition).await?;
+                headers.insert("if-match", etag_header(&version)?);
+            }
+            None => {
+                // S3 reports success for missing objects, so check first. The
+                // check is not atomic with the delete: if another client
+                // deletes the object in between, both deletes succeed
+                let head = self
+                    .head(HeadRequest {
+                        key: request.key.clone(),
+                    })
+                    .await?;
+                if head.is_none() {
+                    return Err(Error::NotFound);
+                }
+            }
+        }
+
+        let url = self.url(bucket, Some(key), &[])?;
+        let resp = self
+            .send(Method::DELETE, url, headers, Bytes::new())
+            .await?;
+
+        match resp.status().as_u16() {
+            200 | 204 => Ok(DeleteResponse),
+            404 => Err(Error::NotFound),
+            409 | 412 if request.condition.is_some() => Err(Error::ConditionFailed {
+                condition: request.condition.unwrap(),
+            }),
+            _ => Err(status_error(resp).await),
+        }
+    }
+
+    async fn list(&self, request: ListRequest) -> Result<ListResponse, Error> {
+        let (bucket, prefix) = parse_prefix(&request.prefix, "bucket")?;
+
+        let mut delimiter = [0; 4];
+        let max_results = request.max_results.map(|n| n.to_string());
+        let mut query = vec![("list-type", "2"), ("prefix", prefix)];
+        if let Some(d) = request.delimiter {
+            query.push(("delimiter", d.encode_utf8(&mut delimiter)));
+        }
+        if let Some(start_after) = &request.start_after {
+            let name = start_after
+                .strip_prefix(bucket)
+                .and_then(|s| s.strip_prefix('/'))
+                .ok_or_else(|| Error::InvalidRequest {
+                    message: format!("start_after '{start_after}' is not in bucket"),
+                })?;
+            query.push(("start-after", name));
+        }
+        if let Some(token) = &request.page_token {
+            query.push(("continuation-token", token));
+        }
+        if let Some(max_results) = &max_results {
+            query.push(("max-keys", max_results));
+        }
+
+        let url = self.url(bucket, None, &query)?;
+        let resp = self
+            .send(Method::GET, url, HeaderMap::new(), Bytes::new())
+            .await?;
+
+        match resp.status().as_u16() {
+            200 => {
+                let body = resp.text().await.map_err(request_error)?;
+                let result: ListBucketResult = quick_xml::de::from_str(&body)
+                    .map_err(|e| Error::Other(format!("invalid list response: {e}")))?;
+
+                let objects = result
+                    .contents
+                    .into_iter()
+                    .map(|entry| {
+                        let key = Path::new(format!("{bucket}/{}", entry.key)).map_err(|e| {
+                            Error::Other(format!("invalid object name '{}': {e}", entry.key))
+                        })?;
+                        Ok(ObjectSummary {
+                            key,
+                            version: Version::new(entry.etag),
+                            size: entry.size,
+                            metadata: Metadata::new(),
+                        })
+                    })
+                    .collect::<Result<_, Error>>()?;
+                let common_prefixes = result
+                    .common_prefixes
+                    .into_iter()
+                    .map(|p| format!("{bucket}/{}", p.prefix))
+                    .collect();
+
+                Ok(ListResponse {
+                    objects,
+                    common_prefixes,
+                    next_page_token: result
+                        .next_continuation_token
+                        .filter(|_| result.is_truncated),
+                })
+            }
+            _ => Err(status_error(resp).await),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use kanso_backends_test_suite::{Capabilities, run_compliance_tests_with};
+
+    use super::*;
+
+    fn credentials() -> Credentials {
+        Credentials::new("access", "secret")
+    }
+
+    #[test]
+    fn test_addressing() {
+        let store = S3Store::new("eu-west-1", credentials());
+        let url = store.url("bucket", Some("a/b c+d"), &[]).unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://bucket.s3.eu-west-1.amazonaws.com/a/b%20c%2Bd"
+        );
+
+        let store = store.endpoint("http://localhost:9000").path_style(true);
+        let url = store
+            .url("bucket", None, &[("list-type", "2"), ("prefix", "a/")])
+            .unwrap();
+        assert_eq!(
+            url.as_str(),
+            "http://localhost:9000/bucket?list-type=2&prefix=a%2F"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compliance() {
+        let endpoint = mock::start(credentials(), "us-east-1").await;
+        let store = S3Store::new("us-east-1", credentials())
+            .endpoint(endpoint)
+            .path_style(true);
+        let client: kanso_client::Client = Arc::new(store);
+        // Content-hash ETags do not change on metadata patches, which also
+        // leaves metadata conditions unenforceable
+        let capabilities = Capabilities {
+            distinct_versions: false,
+            metadata_conditions: false,
+        };
+        run_compliance_tests_with(&client, "bucket/", capabilities).await;
+    }
+
+    #[tokio::test]
+    async fn test_rejects_bad_signatures() {
+        let endpoint = mock::start(credentials(), "us-east-1").await;
+        let store = S3Store::new("us-east-1", Credentials::new("access", "wrong"))
+            .endpoint(endpoint)
+            .path_style(true);
+        let result = store.get(GetRequest::new("bucket/key").unwrap()).await;
+        assert!(matches!(result, Err(Error::Unauthorized { .. })));
+    }
+}
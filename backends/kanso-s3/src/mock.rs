@@ -0,0 +1,379 @@
+//! Minimal in-process stand-in for an S3-compatible server
+//!
+//! Supports path-style addressing of objects (GET, HEAD, PUT, self-copy,
+//! DELETE) and ListObjectsV2, verifies SigV4 signatures and payload hashes,
+//! and honors the conditional headers kanso sends. As on AWS, ETags are
+//! content hashes (a truncated SHA-256 rather than MD5), so rewriting
+//! identical content or copying an object keeps its ETag.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use axum::Router;
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{HeaderMap, Method, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use kanso_client::GetRange;
+use sha2::{Digest, Sha256};
+
+use crate::signing::{self, Credentials};
+
+struct Object {
+    data: Bytes,
+    etag: String,
+    metadata: Vec<(String, String)>,
+    last_modified: SystemTime,
+}
+
+struct Mock {
+    credentials: Credentials,
+    region: String,
+    /// Objects by "bucket/key"
+    objects: Mutex<BTreeMap<String, Object>>,
+}
+
+/// Start a mock server and return its endpoint
+pub(crate) async fn start(credentials: Credentials, region: &str) -> String {
+    let mock = Arc::new(Mock {
+        credentials,
+        region: region.into(),
+        objects: Mutex::new(BTreeMap::new()),
+    });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+    let app = Router::new().fallback(handle).with_state(mock);
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    endpoint
+}
+
+fn error(status: StatusCode, code: &str) -> Response {
+    let body = format!("<Error><Code>{code}</Code><Message>{code}</Message></Error>");
+    (status, body).into_response()
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+/// Quoted content hash of an object, as AWS derives ETags from content
+fn etag(data: &[u8]) -> String {
+    format!("\"{}\"", hex::encode(&Sha256::digest(data)[..16]))
+}
+
+fn decode(s: &str) -> String {
+    urlencoding::decode(s).unwrap().into_owned()
+}
+
+/// Parse an HTTP Range header
+fn parse_range(value: &str) -> Option<GetRange> {
+    let (start, end) = value.strip_prefix("bytes=")?.split_once('-')?;
+    match (start, end) {
+        ("", suffix) => Some(GetRange::Suffix(suffix.parse().ok()?)),
+        (start, "") => Some(GetRange::From(start.parse().ok()?)),
+        (start, end) => {
+            let (start, end): (u64, u64) = (start.parse().ok()?, end.parse().ok()?);
+            Some(GetRange::Bounded {
+                offset: start,
+                length: end.checked_sub(start)? + 1,
+            })
+        }
+    }
+}
+
+impl Mock {
+    /// Recompute the signature of a request and compare it to the one sent
+    fn verify(&self, method: &Method, uri: &Uri, headers: &HeaderMap, body: &Bytes) -> bool {
+        let Some(authorization) = header(headers, "authorization") else {
+            return false;
+        };
+        let Some(signed) = authorization
+            .split(", ")
+            .find_map(|part| part.strip_prefix("SignedHeaders="))
+        else {
+            return false;
+        };
+        let (Some(host), Some(date), Some(hash)) = (
+            header(headers, "host"),
+            header(headers, "x-amz-date"),
+            header(headers, "x-amz-content-sha256"),
+        ) else {
+            return false;
+        };
+        if hash != signing::payload_hash(body) || date.len() != 16 {
+            return false;
+        }
+        let Ok(now) = humantime::parse_rfc3339(&format!(
+            "{}-{}-{}T{}:{}:{}Z",
+            &date[0..4],
+            &date[4..6],
+            &date[6..8],
+            &date[9..11],
+            &date[11..13],
+            &date[13..15]
+        )) else {
+            return false;
+        };
+
+        let mut request = reqwest::Request::new(
+            method.clone(),
+            format!("http://{host}{uri}").parse().unwrap(),
+        );
+        for name in signed.split(';') {
+            for value in headers.get_all(name) {
+                request.headers_mut().append(
+                    reqwest::header::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                    value.clone(),
+                );
+            }
+        }
+        signing::sign(&mut request, &self.credentials, &self.region, hash, now);
+        request.headers()["authorization"] == authorization
+    }
+
+    fn get(&self, name: &str, headers: &HeaderMap) -> Response {
+        let objects = self.objects.lock().unwrap();
+        let Some(object) = objects.get(name) else {
+            return error(StatusCode::NOT_FOUND, "NoSuchKey");
+        };
+        if header(headers, "if-match").is_some_and(|expected| expected != object.etag) {
+            return error(StatusCode::PRECONDITION_FAILED, "PreconditionFailed");
+        }
+        if header(headers, "if-none-match") == Some(object.etag.as_str()) {
+            return StatusCode::NOT_MODIFIED.into_response();
+        }
+        let size = object.data.len() as u64;
+
+        let mut response = match header(headers, "range") {
+            Some(range) => {
+                let Some(range) = parse_range(range).and_then(|r| r.resolve(size).ok()) else {
+                    return error(StatusCode::RANGE_NOT_SATISFIABLE, "InvalidRange");
+                };
+                let content_range = format!("bytes {}-{}/{size}", range.start, range.end - 1);
+                let data = object.data.slice(range.start as usize..range.end as usize);
+                let mut response = (StatusCode::PARTIAL_CONTENT, data).into_response();
+                response
+                    .headers_mut()
+                    .insert("content-range", content_range.parse().unwrap());
+                response
+            }
+            None => object.data.clone().into_response(),
+        };
+
+        let response_headers = response.headers_mut();
+        response_headers.insert("etag", object.etag.parse().unwrap());
+        response_headers.insert(
+            "last-modified",
+            httpdate::fmt_http_date(object.last_modified)
+                .parse()
+                .unwrap(),
+        );
+        for (key, value) in &object.metadata {
+            response_headers.insert(
+                reqwest::header::HeaderName::from_bytes(format!("x-amz-meta-{key}").as_bytes())
+                    .unwrap(),
+                value.parse().unwrap(),
+            );
+        }
+        response
+    }
+
+    fn put(&self, name: String, headers: &HeaderMap, body: Bytes) -> Response {
+        let mut objects = self.objects.lock().unwrap();
+        let current = objects.get(&name);
+        if header(headers, "if-none-match") == Some("*") && current.is_some() {
+            return error(StatusCode::PRECONDITION_FAILED, "PreconditionFailed");
+        }
+        if let Some(expected) = header(headers, "if-match") {
+            match current {
+                None => return error(StatusCode::NOT_FOUND, "NoSuchKey"),
+                Some(current) if current.etag != expected => {
+                    return error(StatusCode::PRECONDITION_FAILED, "PreconditionFailed");
+                }
+                Some(_) => {}
+            }
+        }
+
+        let etag = etag(&body);
+        objects.insert(
+            name,
+            Object {
+                data: body,
+                etag: etag.clone(),
+                metadata: metadata(headers),
+                last_modified: SystemTime::now(),
+            },
+        );
+        (StatusCode::OK, [("etag", etag)]).into_response()
+    }
+
+    fn copy(&self, name: String, source: &str, headers: &HeaderMap) -> Response {
+        let mut objects = self.objects.lock().unwrap();
+        let Some(source) = objects.get(&decode(source.trim_start_matches('/'))) else {
+            return error(StatusCode::NOT_FOUND, "NoSuchKey");
+        };
+        if let Some(expected) = header(headers, "x-amz-copy-source-if-match")
+            && source.etag != expected
+        {
+            return error(StatusCode::PRECONDITION_FAILED, "PreconditionFailed");
+        }
+
+        let metadata = match header(headers, "x-amz-metadata-directive") {
+            Some("REPLACE") => metadata(headers),
+            _ => source.metadata.clone(),
+        };
+        let etag = source.etag.clone();
+        let object = Object {
+            data: source.data.clone(),
+            etag: etag.clone(),
+            metadata,
+            last_modified: SystemTime::now(),
+        };
+        objects.insert(name, object);
+        let body = format!("<CopyObjectResult><ETag>{etag}</ETag></CopyObjectResult>");
+        (StatusCode::OK, body).into_response()
+    }
+
+    fn delete(&self, name: &str, headers: &HeaderMap) -> Response {
+        let mut objects = self.objects.lock().unwrap();
+        if let Some(expected) = header(headers, "if-match") {
+            match objects.get(name) {
+                None => return error(StatusCode::NOT_FOUND, "NoSuchKey"),
+                Some(current) if current.etag != expected => {
+                    return error(StatusCode::PRECONDITION_FAILED, "PreconditionFailed");
+                }
+                Some(_) => {}
+            }
+        }
+        objects.remove(name);
+        StatusCode::NO_CONTENT.into_response()
+    }
+
+    fn list(&self, bucket: &str, query: &HashMap<String, String>) -> Response {
+        let param = |name: &str| query.get(name).map(String::as_str).unwrap_or_default();
+        let prefix = param("prefix");
+        let delimiter = param("delimiter");
+        let start_after = param("start-after");
+        let token = param("continuation-token");
+        let max_keys: usize = query
+            .get("max-keys")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1000);
+
+        let objects = self.objects.lock().unwrap();
+        let mut body = String::from("<ListBucketResult>");
+        let mut count = 0;
+        let mut last = None;
+        let mut truncated = false;
+        for (name, object) in objects.range(format!("{bucket}/")..) {
+            let Some(key) = name.strip_prefix(bucket).and_then(|k| k.strip_prefix('/')) else {
+                break;
+            };
+            // The token is the last key or common prefix returned
+            if !key.starts_with(prefix)
+                || key <= start_after
+                || key <= token
+                || (!token.is_empty() && token.ends_with(delimiter) && key.starts_with(token))
+            {
+                continue;
+            }
+            let common_prefix = match delimiter {
+                "" => None,
+                d => key[prefix.len()..]
+                    .find(d)
+                    .map(|pos| &key[..prefix.len() + pos + d.len()]),
+            };
+            if common_prefix.is_some() && common_prefix == last {
+                continue;
+            }
+            if count == max_keys {
+                truncated = true;
+                break;
+            }
+            count += 1;
+            match common_prefix {
+                Some(p) => {
+                    body.push_str(&format!(
+                        "<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>",
+                        xml_escape(p)
+                    ));
+                    last = Some(p);
+                }
+                None => {
+                    body.push_str(&format!(
+                        "<Contents><Key>{}</Key><ETag>{}</ETag><Size>{}</Size></Contents>",
+                        xml_escape(key),
+                        object.etag,
+                        object.data.len()
+                    ));
+                    last = Some(key);
+                }
+            }
+        }
+        body.push_str(&format!("<IsTruncated>{truncated}</IsTruncated>"));
+        if truncated && let Some(last) = last {
+            body.push_str(&format!(
+                "<NextContinuationToken>{}</NextContinuationToken>",
+                xml_escape(last)
+            ));
+        }
+        body.push_str("</ListBucketResult>");
+        body.into_response()
+    }
+}
+
+fn metadata(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .filter_map(|(name, value)| {
+            let key = name.as_str().strip_prefix("x-amz-meta-")?;
+            Some((key.to_string(), value.to_str().ok()?.to_string()))
+        })
+        .collect()
+}
+
+async fn handle(
+    State(mock): State<Arc<Mock>>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    if !mock.verify(&method, &uri, &headers, &body) {
+        return error(StatusCode::FORBIDDEN, "SignatureDoesNotMatch");
+    }
+
+    let path = uri.path().trim_start_matches('/');
+    let (bucket, key) = match path.split_once('/') {
+        Some((bucket, key)) if !key.is_empty() => (bucket, Some(decode(key))),
+        Some((bucket, _)) => (bucket, None),
+        None => (path, None),
+    };
+    let query: HashMap<String, String> = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(k, v)| (decode(k), decode(v)))
+        .collect();
+
+    match (method, key) {
+        (Method::GET, None) if query.get("list-type").map(String::as_str) == Some("2") => {
+            mock.list(bucket, &query)
+        }
+        (Method::GET | Method::HEAD, Some(key)) => mock.get(&format!("{bucket}/{key}"), &headers),
+        (Method::PUT, Some(key)) => match header(&headers, "x-amz-copy-source") {
+            Some(source) => mock.copy(format!("{bucket}/{key}"), source, &headers),
+            None => mock.put(format!("{bucket}/{key}"), &headers, body),
+        },
+        (Method::DELETE, Some(key)) => mock.delete(&format!("{bucket}/{key}"), &headers),
+        _ => error(StatusCode::METHOD_NOT_ALLOWED, "MethodNotAllowed"),
+    }
+}
@@ -0,0 +1,465 @@
+use std::collections::HashMap;
+use std::path::Path as FsPath;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use kanso_client::{
+    Condition, DeleteRequest, DeleteResponse, Error, GetRequest, GetResponse, HeadRequest,
+    HeadResponse, ListRequest, ListResponse, Metadata, ObjectStore, ObjectSummary, PatchRequest,
+    PatchResponse, Path, PutRequest, PutResponse, Version,
+};
+use rusqlite::{Connection, OptionalExtension, Transaction, TransactionBehavior, params};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS objects (
+        key TEXT PRIMARY KEY NOT NULL,
+        value BLOB NOT NULL,
+        version INTEGER NOT NULL,
+        metadata TEXT NOT NULL,
+        last_modified INTEGER NOT NULL
+    ) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS version_sequence (
+        id INTEGER PRIMARY KEY CHECK (id = 0),
+        version INTEGER NOT NULL
+    );
+    INSERT OR IGNORE INTO version_sequence (id, version) VALUES (0, 0);
+";
+
+/// How long a write waits for another connection's transaction to finish
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// SQLite implementation of ObjectStore
+///
+/// Objects are rows keyed (and clustered) by their key, so lists are range
+/// scans of the primary key index. Versions come from a single sequence
+/// shared by all keys, so they increase monotonically and are never reused,
+/// even after a key is deleted and recreated.
+///
+/// Writes run in immediate transactions, which take the database write lock
+/// before reading the current version: conditions hold across connections
+/// and processes sharing the database file.
+#[derive(Clone)]
+pub struct SqliteStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    /// Open (or create) a database file
+    pub fn open(path: impl AsRef<FsPath>) -> Result<Self, Error> {
+        Self::init(Connection::open(path).map_err(sql_error)?, true)
+    }
+
+    /// Create a private in-memory database
+    pub fn open_in_memory() -> Result<Self, Error> {
+        Self::init(Connection::open_in_memory().map_err(sql_error)?, false)
+    }
+
+    fn init(conn: Connection, wal: bool) -> Result<Self, Error> {
+        // Set first, since switching to WAL needs a lock that another
+        // connection may be holding
+        conn.busy_timeout(BUSY_TIMEOUT).map_err(sql_error)?;
+        if wal {
+            // WAL lets readers proceed while a write is in progress
+            conn.query_row("PRAGMA journal_mode = WAL", [], |_| Ok(()))
+                .map_err(sql_error)?;
+        }
+        conn.execute_batch(SCHEMA).map_err(sql_error)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Run blocking database work off the async runtime
+    async fn blocking<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Connection) -> Result<T, Error> + Send + 'static,
+    ) -> Result<T, Error> {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || f(&mut conn.lock().unwrap()))
+            .await
+            .map_err(|e| Error::Other(format!("database task failed: {e}")))?
+    }
+}
+
+impl std::fmt::Debug for SqliteStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SqliteStore").finish_non_exhaustive()
+    }
+}
+
+fn sql_error(e: rusqlite::Error) -> Error {
+    match e.sqlite_error_code() {
+        Some(rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked) => {
+            Error::Transient {
+                message: format!("database busy: {e}"),
+                source: Some(e.into()),
+            }
+        }
+        _ => Error::Other(format!("sqlite error: {e}")),
+    }
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64)
+}
+
+fn encode_metadata(metadata: &Metadata) -> Result<String, Error> {
+    serde_json::to_string(&metadata.headers)
+        .map_err(|e| Error::Other(format!("failed to encode metadata: {e}")))
+}
+
+fn decode_metadata(json: &str) -> Result<Metadata, Error> {
+    let headers: HashMap<String, String> =
+        serde_json::from_str(json).map_err(|e| Error::Other(format!("corrupt metadata: {e}")))?;
+    Ok(Metadata { headers })
+}
+
+fn begin_write(conn: &mut Connection) -> Result<Transaction<'_>, Error> {
+    conn.transaction_with_behavior(TransactionBehavior::Immediate)
+        .map_err(sql_error)
+}
+
+/// Current version and metadata of a key, if it exists
+fn current_state(tx: &Transaction, key: &str) -> Result<Option<(Version, Metadata)>, Error> {
+    let row = tx
+        .query_row(
+            "SELECT version, metadata FROM objects WHERE key = ?1",
+            [key],
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)),
+        )
+        .optional()
+        .map_err(sql_error)?;
+    row.map(|(version, metadata)| {
+        Ok((
+            Version::new(version.to_string()),
+            decode_metadata(&metadata)?,
+        ))
+    })
+    .transpose()
+}
+
+fn next_version(tx: &Transaction) -> Result<i64, Error> {
+    tx.query_row(
+        "UPDATE version_sequence SET version = version + 1 WHERE id = 0 RETURNING version",
+        [],
+        |row| row.get(0),
+    )
+    .map_err(sql_error)
+}
+
+fn check_condition(
+    condition: Option<&Condition>,
+    current: Option<&(Version, Metadata)>,
+) -> Result<(), Error> {
+    match condition {
+        Some(condition)
+            if !condition
+                .is_satisfied_by(current.map(|(version, metadata)| (version, metadata))) =>
+        {
+            Err(Error::ConditionFailed {
+                condition: condition.clone(),
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+#[async_trait]
+impl ObjectStore for SqliteStore {
+    async fn get(&self, request: GetRequest) -> Result<Option<GetResponse>, Error> {
+        self.blocking(move |conn| {
+            // Both reads see the same snapshot
+            let tx = conn.transaction().map_err(sql_error)?;
+            let row = tx
+                .query_row(
+                    "SELECT length(value), version, metadata FROM objects WHERE key = ?1",
+                    [request.key.as_str()],
+                    |row| {
+                        Ok((
+                            row.get::<_, i64>(0)?,
+                            row.get::<_, i64>(1)?,
+                            row.get::<_, String>(2)?,
+                        ))
+                    },
+                )
+                .optional()
+                .map_err(sql_error)?;
+            let Some((size, version, metadata)) = row else {
+                return Ok(None);
+            };
+            // Only the current version is retained
+            if request
+                .version
+                .as_ref()
+                .is_some_and(|v| v.as_str() != version.to_string())
+            {
+                return Ok(None);
+            }
+            if request
+                .if_version_not_matches
+                .as_ref()
+                .is_some_and(|v| v.as_str() == version.to_string())
+            {
+                return Err(Error::NotModified);
+            }
+            let size = size as u64;
+
+            let value: Vec<u8> = match request.range {
+                Some(range) => {
+                    let range = range.resolve(size)?;
+                    tx.query_row(
+                        "SELECT substr(value, ?2, ?3) FROM objects WHERE key = ?1",
+                        params![
+                            request.key.as_str(),
+                            range.start as i64 + 1,
+                            (range.end - range.start) as i64
+                        ],
+                        |row| row.get(0),
+                    )
+                    .map_err(sql_error)?
+                }
+                None => tx
+                    .query_row(
+                        "SELECT value FROM objects WHERE key = ?1",
+                        [request.key.as_str()],
+                        |row| row.get(0),
+                    )
+                    .map_err(sql_error)?,
+            };
+
+            Ok(Some(GetResponse {
+                value: Bytes::from(value),
+                size,
+                version: Version::new(version.to_string()),
+                metadata: decode_metadata(&metadata)?,
+            }))
+        })
+        .await
+    }
+
+    async fn head(&self, request: HeadRequest) -> Result<Option<HeadResponse>, Error> {
+        self.blocking(move |conn| {
+            let row = conn
+                .query_row(
+                    "SELECT length(value), version, metadata, last_modified FROM objects \
+                     WHERE key = ?1",
+                    [request.key.as_str()],
+                    |row| {
+                        Ok((
+                            row.get::<_, i64>(0)?,
+                            row.get::<_, i64>(1)?,
+                            row.get::<_, String>(2)?,
+                            row.get::<_, i64>(3)?,
+                        ))
+                    },
+                )
+                .optional()
+                .map_err(sql_error)?;
+            let Some((size, version, metadata, last_modified)) = row else {
+                return Ok(None);
+            };
+
+            Ok(Some(HeadResponse {
+                version: Version::new(version.to_string()),
+                metadata: decode_metadata(&metadata)?,
+                size: size as u64,
+                content_type: None,
+                last_modified: Some(UNIX_EPOCH + Duration::from_millis(last_modified as u64)),
+            }))
+        })
+        .await
+    }
+
+    async fn put(&self, request: PutRequest) -> Result<PutResponse, Error> {
+        self.blocking(move |conn| {
+            let tx = begin_write(conn)?;
+            let current = current_state(&tx, request.key.as_str())?;
+            check_condition(request.condition.as_ref(), current.as_ref())?;
+
+            let version = next_version(&tx)?;
+            let metadata = encode_metadata(&request.metadata.unwrap_or_default())?;
+            tx.execute(
+                "INSERT INTO objects (key, value, version, metadata, last_modified) \
+                 VALUES (?1, ?2, ?3, ?4, ?5) \
+                 ON CONFLICT (key) DO UPDATE SET value = excluded.value, \
+                 version = excluded.version, metadata = excluded.metadata, \
+                 last_modified = excluded.last_modified",
+                params![
+                    request.key.as_str(),
+                    &request.value[..],
+                    version,
+                    metadata,
+                    now_millis()
+                ],
+            )
+            .map_err(sql_error)?;
+            tx.commit().map_err(sql_error)?;
+
+            Ok(PutResponse {
+                version: Version::new(version.to_string()),
+            })
+        })
+        .await
+    }
+
+    async fn patch(&self, request: PatchRequest) -> Result<PatchResponse, Error> {
+        self.blocking(move |conn| {
+            let tx = begin_write(conn)?;
+            let current = current_state(&tx, request.key.as_str())?;
+            if current.is_none() {
+                return Err(Error::NotFound);
+            }
+            check_condition(request.condition.as_ref(), current.as_ref())?;
+
+            let version = next_version(&tx)?;
+            tx.execute(
+                "UPDATE objects SET version = ?2, metadata = ?3, last_modified = ?4 \
+                 WHERE key = ?1",
+                params![
+                    request.key.as_str(),
+                    version,
+                    encode_metadata(&request.metadata)?,
+                    now_millis()
+                ],
+            )
+            .map_err(sql_error)?;
+            tx.commit().map_err(sql_error)?;
+
+            Ok(PatchResponse {
+                version: Version::new(version.to_string()),
+            })
+        })
+        .await
+    }
+
+    async fn delete(&self, request: DeleteRequest) -> Result<DeleteResponse, Error> {
+        self.blocking(move |conn| {
+            let tx = begin_write(conn)?;
+            let current = current_state(&tx, request.key.as_str())?;
+            if current.is_none() {
+                return Err(Error::NotFound);
+            }
+            check_condition(request.condition.as_ref(), current.as_ref())?;
+
+            tx.execute("DELETE FROM objects WHERE key = ?1", [request.key.as_str()])
+                .map_err(sql_error)?;
+            tx.commit().map_err(sql_error)?;
+            Ok(DeleteResponse)
+        })
+        .await
+    }
+
+    async fn list(&self, request: ListRequest) -> Result<ListResponse, Error> {
+        self.blocking(move |conn| {
+            // Seek past earlier pages rather than scanning them again
+            let start = request.paginate_from();
+            let mut stmt = conn
+                .prepare(
+                    "SELECT key, version, length(value), metadata FROM objects \
+                     WHERE key >= ?1 ORDER BY key",
+                )
+                .map_err(sql_error)?;
+            let mut rows = stmt.query([start]).map_err(sql_error)?;
+
+            // Rows are read lazily, so the scan stops once the page is full
+            let mut failure = None;
+            let entries = std::iter::from_fn(|| {
+                let entry = rows.next().and_then(|row| match row {
+                    Some(row) => Ok(Some((
+                        row.get::<_, String>(0)?,
+                        (
+                            row.get::<_, i64>(1)?,
+                            row.get::<_, i64>(2)?,
+                            row.get::<_, String>(3)?,
+                        ),
+                    ))),
+                    None => Ok(None),
+                });
+                entry.unwrap_or_else(|e| {
+                    failure = Some(e);
+                    None
+                })
+            });
+
+            let response = request.paginate(entries, |key, (version, size, metadata)| {
+                Ok(Some(ObjectSummary {
+                    key: Path::new(key)
+                        .map_err(|e| Error::Other(format!("invalid key '{key}': {e}")))?,
+                    version: Version::new(version.to_string()),
+                    size: size as u64,
+                    metadata: decode_metadata(&metadata)?,
+                }))
+            })?;
+            match failure {
+                Some(e) => Err(sql_error(e)),
+                None => Ok(response),
+            }
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_compliance() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SqliteStore::open(dir.path().join("kanso.db")).unwrap();
+        let client: kanso_client::Client = Arc::new(store);
+        kanso_backends_test_suite::run_compliance_tests(&client, "").await;
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_connections() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("kanso.db");
+
+        // Racing creates through separate connections: exactly one wins
+        let mut tasks = Vec::new();
+        for _ in 0..8 {
+            let store: kanso_client::Client = Arc::new(SqliteStore::open(&path).unwrap());
+            tasks.push(tokio::spawn(async move {
+                PutRequest::new("key", Bytes::from("v"))
+                    .unwrap()
+                    .if_absent()
+                    .execute(&store)
+                    .await
+            }));
+        }
+        let mut created = 0;
+        for task in tasks {
+            match task.await.unwrap() {
+                Ok(_) => created += 1,
+                Err(Error::ConditionFailed { .. }) => {}
+                Err(e) => panic!("unexpected error: {e}"),
+            }
+        }
+        assert_eq!(created, 1);
+
+        // Versions are never reused, even after a delete
+        let store: kanso_client::Client = Arc::new(SqliteStore::open(&path).unwrap());
+        let head = HeadRequest::new("key").unwrap().execute(&store).await;
+        let old: i64 = head.unwrap().unwrap().version.as_str().parse().unwrap();
+        DeleteRequest::new("key")
+            .unwrap()
+            .execute(&store)
+            .await
+            .unwrap();
+        let put = PutRequest::new("key", Bytes::from("v")).unwrap();
+        let new: i64 = put
+            .execute(&store)
+            .await
+            .unwrap()
+            .version
+            .as_str()
+            .parse()
+            .unwrap();
+        assert!(new > old);
+    }
+}
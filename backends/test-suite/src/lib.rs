@@ -1,14 +1,53 @@
 use bytes::Bytes;
-use kanso_client::{Client, Condition, Error, GetRequest, Metadata, PatchRequest, PutRequest};
+use futures::TryStreamExt;
+use futures::future::join_all;
+use kanso_client::{
+    Client, Condition, CopyRequest, DeleteRequest, Error, GetRange, GetRequest, HeadRequest,
+    ListRequest, ListResponse, Metadata, PatchRequest, Path, PathPrefix, PutRequest,
+    PutStreamRequest, RenameRequest, Version,
+};
+
+/// Parts of the ObjectStore contract that a backend may not provide
+///
+/// Backends lacking one are not fully compliant; the tests that depend on it
+/// are skipped, and the backend documents the gap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Every write produces a new version, including metadata patches and
+    /// rewrites of identical content
+    pub distinct_versions: bool,
+    /// `IfMetadataMatches` conditions are enforced; backends without them
+    /// reject such writes with `Error::InvalidRequest`
+    pub metadata_conditions: bool,
+}
+
+impl Capabilities {
+    /// The full ObjectStore contract
+    pub const FULL: Self = Self {
+        distinct_versions: true,
+        metadata_conditions: true,
+    };
+}
 
 /// Run compliance tests against an ObjectStore implementation.
 ///
-/// The `path_prefix` is prepended to all keys, allowing backends to use
-/// isolated paths (e.g., "bucket/" for GCS).
+/// All keys are placed below `path_prefix` (a `PathPrefix`, so it may be
+/// empty), allowing backends to use isolated paths (e.g., "bucket/" for GCS).
 pub async fn run_compliance_tests(client: &Client, path_prefix: &str) {
-    let key = format!("{path_prefix}test/key");
+    run_compliance_tests_with(client, path_prefix, Capabilities::FULL).await
+}
 
-    // Get non-existent returns None
+/// Run compliance tests against a backend lacking some capabilities, skipping
+/// the tests that depend on them
+pub async fn run_compliance_tests_with(
+    client: &Client,
+    path_prefix: &str,
+    capabilities: Capabilities,
+) {
+    let prefix = PathPrefix::new(path_prefix).expect("path prefix must be valid");
+    let key = prefix.join("test/key").unwrap();
+
+    // Get and head non-existent return None
     assert!(
         GetRequest::new(&key)
             .unwrap()
@@ -17,6 +56,14 @@ pub async fn run_compliance_tests(client: &Client, path_prefix: &str) {
             .unwrap()
             .is_none()
     );
+    assert!(
+        HeadRequest::new(&key)
+            .unwrap()
+            .execute(client)
+            .await
+            .unwrap()
+            .is_none()
+    );
 
     // Put creates object, put if_absent on existing fails
     let v1 = PutRequest::new(&key, Bytes::from("v1"))
@@ -45,9 +92,68 @@ pub async fn run_compliance_tests(client: &Client, path_prefix: &str) {
         .unwrap()
         .unwrap();
     assert_eq!(resp.value, Bytes::from("v1"));
+    assert_eq!(resp.size, 2);
     assert_eq!(resp.version, v1);
     assert_eq!(resp.metadata.get("k"), Some(&"v".to_string()));
 
+    // Get at the current version returns it, at an unknown version None
+    let resp = GetRequest::new(&key)
+        .unwrap()
+        .version(v1.clone())
+        .execute(client)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(resp.value, Bytes::from("v1"));
+    assert_eq!(resp.version, v1);
+    assert!(
+        GetRequest::new(&key)
+            .unwrap()
+            .version(Version::new("999999999"))
+            .execute(client)
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    // Get if the version differs: NotModified at the current version
+    assert!(matches!(
+        GetRequest::new(&key)
+            .unwrap()
+            .if_version_not_matches(v1.clone())
+            .execute(client)
+            .await,
+        Err(Error::NotModified)
+    ));
+    let resp = GetRequest::new(&key)
+        .unwrap()
+        .if_version_not_matches(Version::new("999999999"))
+        .execute(client)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(resp.value, Bytes::from("v1"));
+    assert!(
+        GetRequest::new(prefix.join("test/missing").unwrap())
+            .unwrap()
+            .if_version_not_matches(v1.clone())
+            .execute(client)
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    // Head returns version/metadata/size without the value
+    let head = HeadRequest::new(&key)
+        .unwrap()
+        .execute(client)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(head.version, v1);
+    assert_eq!(head.size, 2);
+    assert_eq!(head.metadata.get("k"), Some(&"v".to_string()));
+
     // Put with version match succeeds, wrong version fails
     let v2 = PutRequest::new(&key, Bytes::from("v2"))
         .unwrap()
@@ -66,21 +172,24 @@ pub async fn run_compliance_tests(client: &Client, path_prefix: &str) {
     );
 
     // Patch updates metadata, wrong version fails
-    let _v3 = PatchRequest::new(&key, Metadata::with("k2", "v2"))
+    let v3 = PatchRequest::new(&key, Metadata::with("k2", "v2"))
         .unwrap()
         .if_version_matches(v2.clone())
         .execute(client)
         .await
         .unwrap()
         .version;
-    assert!(
-        PatchRequest::new(&key, Metadata::new())
-            .unwrap()
-            .if_version_matches(v2)
-            .execute(client)
-            .await
-            .is_err()
-    );
+    if capabilities.distinct_versions {
+        assert_ne!(v3, v2);
+        assert!(
+            PatchRequest::new(&key, Metadata::new())
+                .unwrap()
+                .if_version_matches(v2.clone())
+                .execute(client)
+                .await
+                .is_err()
+        );
+    }
     let resp = GetRequest::new(&key)
         .unwrap()
         .execute(client)
@@ -91,7 +200,7 @@ pub async fn run_compliance_tests(client: &Client, path_prefix: &str) {
     assert_eq!(resp.metadata.get("k2"), Some(&"v2".to_string()));
 
     // Patch non-existent returns NotFound
-    let bad_key = format!("{path_prefix}nonexistent");
+    let bad_key = prefix.join("nonexistent").unwrap();
     assert!(matches!(
         PatchRequest::new(&bad_key, Metadata::new())
             .unwrap()
@@ -99,4 +208,593 @@ pub async fn run_compliance_tests(client: &Client, path_prefix: &str) {
             .await,
         Err(Error::NotFound)
     ));
+
+    // Delete with wrong version fails, matching version removes the object
+    if capabilities.distinct_versions {
+        assert!(matches!(
+            DeleteRequest::new(&key)
+                .unwrap()
+                .if_version_matches(v2)
+                .execute(client)
+                .await,
+            Err(Error::ConditionFailed { .. })
+        ));
+    }
+    DeleteRequest::new(&key)
+        .unwrap()
+        .if_version_matches(v3)
+        .execute(client)
+        .await
+        .unwrap();
+    assert!(
+        GetRequest::new(&key)
+            .unwrap()
+            .execute(client)
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    // Delete non-existent returns NotFound
+    assert!(matches!(
+        DeleteRequest::new(&bad_key).unwrap().execute(client).await,
+        Err(Error::NotFound)
+    ));
+
+    // IfExists and IfMetadataMatches puts need an existing key, while
+    // IfVersionNotMatches holds for a missing one
+    let cond_key = prefix.join("test/conditions").unwrap();
+    let owned_by = |owner: &str| Metadata::with("owner", owner);
+    assert!(matches!(
+        PutRequest::new(&cond_key, Bytes::from("a"))
+            .unwrap()
+            .if_exists()
+            .execute(client)
+            .await,
+        Err(Error::ConditionFailed {
+            condition: Condition::IfExists
+        })
+    ));
+    assert_metadata_condition_fails(
+        PutRequest::new(&cond_key, Bytes::from("a"))
+            .unwrap()
+            .if_metadata_matches(owned_by("a"))
+            .execute(client)
+            .await,
+        capabilities,
+        |e| matches!(e, Error::ConditionFailed { .. }),
+    );
+    let v1 = PutRequest::new(&cond_key, Bytes::from("a"))
+        .unwrap()
+        .if_version_not_matches(Version::new("999999999"))
+        .metadata(owned_by("a"))
+        .execute(client)
+        .await
+        .unwrap()
+        .version;
+
+    // Puts: IfVersionNotMatches fails at that version, IfMetadataMatches
+    // compares the given entries and IfExists holds for any version
+    assert!(matches!(
+        PutRequest::new(&cond_key, Bytes::from("b"))
+            .unwrap()
+            .if_version_not_matches(v1.clone())
+            .execute(client)
+            .await,
+        Err(Error::ConditionFailed { .. })
+    ));
+    assert_metadata_condition_fails(
+        PutRequest::new(&cond_key, Bytes::from("b"))
+            .unwrap()
+            .if_metadata_matches(owned_by("b"))
+            .execute(client)
+            .await,
+        capabilities,
+        |e| matches!(e, Error::ConditionFailed { .. }),
+    );
+    let mut put = PutRequest::new(&cond_key, Bytes::from("b"))
+        .unwrap()
+        .metadata(owned_by("a"));
+    if capabilities.metadata_conditions {
+        put = put.if_metadata_matches(owned_by("a"));
+    }
+    put.execute(client).await.unwrap();
+    PutRequest::new(&cond_key, Bytes::from("c"))
+        .unwrap()
+        .if_exists()
+        .metadata(owned_by("b"))
+        .execute(client)
+        .await
+        .unwrap();
+    PutRequest::new(&cond_key, Bytes::from("d"))
+        .unwrap()
+        .if_version_not_matches(v1)
+        .metadata(owned_by("b"))
+        .execute(client)
+        .await
+        .unwrap();
+
+    // Patches: the same conditions against the existing object
+    let current = HeadRequest::new(&cond_key)
+        .unwrap()
+        .execute(client)
+        .await
+        .unwrap()
+        .unwrap()
+        .version;
+    assert!(matches!(
+        PatchRequest::new(&cond_key, owned_by("c"))
+            .unwrap()
+            .if_version_not_matches(current)
+            .execute(client)
+            .await,
+        Err(Error::ConditionFailed { .. })
+    ));
+    assert_metadata_condition_fails(
+        PatchRequest::new(&cond_key, owned_by("c"))
+            .unwrap()
+            .if_metadata_matches(owned_by("a"))
+            .execute(client)
+            .await,
+        capabilities,
+        |e| matches!(e, Error::ConditionFailed { .. }),
+    );
+    let mut patch = PatchRequest::new(&cond_key, owned_by("c")).unwrap();
+    if capabilities.metadata_conditions {
+        patch = patch.if_metadata_matches(owned_by("b"));
+    }
+    patch.execute(client).await.unwrap();
+
+    // Deletes likewise; patches and deletes of missing keys stay NotFound
+    let current = HeadRequest::new(&cond_key)
+        .unwrap()
+        .execute(client)
+        .await
+        .unwrap()
+        .unwrap()
+        .version;
+    assert!(matches!(
+        DeleteRequest::new(&cond_key)
+            .unwrap()
+            .if_version_not_matches(current)
+            .execute(client)
+            .await,
+        Err(Error::ConditionFailed { .. })
+    ));
+    assert_metadata_condition_fails(
+        DeleteRequest::new(&cond_key)
+            .unwrap()
+            .if_metadata_matches(owned_by("b"))
+            .execute(client)
+            .await,
+        capabilities,
+        |e| matches!(e, Error::ConditionFailed { .. }),
+    );
+    let mut delete = DeleteRequest::new(&cond_key).unwrap();
+    if capabilities.metadata_conditions {
+        delete = delete.if_metadata_matches(owned_by("c"));
+    }
+    delete.execute(client).await.unwrap();
+    assert_metadata_condition_fails(
+        PatchRequest::new(&cond_key, owned_by("d"))
+            .unwrap()
+            .if_metadata_matches(owned_by("c"))
+            .execute(client)
+            .await,
+        capabilities,
+        |e| matches!(e, Error::NotFound),
+    );
+    assert!(matches!(
+        DeleteRequest::new(&cond_key)
+            .unwrap()
+            .if_version_not_matches(Version::new("999999999"))
+            .execute(client)
+            .await,
+        Err(Error::NotFound)
+    ));
+
+    // Copies carry the source's value and metadata to the destination
+    let copy_source = prefix.join("test/copy/source").unwrap();
+    let copy_destination = prefix.join("test/copy/destination").unwrap();
+    PutRequest::new(&copy_source, Bytes::from("original"))
+        .unwrap()
+        .metadata(Metadata::with("k", "v"))
+        .execute(client)
+        .await
+        .unwrap();
+    let copied = CopyRequest::new(&copy_source, &copy_destination)
+        .unwrap()
+        .if_absent()
+        .execute(client)
+        .await
+        .unwrap()
+        .version;
+    let resp = GetRequest::new(&copy_destination)
+        .unwrap()
+        .execute(client)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(resp.value, Bytes::from("original"));
+    assert_eq!(resp.metadata.get("k"), Some(&"v".to_string()));
+    assert_eq!(resp.version, copied);
+
+    // The destination condition applies as for a put, and a missing source
+    // (or source version) is NotFound
+    assert!(matches!(
+        CopyRequest::new(&copy_source, &copy_destination)
+            .unwrap()
+            .if_absent()
+            .execute(client)
+            .await,
+        Err(Error::ConditionFailed { .. })
+    ));
+    assert!(matches!(
+        CopyRequest::new(&bad_key, &copy_destination)
+            .unwrap()
+            .execute(client)
+            .await,
+        Err(Error::NotFound)
+    ));
+    assert!(matches!(
+        CopyRequest::new(&copy_source, &copy_destination)
+            .unwrap()
+            .source_version(Version::new("999999999"))
+            .execute(client)
+            .await,
+        Err(Error::NotFound)
+    ));
+
+    // A pinned source version and a metadata override
+    let source_version = PutRequest::new(&copy_source, Bytes::from("updated"))
+        .unwrap()
+        .execute(client)
+        .await
+        .unwrap()
+        .version;
+    CopyRequest::new(&copy_source, &copy_destination)
+        .unwrap()
+        .source_version(source_version)
+        .destination_condition(Condition::IfVersionMatches(copied))
+        .metadata(Metadata::with("k", "override"))
+        .execute(client)
+        .await
+        .unwrap();
+    let resp = GetRequest::new(&copy_destination)
+        .unwrap()
+        .execute(client)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(resp.value, Bytes::from("updated"));
+    assert_eq!(resp.metadata.get("k"), Some(&"override".to_string()));
+
+    // Renames move the object, and leave the source if the destination
+    // condition fails
+    let renamed = prefix.join("test/copy/renamed").unwrap();
+    RenameRequest::new(&copy_destination, &renamed)
+        .unwrap()
+        .if_absent()
+        .execute(client)
+        .await
+        .unwrap();
+    assert!(
+        GetRequest::new(&copy_destination)
+            .unwrap()
+            .execute(client)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    let resp = GetRequest::new(&renamed)
+        .unwrap()
+        .execute(client)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(resp.value, Bytes::from("updated"));
+    assert!(matches!(
+        RenameRequest::new(&copy_source, &renamed)
+            .unwrap()
+            .if_absent()
+            .execute(client)
+            .await,
+        Err(Error::ConditionFailed { .. })
+    ));
+    assert!(
+        GetRequest::new(&copy_source)
+            .unwrap()
+            .execute(client)
+            .await
+            .unwrap()
+            .is_some()
+    );
+    assert!(matches!(
+        RenameRequest::new(&bad_key, &renamed)
+            .unwrap()
+            .execute(client)
+            .await,
+        Err(Error::NotFound)
+    ));
+    for key in [&copy_source, &renamed] {
+        DeleteRequest::new(key)
+            .unwrap()
+            .execute(client)
+            .await
+            .unwrap();
+    }
+
+    // Percent-encoded paths are stored and listed with their escapes intact
+    let encoded_prefix = PathPrefix::from(prefix.join("encoded").unwrap());
+    let segments = ["a/b", "50%", "..", "line\nbreak"];
+    let encoded_key = encoded_prefix
+        .join(Path::from_segments_encoded(segments).unwrap())
+        .unwrap();
+    PutRequest::new(&encoded_key, Bytes::from("encoded"))
+        .unwrap()
+        .execute(client)
+        .await
+        .unwrap();
+    let page = ListRequest::new(encoded_prefix.to_list_prefix())
+        .unwrap()
+        .execute(client)
+        .await
+        .unwrap();
+    assert_eq!(page.objects.len(), 1);
+    assert_eq!(page.objects[0].key, encoded_key);
+    let decoded = page.objects[0]
+        .key
+        .strip_prefix(&encoded_prefix)
+        .unwrap()
+        .decode_segments()
+        .unwrap();
+    assert_eq!(decoded, segments);
+    let resp = GetRequest::new(&encoded_key)
+        .unwrap()
+        .execute(client)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(resp.value, Bytes::from("encoded"));
+    DeleteRequest::new(&encoded_key)
+        .unwrap()
+        .execute(client)
+        .await
+        .unwrap();
+
+    // Streaming put and get round-trip a value sent in chunks
+    let stream_key = prefix.join("test/stream").unwrap();
+    let chunks = ["chunk-1;", "chunk-2;", "chunk-3"].map(|c| Ok(Bytes::from(c)));
+    let version = PutStreamRequest::new(&stream_key, Box::pin(futures::stream::iter(chunks)))
+        .unwrap()
+        .if_absent()
+        .metadata(Metadata::with("k", "v"))
+        .execute(client)
+        .await
+        .unwrap()
+        .version;
+    let resp = GetRequest::new(&stream_key)
+        .unwrap()
+        .execute_stream(client)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(resp.version, version);
+    assert_eq!(resp.size, 23);
+    assert_eq!(resp.metadata.get("k"), Some(&"v".to_string()));
+    let value: Vec<Bytes> = resp.stream.try_collect().await.unwrap();
+    assert_eq!(value.concat(), b"chunk-1;chunk-2;chunk-3");
+    DeleteRequest::new(&stream_key)
+        .unwrap()
+        .execute(client)
+        .await
+        .unwrap();
+
+    // Ranged gets return the requested bytes and the total size
+    let range_key = prefix.join("test/range").unwrap();
+    PutRequest::new(&range_key, Bytes::from("0123456789"))
+        .unwrap()
+        .execute(client)
+        .await
+        .unwrap();
+    for (range, expected) in [
+        (
+            GetRange::Bounded {
+                offset: 2,
+                length: 3,
+            },
+            "234",
+        ),
+        (
+            GetRange::Bounded {
+                offset: 8,
+                length: 5,
+            },
+            "89",
+        ),
+        (GetRange::From(7), "789"),
+        (GetRange::Suffix(3), "789"),
+        (GetRange::Suffix(20), "0123456789"),
+    ] {
+        let resp = GetRequest::new(&range_key)
+            .unwrap()
+            .range(range)
+            .execute(client)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(resp.value, Bytes::from(expected), "range {range:?}");
+        assert_eq!(resp.size, 10);
+    }
+
+    // Ranges starting past the end are not satisfiable
+    for range in [
+        GetRange::From(10),
+        GetRange::Bounded {
+            offset: 12,
+            length: 1,
+        },
+    ] {
+        assert!(matches!(
+            GetRequest::new(&range_key)
+                .unwrap()
+                .range(range)
+                .execute(client)
+                .await,
+            Err(Error::RangeNotSatisfiable)
+        ));
+    }
+    DeleteRequest::new(&range_key)
+        .unwrap()
+        .execute(client)
+        .await
+        .unwrap();
+
+    // List rolls up common prefixes when a delimiter is set
+    let list_prefix = PathPrefix::from(prefix.join("list").unwrap()).to_list_prefix();
+    let names = ["a", "b/1", "b/2", "c"];
+    for name in names {
+        PutRequest::new(format!("{list_prefix}{name}"), Bytes::from(name))
+            .unwrap()
+            .execute(client)
+            .await
+            .unwrap();
+    }
+    let page = ListRequest::new(&list_prefix)
+        .unwrap()
+        .delimiter('/')
+        .execute(client)
+        .await
+        .unwrap();
+    let keys: Vec<_> = page.objects.iter().map(|o| o.key.to_string()).collect();
+    assert_eq!(keys, [format!("{list_prefix}a"), format!("{list_prefix}c")]);
+    assert_eq!(page.common_prefixes, [format!("{list_prefix}b/")]);
+    assert_eq!(page.objects[0].size, 1);
+    assert!(page.next_page_token.is_none());
+
+    // List paginates through every key in order
+    let pages: Vec<ListResponse> = ListRequest::new(&list_prefix)
+        .unwrap()
+        .max_results(1)
+        .into_stream(client)
+        .try_collect()
+        .await
+        .unwrap();
+    let keys: Vec<_> = pages
+        .iter()
+        .flat_map(|p| p.objects.iter().map(|o| o.key.to_string()))
+        .collect();
+    let expected: Vec<_> = names.iter().map(|n| format!("{list_prefix}{n}")).collect();
+    assert_eq!(keys, expected);
+
+    // Pagination does not repeat common prefixes
+    let pages: Vec<ListResponse> = ListRequest::new(&list_prefix)
+        .unwrap()
+        .delimiter('/')
+        .max_results(1)
+        .into_stream(client)
+        .try_collect()
+        .await
+        .unwrap();
+    let prefixes: Vec<_> = pages
+        .iter()
+        .flat_map(|p| p.common_prefixes.clone())
+        .collect();
+    assert_eq!(pages.len(), 3);
+    assert_eq!(prefixes, [format!("{list_prefix}b/")]);
+
+    // List honors start_after
+    let page = ListRequest::new(&list_prefix)
+        .unwrap()
+        .start_after(format!("{list_prefix}b/1"))
+        .execute(client)
+        .await
+        .unwrap();
+    let keys: Vec<_> = page.objects.iter().map(|o| o.key.to_string()).collect();
+    assert_eq!(keys, expected[2..]);
+
+    for key in expected {
+        DeleteRequest::new(key)
+            .unwrap()
+            .execute(client)
+            .await
+            .unwrap();
+    }
+
+    // Exactly one of several concurrent creates wins
+    let counter_key = prefix.join("test/counter").unwrap();
+    let results = join_all((0..8).map(|i| {
+        PutRequest::new(&counter_key, Bytes::from("0"))
+            .unwrap()
+            .metadata(Metadata::with("writer", i.to_string()))
+            .if_absent()
+            .execute(client)
+    }))
+    .await;
+    assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+    assert!(
+        results
+            .iter()
+            .all(|r| matches!(r, Ok(_) | Err(Error::ConditionFailed { .. })))
+    );
+
+    // Concurrent compare-and-swap increments never lose an update
+    join_all((0..4).map(|_| async {
+        for _ in 0..5 {
+            loop {
+                let current = GetRequest::new(&counter_key)
+                    .unwrap()
+                    .execute(client)
+                    .await
+                    .unwrap()
+                    .unwrap();
+                let count: u32 = std::str::from_utf8(&current.value)
+                    .unwrap()
+                    .parse()
+                    .unwrap();
+                let result = PutRequest::new(&counter_key, Bytes::from((count + 1).to_string()))
+                    .unwrap()
+                    .if_version_matches(current.version)
+                    .execute(client)
+                    .await;
+                match result {
+                    Ok(_) => break,
+                    Err(Error::ConditionFailed { .. }) => continue,
+                    Err(e) => panic!("unexpected error: {e}"),
+                }
+            }
+        }
+    }))
+    .await;
+    let resp = GetRequest::new(&counter_key)
+        .unwrap()
+        .execute(client)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(resp.value, Bytes::from("20"));
+    DeleteRequest::new(&counter_key)
+        .unwrap()
+        .execute(client)
+        .await
+        .unwrap();
+}
+
+/// Check a write whose `IfMetadataMatches` condition does not hold: it fails
+/// as `expected`, or with InvalidRequest on backends without metadata
+/// conditions
+fn assert_metadata_condition_fails<T: std::fmt::Debug>(
+    result: Result<T, Error>,
+    capabilities: Capabilities,
+    expected: fn(&Error) -> bool,
+) {
+    let error = result.expect_err("the metadata condition should fail");
+    if capabilities.metadata_conditions {
+        assert!(expected(&error), "unexpected error: {error}");
+    } else {
+        assert!(
+            matches!(error, Error::InvalidRequest { .. }),
+            "unexpected error: {error}"
+        );
+    }
 }
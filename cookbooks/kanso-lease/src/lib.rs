@@ -268,6 +268,7 @@ fn is_lease_alive(expiry: u64) -> bool {
 mod tests {
     use super::*;
     use kanso_inmemory::InMemoryStore;
+    use kanso_middleware::{Fault, FaultyStore, Operation};
     use serde::{Deserialize, Serialize};
     use std::sync::Arc;
     use std::time::Duration;
@@ -322,4 +323,38 @@ mod tests {
             .unwrap();
         assert_eq!(value3.count, 1); // Should get the existing value
     }
+
+    #[tokio::test]
+    async fn test_lease_renew_with_dropped_response() {
+        let faulty = Arc::new(FaultyStore::new(Arc::new(InMemoryStore::new()), 0));
+        let store: Arc<dyn kanso_client::ObjectStore> = faulty.clone();
+
+        let (mut lease, _) = AcquireRequest::new("test-key", TestData { count: 0 })
+            .owner("test-owner")
+            .execute(&store)
+            .await
+            .unwrap();
+
+        // The renewal commits, but the lease never learns the new version
+        faulty.script(Operation::Patch, [Some(Fault::DropResponse)]);
+        assert!(matches!(
+            lease.renew().await,
+            Err(LeaseError::Conflict { source, .. }) if source.is_retryable()
+        ));
+        assert!(matches!(
+            lease.renew().await,
+            Err(LeaseError::Conflict {
+                source: kanso_client::Error::ConditionFailed { .. },
+                ..
+            })
+        ));
+
+        // The owner can still re-acquire and carry on
+        let (mut lease, _) = AcquireRequest::new("test-key", TestData { count: 0 })
+            .owner("test-owner")
+            .execute(&store)
+            .await
+            .unwrap();
+        lease.renew().await.unwrap();
+    }
 }
@@ -0,0 +1,88 @@
+//! Helpers shared by HTTP backends, and the wire format of the kanso-server
+//! gateway
+//!
+//! The helpers working on reqwest types need the `reqwest` feature.
+
+#[cfg(feature = "reqwest")]
+use std::time::Duration;
+
+use crate::{Error, Path, Version};
+
+/// Prefix of the gateway's headers carrying object metadata
+pub const META_PREFIX: &str = "x-kanso-meta-";
+
+/// Prefix of the gateway's headers carrying the entries of an
+/// `IfMetadataMatches` condition
+pub const IF_META_PREFIX: &str = "x-kanso-if-meta-";
+
+/// Format a version as the gateway's ETag
+pub fn etag(version: &Version) -> String {
+    format!("\"{}\"", urlencoding::encode(version.as_str()))
+}
+
+/// Parse a version from the gateway's ETag, or `None` if it is malformed
+pub fn parse_etag(etag: &str) -> Option<Version> {
+    etag.strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .and_then(|v| urlencoding::decode(v).ok())
+        .map(Version::new)
+}
+
+/// Map a reqwest error onto the error taxonomy
+#[cfg(feature = "reqwest")]
+pub fn request_error(e: reqwest::Error) -> Error {
+    if e.is_timeout() {
+        Error::Timeout {
+            source: Some(e.into()),
+        }
+    } else if e.is_builder() {
+        Error::InvalidRequest {
+            message: e.to_string(),
+        }
+    } else if e.is_decode() {
+        Error::Other(format!("decode error: {e}"))
+    } else {
+        Error::Transient {
+            message: format!("request error: {e}"),
+            source: Some(e.into()),
+        }
+    }
+}
+
+/// Map an unexpected response onto the error taxonomy with
+/// `Error::from_status`
+#[cfg(feature = "reqwest")]
+pub async fn status_error(resp: reqwest::Response) -> Error {
+    let status = resp.status().as_u16();
+    let retry_after = retry_after(resp.headers());
+    let body = resp.text().await.unwrap_or_default();
+    Error::from_status(status, body, retry_after)
+}
+
+/// The delay of a `Retry-After` header given in seconds
+#[cfg(feature = "reqwest")]
+pub fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    headers
+        .get("retry-after")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .map(Duration::from_secs)
+}
+
+/// Split a key into the bucket (or container, as named by `kind`) in its
+/// first component and the object name, as in "bucket/path/to/object"
+pub fn parse_path<'a>(path: &'a Path, kind: &str) -> Result<(&'a str, &'a str), Error> {
+    path.as_str()
+        .split_once('/')
+        .ok_or_else(|| Error::InvalidRequest {
+            message: format!("path must include {kind}: expected '{kind}/key'"),
+        })
+}
+
+/// Split a list prefix into the bucket (or container, as named by `kind`) in
+/// its first component and the object prefix, which may be empty
+pub fn parse_prefix<'a>(prefix: &'a str, kind: &str) -> Result<(&'a str, &'a str), Error> {
+    prefix.split_once('/').ok_or_else(|| Error::InvalidRequest {
+        message: format!("list prefix must include {kind}: expected '{kind}/prefix'"),
+    })
+}
@@ -1,10 +1,21 @@
 use std::collections::HashMap;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use async_trait::async_trait;
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
+use futures::{Stream, TryStreamExt};
 use thiserror::Error;
 
+pub mod http;
+mod path;
+
+pub use path::{Path, PathError, PathPrefix};
+
+/// Boxed error used as the underlying cause of a backend failure
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
 /// Error type for object store operations
 #[derive(Debug, Error)]
 pub enum Error {
@@ -14,10 +25,85 @@ pub enum Error {
     #[error("not found")]
     NotFound,
 
+    #[error("not modified")]
+    NotModified,
+
+    #[error("range not satisfiable")]
+    RangeNotSatisfiable,
+
+    #[error("unauthorized: {message}")]
+    Unauthorized {
+        message: String,
+        #[source]
+        source: Option<BoxError>,
+    },
+
+    #[error("permission denied: {message}")]
+    PermissionDenied { message: String },
+
+    #[error("rate limited (retry after {retry_after:?})")]
+    RateLimited { retry_after: Option<Duration> },
+
+    #[error("request timed out")]
+    Timeout {
+        #[source]
+        source: Option<BoxError>,
+    },
+
+    #[error("transient error: {message}")]
+    Transient {
+        message: String,
+        #[source]
+        source: Option<BoxError>,
+    },
+
+    #[error("invalid request: {message}")]
+    InvalidRequest { message: String },
+
+    #[error("backend error: status {status}: {body}")]
+    Backend { status: u16, body: String },
+
     #[error("{0}")]
     Other(String),
 }
 
+impl Error {
+    /// Whether the operation may succeed if retried unchanged
+    ///
+    /// Rate limiting, timeouts, transient failures and 5xx backend responses
+    /// are retryable; everything else (including condition failures) is not.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::RateLimited { .. } | Error::Timeout { .. } | Error::Transient { .. } => true,
+            Error::Backend { status, .. } => matches!(status, 408 | 429 | 500..=599),
+            _ => false,
+        }
+    }
+
+    /// Map an unexpected HTTP response status onto the error taxonomy
+    ///
+    /// For backends speaking HTTP, after handling the statuses whose meaning
+    /// depends on the request (such as 404 or 412) and any service-specific
+    /// error codes.
+    pub fn from_status(status: u16, body: String, retry_after: Option<Duration>) -> Self {
+        match status {
+            400 => Error::InvalidRequest { message: body },
+            401 => Error::Unauthorized {
+                message: body,
+                source: None,
+            },
+            403 => Error::PermissionDenied { message: body },
+            408 => Error::Timeout { source: None },
+            429 => Error::RateLimited { retry_after },
+            500 | 502 | 503 | 504 => Error::Transient {
+                message: format!("status {status}: {body}"),
+                source: None,
+            },
+            _ => Error::Backend { status, body },
+        }
+    }
+}
+
 /// Represents a version/etag for an object in the store
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Version(String);
@@ -52,95 +138,6 @@ impl std::fmt::Display for Version {
     }
 }
 
-/// Error type for path validation
-#[derive(Debug, Error)]
-pub enum PathError {
-    #[error("path cannot have leading or trailing slashes")]
-    LeadingTrailingSlash,
-
-    #[error("path cannot contain empty segments")]
-    EmptySegment,
-
-    #[error("path cannot contain relative segments (. or ..)")]
-    RelativeSegment,
-
-    #[error("path cannot contain ASCII control characters")]
-    ControlCharacter,
-
-    #[error("path cannot be empty")]
-    Empty,
-}
-
-/// Represents a validated path in the object store
-///
-/// A Path maintains the following invariants:
-/// - Paths are delimited by `/`
-/// - Paths do not contain leading or trailing `/`
-/// - Paths do not contain relative path segments (`.` or `..`)
-/// - Paths do not contain empty path segments
-/// - Paths do not contain any ASCII control characters
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct Path(String);
-
-impl Path {
-    /// Create a new path with validation
-    pub fn new(s: impl AsRef<str>) -> Result<Self, PathError> {
-        let s = s.as_ref();
-
-        // Empty check
-        if s.is_empty() {
-            return Err(PathError::Empty);
-        }
-
-        // Leading/trailing slash check
-        if s.starts_with('/') || s.ends_with('/') {
-            return Err(PathError::LeadingTrailingSlash);
-        }
-
-        // Validate each segment
-        for segment in s.split('/') {
-            if segment.is_empty() {
-                return Err(PathError::EmptySegment);
-            }
-            if segment == "." || segment == ".." {
-                return Err(PathError::RelativeSegment);
-            }
-            if segment.chars().any(|c| c.is_ascii_control()) {
-                return Err(PathError::ControlCharacter);
-            }
-        }
-
-        Ok(Self(s.to_string()))
-    }
-
-    /// Get the path as a string slice
-    pub fn as_str(&self) -> &str {
-        &self.0
-    }
-}
-
-impl TryFrom<String> for Path {
-    type Error = PathError;
-
-    fn try_from(s: String) -> Result<Self, Self::Error> {
-        Self::new(s)
-    }
-}
-
-impl TryFrom<&str> for Path {
-    type Error = PathError;
-
-    fn try_from(s: &str) -> Result<Self, Self::Error> {
-        Self::new(s)
-    }
-}
-
-impl std::fmt::Display for Path {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
-    }
-}
-
 /// Metadata associated with an object (e.g., user-defined headers)
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct Metadata {
@@ -192,18 +189,157 @@ impl Metadata {
 }
 
 /// Condition for conditional writes
+///
+/// Puts evaluate the condition against the key as it currently is, which may
+/// be absent. Patches and deletes require the object to exist: they fail with
+/// `Error::NotFound` for a missing key before the condition is considered, so
+/// for them `IfExists` always holds and `IfAbsent` never does.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Condition {
+    /// Only write if the key does not exist
+    IfAbsent,
+    /// Only write if the key exists, whatever its version
+    IfExists,
+    /// Only write if the current version matches the specified version
+    IfVersionMatches(Version),
+    /// Only write if the key is absent or its current version differs from
+    /// the specified version
+    IfVersionNotMatches(Version),
+    /// Only write if the key exists and its metadata contains every entry of
+    /// the specified metadata (other entries are ignored)
+    IfMetadataMatches(Metadata),
+}
+
+impl Condition {
+    /// Whether the condition holds for a key whose current object has the
+    /// given version and metadata, or `None` if the key does not exist
+    pub fn is_satisfied_by(&self, current: Option<(&Version, &Metadata)>) -> bool {
+        match self {
+            Condition::IfAbsent => current.is_none(),
+            Condition::IfExists => current.is_some(),
+            Condition::IfVersionMatches(expected) => {
+                current.is_some_and(|(version, _)| version == expected)
+            }
+            Condition::IfVersionNotMatches(unexpected) => {
+                current.is_none_or(|(version, _)| version != unexpected)
+            }
+            Condition::IfMetadataMatches(expected) => current.is_some_and(|(_, metadata)| {
+                expected
+                    .headers
+                    .iter()
+                    .all(|(key, value)| metadata.get(key) == Some(value))
+            }),
+        }
+    }
+
+    /// Reduce the condition to `IfAbsent` or `IfVersionMatches` for a key in
+    /// the given state, failing with `Error::ConditionFailed` if it does not hold
+    ///
+    /// For backends whose preconditions can only compare versions: the state
+    /// is read first, and the write is then made conditional on that state
+    /// being unchanged. A concurrent write in between makes the write fail even
+    /// if the original condition would still hold.
+    pub fn pinned(&self, current: Option<(&Version, &Metadata)>) -> Result<PinnedCondition, Error> {
+        if !self.is_satisfied_by(current) {
+            return Err(Error::ConditionFailed {
+                condition: self.clone(),
+            });
+        }
+        Ok(match current {
+            None => PinnedCondition::IfAbsent,
+            Some((version, _)) => PinnedCondition::IfVersionMatches(version.clone()),
+        })
+    }
+}
+
+/// A condition that compares versions only, as returned by `Condition::pinned`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PinnedCondition {
     /// Only write if the key does not exist
     IfAbsent,
     /// Only write if the current version matches the specified version
     IfVersionMatches(Version),
 }
 
+impl From<PinnedCondition> for Condition {
+    fn from(condition: PinnedCondition) -> Self {
+        match condition {
+            PinnedCondition::IfAbsent => Condition::IfAbsent,
+            PinnedCondition::IfVersionMatches(version) => Condition::IfVersionMatches(version),
+        }
+    }
+}
+
+/// Byte range of an object to read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GetRange {
+    /// `length` bytes starting at `offset` (truncated at the end of the object)
+    Bounded { offset: u64, length: u64 },
+    /// All bytes starting at `offset`
+    From(u64),
+    /// The last `length` bytes (the whole object if it is shorter)
+    Suffix(u64),
+}
+
+impl GetRange {
+    /// Resolve the range against an object of `size` bytes
+    ///
+    /// Follows HTTP range semantics: returns `Error::RangeNotSatisfiable` if
+    /// the range selects no bytes of the object.
+    pub fn resolve(&self, size: u64) -> Result<std::ops::Range<u64>, Error> {
+        match *self {
+            GetRange::Bounded { offset, length } if offset < size && length > 0 => {
+                Ok(offset..size.min(offset.saturating_add(length)))
+            }
+            GetRange::From(offset) if offset < size => Ok(offset..size),
+            GetRange::Suffix(length) if size > 0 && length > 0 => {
+                Ok(size.saturating_sub(length)..size)
+            }
+            _ => Err(Error::RangeNotSatisfiable),
+        }
+    }
+
+    /// The range as an HTTP `Range` header value
+    ///
+    /// Returns `Error::RangeNotSatisfiable` for zero-length ranges, which
+    /// select no bytes of any object: a bounded one has no header value (its
+    /// last byte would come before its first), and servers ignoring an
+    /// invalid header would return the whole object.
+    pub fn header_value(&self) -> Result<String, Error> {
+        match self {
+            GetRange::Bounded { length: 0, .. } | GetRange::Suffix(0) => {
+                Err(Error::RangeNotSatisfiable)
+            }
+            range => Ok(range.to_string()),
+        }
+    }
+}
+
+/// Formats the range as an HTTP `Range` header value, which zero-length
+/// ranges do not have (see `GetRange::header_value`)
+impl std::fmt::Display for GetRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GetRange::Bounded { offset, length } => {
+                write!(
+                    f,
+                    "bytes={offset}-{}",
+                    offset.saturating_add(*length).saturating_sub(1)
+                )
+            }
+            GetRange::From(offset) => write!(f, "bytes={offset}-"),
+            GetRange::Suffix(length) => write!(f, "bytes=-{length}"),
+        }
+    }
+}
+
 /// Request for a get operation
 #[derive(Debug, Clone)]
 pub struct GetRequest {
     pub key: Path,
+    pub range: Option<GetRange>,
+    pub version: Option<Version>,
+    pub if_version_not_matches: Option<Version>,
 }
 
 impl GetRequest {
@@ -213,24 +349,155 @@ impl GetRequest {
     pub fn new(key: impl AsRef<str>) -> Result<Self, PathError> {
         Ok(Self {
             key: Path::new(key)?,
+            range: None,
+            version: None,
+            if_version_not_matches: None,
         })
     }
 
+    /// Only read the given byte range of the object
+    pub fn range(mut self, range: GetRange) -> Self {
+        self.range = Some(range);
+        self
+    }
+
+    /// Read the object as of the given version
+    ///
+    /// The get returns `None` if the key has no such version. Only backends
+    /// that retain history (e.g. GCS with object versioning, or InMemoryStore
+    /// in history mode) can serve past versions; others only hold the current
+    /// one.
+    pub fn version(mut self, version: Version) -> Self {
+        self.version = Some(version);
+        self
+    }
+
+    /// Only read the object if its version differs from the given one
+    ///
+    /// The get fails with `Error::NotModified` instead of returning the value
+    /// if the object is at that version, so an unchanged object is not
+    /// downloaded again. A missing object still returns `None`.
+    pub fn if_version_not_matches(mut self, version: Version) -> Self {
+        self.if_version_not_matches = Some(version);
+        self
+    }
+
     /// Execute the get request against a client
     pub async fn execute(self, client: &Client) -> Result<Option<GetResponse>, Error> {
         client.get(self).await
     }
+
+    /// Execute the get request against a client, streaming the value
+    pub async fn execute_stream(self, client: &Client) -> Result<Option<GetStreamResponse>, Error> {
+        client.get_stream(self).await
+    }
 }
 
 /// Response from a get operation
 #[derive(Debug, Clone)]
 pub struct GetResponse {
-    /// The value associated with the key
+    /// The value associated with the key (only the requested range, if any)
     pub value: Bytes,
+    /// The total size of the object in bytes
+    pub size: u64,
+    /// The version of the object
+    pub version: Version,
+    /// Metadata associated with the object
+    pub metadata: Metadata,
+}
+
+/// Stream of byte chunks used by streaming reads and writes
+pub type ByteStream = Pin<Box<dyn Stream<Item = Result<Bytes, Error>> + Send>>;
+
+/// Read a byte stream to completion into a single buffer
+pub async fn collect_bytes(mut stream: ByteStream) -> Result<Bytes, Error> {
+    let mut buf = BytesMut::new();
+    while let Some(chunk) = stream.try_next().await? {
+        buf.extend_from_slice(&chunk);
+    }
+    Ok(buf.freeze())
+}
+
+/// Response from a streaming get operation
+pub struct GetStreamResponse {
+    /// The value associated with the key (only the requested range, if any)
+    pub stream: ByteStream,
+    /// The total size of the object in bytes
+    pub size: u64,
+    /// The version of the object
+    pub version: Version,
+    /// Metadata associated with the object
+    pub metadata: Metadata,
+}
+
+impl GetStreamResponse {
+    /// Buffer the whole stream into a GetResponse
+    pub async fn collect(self) -> Result<GetResponse, Error> {
+        Ok(GetResponse {
+            value: collect_bytes(self.stream).await?,
+            size: self.size,
+            version: self.version,
+            metadata: self.metadata,
+        })
+    }
+}
+
+impl From<GetResponse> for GetStreamResponse {
+    fn from(response: GetResponse) -> Self {
+        Self {
+            stream: Box::pin(futures::stream::once(async move { Ok(response.value) })),
+            size: response.size,
+            version: response.version,
+            metadata: response.metadata,
+        }
+    }
+}
+
+impl std::fmt::Debug for GetStreamResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GetStreamResponse")
+            .field("size", &self.size)
+            .field("version", &self.version)
+            .field("metadata", &self.metadata)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Request for a head operation (fetch object attributes without the value)
+#[derive(Debug, Clone)]
+pub struct HeadRequest {
+    pub key: Path,
+}
+
+impl HeadRequest {
+    /// Create a new head request
+    ///
+    /// Returns a PathError if the key doesn't satisfy Path invariants
+    pub fn new(key: impl AsRef<str>) -> Result<Self, PathError> {
+        Ok(Self {
+            key: Path::new(key)?,
+        })
+    }
+
+    /// Execute the head request against a client
+    pub async fn execute(self, client: &Client) -> Result<Option<HeadResponse>, Error> {
+        client.head(self).await
+    }
+}
+
+/// Response from a head operation
+#[derive(Debug, Clone)]
+pub struct HeadResponse {
     /// The version of the object
     pub version: Version,
     /// Metadata associated with the object
     pub metadata: Metadata,
+    /// The size of the object in bytes
+    pub size: u64,
+    /// The content type of the object, if the backend records one
+    pub content_type: Option<String>,
+    /// The time the object was last modified, if the backend records one
+    pub last_modified: Option<SystemTime>,
 }
 
 /// Request for a put operation
@@ -267,6 +534,24 @@ impl PutRequest {
         self
     }
 
+    /// Set the condition to only write if the key already exists
+    pub fn if_exists(mut self) -> Self {
+        self.condition = Some(Condition::IfExists);
+        self
+    }
+
+    /// Set the condition to only write if the key is absent or at another version
+    pub fn if_version_not_matches(mut self, version: Version) -> Self {
+        self.condition = Some(Condition::IfVersionNotMatches(version));
+        self
+    }
+
+    /// Set the condition to only write if the key exists with the given metadata entries
+    pub fn if_metadata_matches(mut self, metadata: Metadata) -> Self {
+        self.condition = Some(Condition::IfMetadataMatches(metadata));
+        self
+    }
+
     /// Set metadata for the object
     pub fn metadata(mut self, metadata: Metadata) -> Self {
         self.metadata = Some(metadata);
@@ -279,6 +564,79 @@ impl PutRequest {
     }
 }
 
+/// Request for a streaming put operation
+pub struct PutStreamRequest {
+    pub key: Path,
+    pub stream: ByteStream,
+    pub condition: Option<Condition>,
+    pub metadata: Option<Metadata>,
+}
+
+impl PutStreamRequest {
+    /// Create a new streaming put request
+    ///
+    /// Returns a PathError if the key doesn't satisfy Path invariants
+    pub fn new(key: impl AsRef<str>, stream: ByteStream) -> Result<Self, PathError> {
+        Ok(Self {
+            key: Path::new(key)?,
+            stream,
+            condition: None,
+            metadata: None,
+        })
+    }
+
+    /// Set the condition to only write if the key does not exist
+    pub fn if_absent(mut self) -> Self {
+        self.condition = Some(Condition::IfAbsent);
+        self
+    }
+
+    /// Set the condition to only write if the current version matches
+    pub fn if_version_matches(mut self, version: Version) -> Self {
+        self.condition = Some(Condition::IfVersionMatches(version));
+        self
+    }
+
+    /// Set the condition to only write if the key already exists
+    pub fn if_exists(mut self) -> Self {
+        self.condition = Some(Condition::IfExists);
+        self
+    }
+
+    /// Set the condition to only write if the key is absent or at another version
+    pub fn if_version_not_matches(mut self, version: Version) -> Self {
+        self.condition = Some(Condition::IfVersionNotMatches(version));
+        self
+    }
+
+    /// Set the condition to only write if the key exists with the given metadata entries
+    pub fn if_metadata_matches(mut self, metadata: Metadata) -> Self {
+        self.condition = Some(Condition::IfMetadataMatches(metadata));
+        self
+    }
+
+    /// Set metadata for the object
+    pub fn metadata(mut self, metadata: Metadata) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    /// Execute the streaming put request against a client
+    pub async fn execute(self, client: &Client) -> Result<PutResponse, Error> {
+        client.put_stream(self).await
+    }
+}
+
+impl std::fmt::Debug for PutStreamRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PutStreamRequest")
+            .field("key", &self.key)
+            .field("condition", &self.condition)
+            .field("metadata", &self.metadata)
+            .finish_non_exhaustive()
+    }
+}
+
 /// Response from a put operation
 #[derive(Debug, Clone)]
 pub struct PutResponse {
@@ -312,6 +670,18 @@ impl PatchRequest {
         self
     }
 
+    /// Set the condition to only patch if the current version differs
+    pub fn if_version_not_matches(mut self, version: Version) -> Self {
+        self.condition = Some(Condition::IfVersionNotMatches(version));
+        self
+    }
+
+    /// Set the condition to only patch if the object has the given metadata entries
+    pub fn if_metadata_matches(mut self, metadata: Metadata) -> Self {
+        self.condition = Some(Condition::IfMetadataMatches(metadata));
+        self
+    }
+
     /// Execute the patch request against a client
     pub async fn execute(self, client: &Client) -> Result<PatchResponse, Error> {
         client.patch(self).await
@@ -325,6 +695,435 @@ pub struct PatchResponse {
     pub version: Version,
 }
 
+/// Request for a delete operation
+#[derive(Debug, Clone)]
+pub struct DeleteRequest {
+    pub key: Path,
+    pub condition: Option<Condition>,
+}
+
+impl DeleteRequest {
+    /// Create a new delete request
+    ///
+    /// Returns a PathError if the key doesn't satisfy Path invariants
+    pub fn new(key: impl AsRef<str>) -> Result<Self, PathError> {
+        Ok(Self {
+            key: Path::new(key)?,
+            condition: None,
+        })
+    }
+
+    /// Set the condition to only delete if the current version matches
+    pub fn if_version_matches(mut self, version: Version) -> Self {
+        self.condition = Some(Condition::IfVersionMatches(version));
+        self
+    }
+
+    /// Set the condition to only delete if the current version differs
+    pub fn if_version_not_matches(mut self, version: Version) -> Self {
+        self.condition = Some(Condition::IfVersionNotMatches(version));
+        self
+    }
+
+    /// Set the condition to only delete if the object has the given metadata entries
+    pub fn if_metadata_matches(mut self, metadata: Metadata) -> Self {
+        self.condition = Some(Condition::IfMetadataMatches(metadata));
+        self
+    }
+
+    /// Execute the delete request against a client
+    pub async fn execute(self, client: &Client) -> Result<DeleteResponse, Error> {
+        client.delete(self).await
+    }
+}
+
+/// Response from a delete operation
+#[derive(Debug, Clone)]
+pub struct DeleteResponse;
+
+/// Request for a copy operation (server-side where the backend supports it)
+#[derive(Debug, Clone)]
+pub struct CopyRequest {
+    pub from: Path,
+    pub to: Path,
+    /// Copy this version of the source instead of the current one
+    pub source_version: Option<Version>,
+    /// Condition on the destination, as for a put
+    pub destination_condition: Option<Condition>,
+    /// Metadata for the destination instead of the source's metadata
+    pub metadata_override: Option<Metadata>,
+}
+
+impl CopyRequest {
+    /// Create a new copy request
+    ///
+    /// Returns a PathError if either key doesn't satisfy Path invariants
+    pub fn new(from: impl AsRef<str>, to: impl AsRef<str>) -> Result<Self, PathError> {
+        Ok(Self {
+            from: Path::new(from)?,
+            to: Path::new(to)?,
+            source_version: None,
+            destination_condition: None,
+            metadata_override: None,
+        })
+    }
+
+    /// Copy a specific version of the source
+    pub fn source_version(mut self, version: Version) -> Self {
+        self.source_version = Some(version);
+        self
+    }
+
+    /// Set the condition to only copy if the destination does not exist
+    pub fn if_absent(mut self) -> Self {
+        self.destination_condition = Some(Condition::IfAbsent);
+        self
+    }
+
+    /// Set the condition the destination must satisfy
+    pub fn destination_condition(mut self, condition: Condition) -> Self {
+        self.destination_condition = Some(condition);
+        self
+    }
+
+    /// Give the destination this metadata instead of the source's
+    pub fn metadata(mut self, metadata: Metadata) -> Self {
+        self.metadata_override = Some(metadata);
+        self
+    }
+
+    /// Execute the copy request against a client
+    pub async fn execute(self, client: &Client) -> Result<CopyResponse, Error> {
+        client.copy(self).await
+    }
+}
+
+/// Response from a copy or rename operation
+#[derive(Debug, Clone)]
+pub struct CopyResponse {
+    /// The version of the destination object
+    pub version: Version,
+}
+
+/// Request for a rename, built from a conditional copy and a conditional delete
+///
+/// The source is copied at its current version, then deleted only if it is
+/// still at that version. If the source changed or vanished in between, the
+/// copy is undone (unless the destination changed too) and the rename fails
+/// with the delete's error. Renames are not atomic: for a moment both keys
+/// hold the object.
+#[derive(Debug, Clone)]
+pub struct RenameRequest {
+    pub from: Path,
+    pub to: Path,
+    /// Condition on the destination, as for a put
+    pub destination_condition: Option<Condition>,
+}
+
+impl RenameRequest {
+    /// Create a new rename request
+    ///
+    /// Returns a PathError if either key doesn't satisfy Path invariants
+    pub fn new(from: impl AsRef<str>, to: impl AsRef<str>) -> Result<Self, PathError> {
+        Ok(Self {
+            from: Path::new(from)?,
+            to: Path::new(to)?,
+            destination_condition: None,
+        })
+    }
+
+    /// Set the condition to only rename if the destination does not exist
+    pub fn if_absent(mut self) -> Self {
+        self.destination_condition = Some(Condition::IfAbsent);
+        self
+    }
+
+    /// Set the condition the destination must satisfy
+    pub fn destination_condition(mut self, condition: Condition) -> Self {
+        self.destination_condition = Some(condition);
+        self
+    }
+
+    /// Execute the rename against a client
+    ///
+    /// Returns `Error::NotFound` if the source does not exist.
+    pub async fn execute(self, client: &Client) -> Result<CopyResponse, Error> {
+        if self.from == self.to {
+            return Err(Error::InvalidRequest {
+                message: "cannot rename an object onto itself".into(),
+            });
+        }
+        let source = client
+            .head(HeadRequest {
+                key: self.from.clone(),
+            })
+            .await?
+            .ok_or(Error::NotFound)?;
+        let copied = client
+            .copy(CopyRequest {
+                from: self.from.clone(),
+                to: self.to.clone(),
+                source_version: Some(source.version.clone()),
+                destination_condition: self.destination_condition,
+                metadata_override: None,
+            })
+            .await?;
+
+        let deleted = client
+            .delete(DeleteRequest {
+                key: self.from,
+                condition: Some(Condition::IfVersionMatches(source.version)),
+            })
+            .await;
+        match deleted {
+            Ok(DeleteResponse) => Ok(copied),
+            // The source changed after the copy: undo it. Other errors leave
+            // both objects, since the delete may have gone through.
+            Err(e @ (Error::ConditionFailed { .. } | Error::NotFound)) => {
+                let _ = client
+                    .delete(DeleteRequest {
+                        key: self.to,
+                        condition: Some(Condition::IfVersionMatches(copied.version)),
+                    })
+                    .await;
+                Err(e)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Default page size for list operations, matching GCS
+const DEFAULT_MAX_RESULTS: usize = 1000;
+
+/// Page token prefixes used by `ListRequest::paginate`, recording whether the
+/// last entry of a page was an object key or a common prefix
+const KEY_TOKEN: &str = "k:";
+const PREFIX_TOKEN: &str = "p:";
+
+/// Request for a list operation
+///
+/// Lists objects whose keys start with `prefix`, in lexicographic key order.
+/// When a delimiter is set, keys containing the delimiter after the prefix are
+/// rolled up into common prefixes (ending with the delimiter) instead of being
+/// returned individually.
+#[derive(Debug, Clone, Default)]
+pub struct ListRequest {
+    pub prefix: String,
+    pub delimiter: Option<char>,
+    pub start_after: Option<String>,
+    pub page_token: Option<String>,
+    pub max_results: Option<usize>,
+}
+
+impl ListRequest {
+    /// Create a new list request
+    ///
+    /// The prefix may be empty or end with `/`, but must otherwise satisfy
+    /// Path invariants. Returns a PathError if it doesn't.
+    pub fn new(prefix: impl Into<String>) -> Result<Self, PathError> {
+        let prefix = prefix.into();
+        let trimmed = prefix.strip_suffix('/').unwrap_or(&prefix);
+        if !trimmed.is_empty() {
+            Path::new(trimmed)?;
+        }
+        Ok(Self {
+            prefix,
+            ..Self::default()
+        })
+    }
+
+    /// Roll up keys containing the delimiter after the prefix into common prefixes
+    pub fn delimiter(mut self, delimiter: char) -> Self {
+        self.delimiter = Some(delimiter);
+        self
+    }
+
+    /// Only list keys that sort strictly after the given key
+    pub fn start_after(mut self, key: impl Into<String>) -> Self {
+        self.start_after = Some(key.into());
+        self
+    }
+
+    /// Continue a previous listing from its `next_page_token`
+    pub fn page_token(mut self, token: impl Into<String>) -> Self {
+        self.page_token = Some(token.into());
+        self
+    }
+
+    /// Limit the number of entries (objects and common prefixes) per page
+    ///
+    /// A page may hold fewer entries even when more follow: GcsStore returns
+    /// the first page after `start_after` one short, for instance. Only a
+    /// missing `next_page_token` marks the end of the listing.
+    pub fn max_results(mut self, max_results: usize) -> Self {
+        self.max_results = Some(max_results);
+        self
+    }
+
+    /// Execute the list request against a client, returning a single page
+    pub async fn execute(self, client: &Client) -> Result<ListResponse, Error> {
+        client.list(self).await
+    }
+
+    /// The key from which `paginate` needs entries
+    ///
+    /// Keys sorting before it are outside the prefix, or at or before
+    /// `start_after` or the position recorded in the page token, so backends
+    /// with ordered storage can seek to it instead of scanning the prefix.
+    pub fn paginate_from(&self) -> &str {
+        let resume = self.page_token.as_deref().and_then(|token| {
+            token
+                .strip_prefix(KEY_TOKEN)
+                .or_else(|| token.strip_prefix(PREFIX_TOKEN))
+        });
+        [self.start_after.as_deref(), resume]
+            .into_iter()
+            .flatten()
+            .fold(self.prefix.as_str(), Ord::max)
+    }
+
+    /// Build a page of results from entries sorted by key
+    ///
+    /// Helper for backends without native listing: `entries` yields
+    /// `(key, value)` pairs in lexicographic key order, and `summarize` turns
+    /// a value into an ObjectSummary (or `None` to skip it). It is called for
+    /// the objects that end up in the page, and for the first object after a
+    /// full page, to find whether another page follows.
+    pub fn paginate<K: AsRef<str>, V>(
+        &self,
+        entries: impl IntoIterator<Item = (K, V)>,
+        mut summarize: impl FnMut(&str, V) -> Result<Option<ObjectSummary>, Error>,
+    ) -> Result<ListResponse, Error> {
+        let max_results = self.max_results.unwrap_or(DEFAULT_MAX_RESULTS).max(1);
+
+        let (resume_key, resume_prefix) = match self.page_token.as_deref() {
+            None => (None, None),
+            Some(token) => match (
+                token.strip_prefix(KEY_TOKEN),
+                token.strip_prefix(PREFIX_TOKEN),
+            ) {
+                (Some(key), _) => (Some(key), None),
+                (_, Some(prefix)) => (None, Some(prefix)),
+                _ => {
+                    return Err(Error::InvalidRequest {
+                        message: format!("invalid page token: {token}"),
+                    });
+                }
+            },
+        };
+
+        enum Entry<'a> {
+            Object(ObjectSummary),
+            Prefix(&'a str),
+        }
+
+        let mut response = ListResponse::default();
+        let mut emitted = 0;
+        let mut last_token = None;
+        for (key, value) in entries {
+            let key = key.as_ref();
+            if !key.starts_with(&self.prefix) {
+                if key > self.prefix.as_str() {
+                    break;
+                }
+                continue;
+            }
+            if self.start_after.as_deref().is_some_and(|s| key <= s)
+                || resume_key.is_some_and(|s| key <= s)
+                || resume_prefix.is_some_and(|p| key <= p || key.starts_with(p))
+            {
+                continue;
+            }
+
+            // Roll up keys containing the delimiter after the prefix
+            let common_prefix = self.delimiter.and_then(|delimiter| {
+                let rest = &key[self.prefix.len()..];
+                rest.find(delimiter)
+                    .map(|pos| &key[..self.prefix.len() + pos + delimiter.len_utf8()])
+            });
+            if let Some(common_prefix) = common_prefix
+                && response.common_prefixes.last().map(String::as_str) == Some(common_prefix)
+            {
+                continue;
+            }
+
+            let entry = match common_prefix {
+                Some(common_prefix) => Entry::Prefix(common_prefix),
+                None => match summarize(key, value)? {
+                    Some(object) => Entry::Object(object),
+                    None => continue,
+                },
+            };
+
+            if emitted == max_results {
+                response.next_page_token = last_token;
+                break;
+            }
+
+            emitted += 1;
+            match entry {
+                Entry::Prefix(common_prefix) => {
+                    last_token = Some(format!("{PREFIX_TOKEN}{common_prefix}"));
+                    response.common_prefixes.push(common_prefix.to_string());
+                }
+                Entry::Object(object) => {
+                    last_token = Some(format!("{KEY_TOKEN}{key}"));
+                    response.objects.push(object);
+                }
+            }
+        }
+
+        Ok(response)
+    }
+
+    /// Walk all pages of the listing, starting from this request
+    pub fn into_stream(
+        self,
+        client: &Client,
+    ) -> impl Stream<Item = Result<ListResponse, Error>> + Send + 'static {
+        let client = client.clone();
+        futures::stream::try_unfold(Some(self), move |request| {
+            let client = client.clone();
+            async move {
+                let Some(request) = request else {
+                    return Ok(None);
+                };
+
+                let mut next = request.clone();
+                let response = client.list(request).await?;
+                next.page_token = response.next_page_token.clone();
+                let next = next.page_token.is_some().then_some(next);
+                Ok(Some((response, next)))
+            }
+        })
+    }
+}
+
+/// Summary of an object returned by a list operation
+#[derive(Debug, Clone)]
+pub struct ObjectSummary {
+    /// The key of the object
+    pub key: Path,
+    /// The version of the object
+    pub version: Version,
+    /// The size of the object in bytes
+    pub size: u64,
+    /// Metadata associated with the object
+    pub metadata: Metadata,
+}
+
+/// Response from a list operation
+#[derive(Debug, Clone, Default)]
+pub struct ListResponse {
+    /// Objects in this page, in key order
+    pub objects: Vec<ObjectSummary>,
+    /// Common prefixes in this page when a delimiter was set
+    pub common_prefixes: Vec<String>,
+    /// Token for fetching the next page, if there is one
+    pub next_page_token: Option<String>,
+}
+
 /// Trait representing an object store client
 #[async_trait]
 pub trait ObjectStore: Send + Sync {
@@ -333,14 +1132,216 @@ pub trait ObjectStore: Send + Sync {
     /// Returns `None` if the key does not exist
     async fn get(&self, request: GetRequest) -> Result<Option<GetResponse>, Error>;
 
+    /// Execute a head operation
+    ///
+    /// Returns `None` if the key does not exist
+    async fn head(&self, request: HeadRequest) -> Result<Option<HeadResponse>, Error>;
+
     /// Execute a put operation
     async fn put(&self, request: PutRequest) -> Result<PutResponse, Error>;
 
+    /// Execute a get operation, streaming the value
+    ///
+    /// Returns `None` if the key does not exist. The default implementation
+    /// buffers the whole value via `get`.
+    async fn get_stream(&self, request: GetRequest) -> Result<Option<GetStreamResponse>, Error> {
+        Ok(self.get(request).await?.map(GetStreamResponse::from))
+    }
+
+    /// Execute a put operation, streaming the value
+    ///
+    /// The default implementation buffers the whole value and calls `put`.
+    async fn put_stream(&self, request: PutStreamRequest) -> Result<PutResponse, Error> {
+        self.put(PutRequest {
+            key: request.key,
+            value: collect_bytes(request.stream).await?,
+            condition: request.condition,
+            metadata: request.metadata,
+        })
+        .await
+    }
+
     /// Execute a patch operation (update object metadata without touching data)
     async fn patch(&self, request: PatchRequest) -> Result<PatchResponse, Error>;
+
+    /// Execute a delete operation
+    ///
+    /// Returns `Error::NotFound` if the key does not exist
+    async fn delete(&self, request: DeleteRequest) -> Result<DeleteResponse, Error>;
+
+    /// Execute a list operation, returning a single page of results
+    async fn list(&self, request: ListRequest) -> Result<ListResponse, Error>;
+
+    /// Execute a copy operation
+    ///
+    /// Returns `Error::NotFound` if the source (at `source_version`, if set)
+    /// does not exist. The default implementation streams the value through
+    /// the client with `get_stream` and `put_stream`.
+    async fn copy(&self, request: CopyRequest) -> Result<CopyResponse, Error> {
+        let source = self
+            .get_stream(GetRequest {
+                key: request.from,
+                range: None,
+                version: request.source_version,
+                if_version_not_matches: None,
+            })
+            .await?
+            .ok_or(Error::NotFound)?;
+        let response = self
+            .put_stream(PutStreamRequest {
+                key: request.to,
+                stream: source.stream,
+                condition: request.destination_condition,
+                metadata: Some(request.metadata_override.unwrap_or(source.metadata)),
+            })
+            .await?;
+        Ok(CopyResponse {
+            version: response.version,
+        })
+    }
+}
+
+/// Shared stores (including `Client`) are stores too, so wrappers can be
+/// layered on top of any client
+#[async_trait]
+impl<T: ObjectStore + ?Sized> ObjectStore for Arc<T> {
+    async fn get(&self, request: GetRequest) -> Result<Option<GetResponse>, Error> {
+        (**self).get(request).await
+    }
+
+    async fn head(&self, request: HeadRequest) -> Result<Option<HeadResponse>, Error> {
+        (**self).head(request).await
+    }
+
+    async fn put(&self, request: PutRequest) -> Result<PutResponse, Error> {
+        (**self).put(request).await
+    }
+
+    async fn get_stream(&self, request: GetRequest) -> Result<Option<GetStreamResponse>, Error> {
+        (**self).get_stream(request).await
+    }
+
+    async fn put_stream(&self, request: PutStreamRequest) -> Result<PutResponse, Error> {
+        (**self).put_stream(request).await
+    }
+
+    async fn patch(&self, request: PatchRequest) -> Result<PatchResponse, Error> {
+        (**self).patch(request).await
+    }
+
+    async fn delete(&self, request: DeleteRequest) -> Result<DeleteResponse, Error> {
+        (**self).delete(request).await
+    }
+
+    async fn list(&self, request: ListRequest) -> Result<ListResponse, Error> {
+        (**self).list(request).await
+    }
+
+    async fn copy(&self, request: CopyRequest) -> Result<CopyResponse, Error> {
+        (**self).copy(request).await
+    }
 }
 
 /// Type alias for the object store client
 ///
 /// Users should use this type to interact with the object store
 pub type Client = Arc<dyn ObjectStore>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retryable_errors() {
+        let retryable = [
+            Error::RateLimited { retry_after: None },
+            Error::Timeout { source: None },
+            Error::Transient {
+                message: "reset".into(),
+                source: None,
+            },
+            Error::Backend {
+                status: 408,
+                body: String::new(),
+            },
+            Error::Backend {
+                status: 503,
+                body: String::new(),
+            },
+        ];
+        for error in retryable {
+            assert!(error.is_retryable(), "{error:?}");
+        }
+
+        let permanent = [
+            Error::ConditionFailed {
+                condition: Condition::IfAbsent,
+            },
+            Error::NotFound,
+            Error::NotModified,
+            Error::RangeNotSatisfiable,
+            Error::Unauthorized {
+                message: String::new(),
+                source: None,
+            },
+            Error::PermissionDenied {
+                message: String::new(),
+            },
+            Error::InvalidRequest {
+                message: String::new(),
+            },
+            Error::Backend {
+                status: 409,
+                body: String::new(),
+            },
+            Error::Other("other".into()),
+        ];
+        for error in permanent {
+            assert!(!error.is_retryable(), "{error:?}");
+        }
+    }
+
+    #[test]
+    fn test_range_header_values() {
+        let bounded = |offset, length| GetRange::Bounded { offset, length };
+        assert_eq!(bounded(0, 1).header_value().unwrap(), "bytes=0-0");
+        assert_eq!(bounded(5, 10).header_value().unwrap(), "bytes=5-14");
+        assert_eq!(GetRange::From(3).header_value().unwrap(), "bytes=3-");
+        assert_eq!(GetRange::Suffix(4).header_value().unwrap(), "bytes=-4");
+        for empty in [bounded(0, 0), bounded(5, 0), GetRange::Suffix(0)] {
+            assert!(matches!(
+                empty.header_value(),
+                Err(Error::RangeNotSatisfiable)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_errors_from_status() {
+        let error = |status| Error::from_status(status, "body".into(), None);
+        assert!(matches!(error(400), Error::InvalidRequest { message } if message == "body"));
+        assert!(matches!(error(401), Error::Unauthorized { .. }));
+        assert!(matches!(error(403), Error::PermissionDenied { .. }));
+        assert!(matches!(error(408), Error::Timeout { .. }));
+        for status in [500, 502, 503, 504] {
+            assert!(matches!(error(status), Error::Transient { .. }));
+        }
+        assert!(matches!(
+            error(409),
+            Error::Backend { status: 409, body } if body == "body"
+        ));
+        assert!(matches!(error(501), Error::Backend { status: 501, .. }));
+
+        let retry_after = Some(Duration::from_secs(3));
+        assert!(matches!(
+            Error::from_status(429, String::new(), retry_after),
+            Error::RateLimited { retry_after: Some(d) } if d == Duration::from_secs(3)
+        ));
+
+        // Every mapped error is retryable exactly when its status is
+        for status in 400..600 {
+            let retryable = matches!(status, 408 | 429 | 500..=599);
+            assert_eq!(error(status).is_retryable(), retryable, "status {status}");
+        }
+    }
+}
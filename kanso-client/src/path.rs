@@ -0,0 +1,521 @@
+//! Validated object paths and path prefixes
+
+use thiserror::Error;
+
+/// Error type for path validation
+#[derive(Debug, Error)]
+pub enum PathError {
+    #[error("path cannot have leading or trailing slashes")]
+    LeadingTrailingSlash,
+
+    #[error("path cannot contain empty segments")]
+    EmptySegment,
+
+    #[error("path cannot contain relative segments (. or ..)")]
+    RelativeSegment,
+
+    #[error("path cannot contain ASCII control characters")]
+    ControlCharacter,
+
+    #[error("path cannot be empty")]
+    Empty,
+
+    #[error("path segment is not validly percent-encoded")]
+    InvalidEncoding,
+}
+
+/// Check a string of `/`-delimited segments against the Path invariants
+fn validate(s: &str) -> Result<(), PathError> {
+    // Empty check
+    if s.is_empty() {
+        return Err(PathError::Empty);
+    }
+
+    // Leading/trailing slash check
+    if s.starts_with('/') || s.ends_with('/') {
+        return Err(PathError::LeadingTrailingSlash);
+    }
+
+    // Validate each segment
+    for segment in s.split('/') {
+        if segment.is_empty() {
+            return Err(PathError::EmptySegment);
+        }
+        if segment == "." || segment == ".." {
+            return Err(PathError::RelativeSegment);
+        }
+        if segment.chars().any(|c| c.is_ascii_control()) {
+            return Err(PathError::ControlCharacter);
+        }
+    }
+    Ok(())
+}
+
+/// Percent-encode the characters a segment cannot contain
+///
+/// `%`, `/` and ASCII control characters are always escaped, as are the dots
+/// of a `.` or `..` segment, so the result is a valid segment unless empty.
+fn encode_segment(segment: &str) -> String {
+    let relative = segment == "." || segment == "..";
+    let mut encoded = String::with_capacity(segment.len());
+    for c in segment.chars() {
+        if c == '%' || c == '/' || c.is_ascii_control() || (relative && c == '.') {
+            encoded.push_str(&format!("%{:02X}", c as u8));
+        } else {
+            encoded.push(c);
+        }
+    }
+    encoded
+}
+
+/// Reverse `encode_segment`, decoding every `%XX` escape
+fn decode_segment(segment: &str) -> Result<String, PathError> {
+    let mut bytes = Vec::with_capacity(segment.len());
+    let mut rest = segment.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = tail.get(..2).ok_or(PathError::InvalidEncoding)?;
+            let hex = std::str::from_utf8(hex).map_err(|_| PathError::InvalidEncoding)?;
+            bytes.push(u8::from_str_radix(hex, 16).map_err(|_| PathError::InvalidEncoding)?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| PathError::InvalidEncoding)
+}
+
+/// Represents a validated path in the object store
+///
+/// A Path maintains the following invariants:
+/// - Paths are delimited by `/`
+/// - Paths do not contain leading or trailing `/`
+/// - Paths do not contain relative path segments (`.` or `..`)
+/// - Paths do not contain empty path segments
+/// - Paths do not contain any ASCII control characters
+///
+/// Every operation that produces a Path preserves these invariants.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Path(String);
+
+impl Path {
+    /// Create a new path with validation
+    pub fn new(s: impl AsRef<str>) -> Result<Self, PathError> {
+        let s = s.as_ref();
+        validate(s)?;
+        Ok(Self(s.to_string()))
+    }
+
+    /// Build a path from arbitrary segments, percent-encoding the characters
+    /// Path invariants disallow
+    ///
+    /// Unlike `new`, this accepts user-provided names such as `a/b`, `..` or
+    /// names with newlines, each becoming a single segment. `%` is escaped as
+    /// well, so `decode_segments` recovers the original segments exactly.
+    /// Returns a PathError if there are no segments or one is empty, as an
+    /// empty segment has no encoding.
+    pub fn from_segments_encoded<I>(segments: I) -> Result<Self, PathError>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let mut path = String::new();
+        for segment in segments {
+            let segment = segment.as_ref();
+            if segment.is_empty() {
+                return Err(PathError::EmptySegment);
+            }
+            if !path.is_empty() {
+                path.push('/');
+            }
+            path.push_str(&encode_segment(segment));
+        }
+        if path.is_empty() {
+            return Err(PathError::Empty);
+        }
+        Ok(Self(path))
+    }
+
+    /// The segments of a path built by `from_segments_encoded`, with their
+    /// percent-encoding reversed
+    ///
+    /// Returns a PathError if a `%` isn't followed by two hex digits, or the
+    /// decoded bytes aren't UTF-8.
+    pub fn decode_segments(&self) -> Result<Vec<String>, PathError> {
+        self.segments().map(decode_segment).collect()
+    }
+
+    /// Get the path as a string slice
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The `/`-delimited segments of the path
+    pub fn segments(&self) -> impl Iterator<Item = &str> {
+        self.0.split('/')
+    }
+
+    /// Append a relative path of one or more segments
+    ///
+    /// Returns a PathError if the appended path doesn't satisfy Path invariants
+    pub fn join(&self, path: impl AsRef<str>) -> Result<Path, PathError> {
+        let path = path.as_ref();
+        validate(path)?;
+        Ok(Self(format!("{}/{path}", self.0)))
+    }
+
+    /// The path without its last segment, or `None` for a single segment
+    pub fn parent(&self) -> Option<Path> {
+        self.0
+            .rsplit_once('/')
+            .map(|(parent, _)| Self(parent.to_string()))
+    }
+
+    /// The last segment of the path
+    pub fn file_name(&self) -> &str {
+        self.0.rsplit_once('/').map_or(&self.0, |(_, name)| name)
+    }
+
+    /// The part of the last segment after its last `.`, unless that `.`
+    /// starts the segment (as in `.hidden`)
+    pub fn extension(&self) -> Option<&str> {
+        self.file_name()
+            .rsplit_once('.')
+            .filter(|(stem, _)| !stem.is_empty())
+            .map(|(_, extension)| extension)
+    }
+
+    /// Whether the path is the prefix or lies below it, comparing whole
+    /// segments (`a/bc` does not start with `a/b`)
+    pub fn starts_with(&self, prefix: &PathPrefix) -> bool {
+        self.rest(prefix).is_some()
+    }
+
+    /// The rest of the path below a prefix, comparing whole segments
+    ///
+    /// Returns `None` if the path does not lie below the prefix, including
+    /// when it equals the prefix.
+    pub fn strip_prefix(&self, prefix: &PathPrefix) -> Option<Path> {
+        self.rest(prefix)
+            .filter(|rest| !rest.is_empty())
+            .map(|rest| Self(rest.to_string()))
+    }
+
+    /// The part of the path after a prefix and its delimiter, if it starts
+    /// with the prefix
+    fn rest(&self, prefix: &PathPrefix) -> Option<&str> {
+        if prefix.is_root() {
+            return Some(&self.0);
+        }
+        match self.0.strip_prefix(prefix.as_str())? {
+            "" => Some(""),
+            rest => rest.strip_prefix('/'),
+        }
+    }
+}
+
+impl AsRef<str> for Path {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for Path {
+    type Error = PathError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Self::new(s)
+    }
+}
+
+impl TryFrom<&str> for Path {
+    type Error = PathError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        Self::new(s)
+    }
+}
+
+impl std::fmt::Display for Path {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A validated prefix of paths: the root, or the segments of a path
+///
+/// Unlike a Path, a prefix may be empty (the root, under which every path
+/// lies). A single trailing `/` is accepted and dropped, so list-style
+/// prefixes such as `bucket/` can be used directly.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct PathPrefix(String);
+
+impl PathPrefix {
+    /// The empty prefix, under which every path lies
+    pub fn root() -> Self {
+        Self::default()
+    }
+
+    /// Create a new prefix with validation
+    ///
+    /// Returns a PathError if a non-empty prefix (without its trailing `/`)
+    /// doesn't satisfy Path invariants
+    pub fn new(s: impl AsRef<str>) -> Result<Self, PathError> {
+        let s = s.as_ref();
+        if s.is_empty() {
+            return Ok(Self::root());
+        }
+        let s = s.strip_suffix('/').unwrap_or(s);
+        validate(s)?;
+        Ok(Self(s.to_string()))
+    }
+
+    /// Get the prefix as a string slice, empty for the root
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether this is the empty prefix
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The `/`-delimited segments of the prefix (none for the root)
+    pub fn segments(&self) -> impl Iterator<Item = &str> {
+        self.0.split('/').filter(|segment| !segment.is_empty())
+    }
+
+    /// The path of a relative path of one or more segments below the prefix
+    ///
+    /// Returns a PathError if the relative path doesn't satisfy Path invariants
+    pub fn join(&self, path: impl AsRef<str>) -> Result<Path, PathError> {
+        if self.is_root() {
+            Path::new(path)
+        } else {
+            Path(self.0.clone()).join(path)
+        }
+    }
+
+    /// The path of a validated path below the prefix
+    ///
+    /// Unlike `join` this cannot fail: a Path has no relative segments, so
+    /// the result always lies below the prefix.
+    pub fn join_path(&self, path: &Path) -> Path {
+        if self.is_root() {
+            path.clone()
+        } else {
+            Path(format!("{}/{}", self.0, path.0))
+        }
+    }
+
+    /// The prefix to list the paths below it: empty for the root, otherwise
+    /// followed by `/` so that only whole segments match
+    pub fn to_list_prefix(&self) -> String {
+        if self.is_root() {
+            String::new()
+        } else {
+            format!("{}/", self.0)
+        }
+    }
+}
+
+impl From<Path> for PathPrefix {
+    fn from(path: Path) -> Self {
+        Self(path.0)
+    }
+}
+
+impl From<&Path> for PathPrefix {
+    fn from(path: &Path) -> Self {
+        Self(path.0.clone())
+    }
+}
+
+impl TryFrom<&str> for PathPrefix {
+    type Error = PathError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        Self::new(s)
+    }
+}
+
+impl std::fmt::Display for PathPrefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    /// Any segment allowed by `Path::new`
+    fn segment() -> impl Strategy<Value = String> {
+        "[^/\\x00-\\x1f\\x7f]{1,6}".prop_filter("relative segment", |s| s != "." && s != "..")
+    }
+
+    fn path() -> impl Strategy<Value = Path> {
+        prop::collection::vec(segment(), 1..5)
+            .prop_map(|segments| Path::new(segments.join("/")).unwrap())
+    }
+
+    fn prefix() -> impl Strategy<Value = PathPrefix> {
+        prop::collection::vec(segment(), 0..4)
+            .prop_map(|segments| PathPrefix::new(segments.join("/")).unwrap())
+    }
+
+    /// Check that a path satisfies the invariants enforced by `Path::new`
+    fn check_valid(path: &Path) -> Result<(), TestCaseError> {
+        match Path::new(path.as_str()) {
+            Ok(validated) => prop_assert_eq!(&validated, path),
+            Err(e) => prop_assert!(false, "{path:?} is invalid: {e}"),
+        }
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn segments_rebuild_the_path(path in path()) {
+            let segments: Vec<&str> = path.segments().collect();
+            prop_assert_eq!(Path::new(segments.join("/")).unwrap(), path.clone());
+            for segment in segments {
+                prop_assert_eq!(Path::new(segment).unwrap().segments().count(), 1);
+            }
+        }
+
+        #[test]
+        fn join_appends_segments(a in path(), b in path()) {
+            let joined = a.join(&b).unwrap();
+            check_valid(&joined)?;
+            let expected: Vec<&str> = a.segments().chain(b.segments()).collect();
+            prop_assert_eq!(joined.segments().collect::<Vec<_>>(), expected);
+            prop_assert!(joined.starts_with(&PathPrefix::from(&a)));
+            prop_assert_eq!(joined.strip_prefix(&PathPrefix::from(&a)), Some(b));
+        }
+
+        #[test]
+        fn parent_and_file_name_split_the_path(path in path()) {
+            let file_name = path.file_name();
+            prop_assert_eq!(Path::new(file_name).unwrap().segments().count(), 1);
+            match path.parent() {
+                Some(parent) => {
+                    check_valid(&parent)?;
+                    prop_assert_eq!(parent.join(file_name).unwrap(), path.clone());
+                    prop_assert!(path.starts_with(&parent.into()));
+                }
+                None => prop_assert_eq!(file_name, path.as_str()),
+            }
+        }
+
+        #[test]
+        fn starts_with_compares_whole_segments(a in path(), b in path()) {
+            let a_segments: Vec<&str> = a.segments().collect();
+            let b_segments: Vec<&str> = b.segments().collect();
+            prop_assert_eq!(
+                a.starts_with(&PathPrefix::from(&b)),
+                a_segments.starts_with(&b_segments)
+            );
+            prop_assert!(a.starts_with(&PathPrefix::from(&a)));
+            prop_assert_eq!(a.strip_prefix(&PathPrefix::from(&a)), None);
+        }
+
+        #[test]
+        fn prefixes_join_and_strip(prefix in prefix(), path in path()) {
+            let joined = prefix.join(&path).unwrap();
+            check_valid(&joined)?;
+            prop_assert_eq!(prefix.join_path(&path), joined.clone());
+            prop_assert!(joined.starts_with(&prefix));
+            prop_assert_eq!(joined.strip_prefix(&prefix), Some(path));
+            prop_assert!(joined.as_str().starts_with(&prefix.to_list_prefix()));
+            prop_assert_eq!(PathPrefix::new(prefix.to_list_prefix()).unwrap(), prefix);
+        }
+
+        #[test]
+        fn extension_ends_the_file_name(path in path()) {
+            if let Some(extension) = path.extension() {
+                prop_assert!(!extension.contains('.'));
+                let file_name = path.file_name();
+                let stem = &file_name[..file_name.len() - extension.len() - 1];
+                prop_assert!(!stem.is_empty());
+                prop_assert_eq!(format!("{stem}.{extension}"), file_name);
+            }
+        }
+
+        #[test]
+        fn encoded_segments_round_trip(segments in prop::collection::vec("(?s).{1,6}", 1..5)) {
+            let path = Path::from_segments_encoded(&segments).unwrap();
+            check_valid(&path)?;
+            prop_assert_eq!(path.segments().count(), segments.len());
+            prop_assert_eq!(path.decode_segments().unwrap(), segments);
+        }
+
+        #[test]
+        fn encoding_keeps_valid_segments_without_percent(segments in prop::collection::vec(segment(), 1..5)) {
+            let path = Path::from_segments_encoded(&segments).unwrap();
+            if segments.iter().all(|segment| !segment.contains('%')) {
+                prop_assert_eq!(path.as_str(), segments.join("/"));
+            }
+            prop_assert_eq!(path.decode_segments().unwrap(), segments);
+        }
+    }
+
+    #[test]
+    fn test_encoded_segments() {
+        let path = Path::from_segments_encoded(["a/b", "..", "50%", "line\nbreak", "é"]).unwrap();
+        assert_eq!(path.as_str(), "a%2Fb/%2E%2E/50%25/line%0Abreak/é");
+        assert_eq!(
+            path.decode_segments().unwrap(),
+            ["a/b", "..", "50%", "line\nbreak", "é"]
+        );
+        assert_eq!(
+            Path::from_segments_encoded(["..."]).unwrap().as_str(),
+            "..."
+        );
+
+        assert!(matches!(
+            Path::from_segments_encoded(["a", ""]),
+            Err(PathError::EmptySegment)
+        ));
+        assert!(matches!(
+            Path::from_segments_encoded(Vec::<String>::new()),
+            Err(PathError::Empty)
+        ));
+        for invalid in ["100%", "%2", "%zz", "%FF"] {
+            assert!(matches!(
+                Path::new(invalid).unwrap().decode_segments(),
+                Err(PathError::InvalidEncoding)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_operations_reject_invalid_input() {
+        let path = Path::new("a/b").unwrap();
+        for invalid in ["", "/c", "c/", "c//d", "..", "c/./d", "c\nd"] {
+            assert!(path.join(invalid).is_err(), "joined {invalid:?}");
+            assert!(PathPrefix::root().join(invalid).is_err());
+        }
+        assert!(PathPrefix::new("a//").is_err());
+        assert!(PathPrefix::new("/a").is_err());
+    }
+
+    #[test]
+    fn test_examples() {
+        let path = Path::new("data/2024/report.tar.gz").unwrap();
+        assert_eq!(path.file_name(), "report.tar.gz");
+        assert_eq!(path.extension(), Some("gz"));
+        assert_eq!(path.parent().unwrap().as_str(), "data/2024");
+        assert_eq!(Path::new("data/.hidden").unwrap().extension(), None);
+        assert!(!path.starts_with(&PathPrefix::new("data/20").unwrap()));
+        assert_eq!(
+            path.strip_prefix(&PathPrefix::new("data/").unwrap())
+                .unwrap()
+                .as_str(),
+            "2024/report.tar.gz"
+        );
+        assert_eq!(PathPrefix::root().to_list_prefix(), "");
+    }
+}
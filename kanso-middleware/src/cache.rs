@@ -0,0 +1,579 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use kanso_client::{
+    Condition, CopyRequest, CopyResponse, DeleteRequest, DeleteResponse, Error, GetRequest,
+    GetResponse, GetStreamResponse, HeadRequest, HeadResponse, ListRequest, ListResponse,
+    ObjectStore, PatchRequest, PatchResponse, Path, PutRequest, PutResponse, PutStreamRequest,
+    Version,
+};
+
+/// Bounds and freshness of the entries kept by a CachingStore
+#[derive(Debug, Clone)]
+pub struct CachePolicy {
+    /// Maximum number of cached objects
+    pub max_entries: usize,
+    /// Maximum total size of cached values; larger objects are never cached
+    pub max_bytes: usize,
+    /// How long an entry is served without revalidating it
+    pub ttl: Duration,
+}
+
+impl CachePolicy {
+    /// Create a policy with 1024 entries, 64 MiB and a 1s TTL
+    pub fn new() -> Self {
+        Self {
+            max_entries: 1024,
+            max_bytes: 64 * 1024 * 1024,
+            ttl: Duration::from_secs(1),
+        }
+    }
+
+    /// Set the maximum number of cached objects
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    /// Set the maximum total size of cached values
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Set how long an entry is served without revalidating it
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Whether a value of this many bytes can be cached at all
+    fn admits(&self, size: usize) -> bool {
+        size <= self.max_bytes && self.max_entries > 0
+    }
+}
+
+impl Default for CachePolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// ObjectStore wrapper that caches whole-object reads in an LRU
+///
+/// Entries younger than the TTL are served without contacting the store.
+/// Older entries are revalidated with a get conditioned on the version being
+/// different (`if_version_not_matches`): an unchanged object is not downloaded
+/// again and the cached value is served (and the entry is fresh again),
+/// otherwise the new value replaces it. Ranged reads and reads of the cached
+/// version are served from fresh entries, but only whole-object reads of the
+/// latest version populate the cache.
+///
+/// Writes through the wrapper refresh (put, patch) or drop (streaming put,
+/// delete, failed writes) the entry for their key, so a reader sees its own
+/// writes. Refreshed entries take their metadata from a head request, since
+/// stores may not keep metadata exactly as written. Writes by other clients
+/// become visible once the entry is revalidated.
+pub struct CachingStore<S> {
+    inner: S,
+    policy: CachePolicy,
+    state: Mutex<CacheState>,
+}
+
+struct Entry {
+    response: GetResponse,
+    validated: Instant,
+    /// Position in the LRU order
+    tick: u64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<Path, Entry>,
+    /// Keys by last use, least recent first
+    order: BTreeMap<u64, Path>,
+    bytes: usize,
+    tick: u64,
+    /// Bumped by every write, so reads racing with a write don't cache the
+    /// value they read before it
+    writes: u64,
+}
+
+impl CacheState {
+    fn remove(&mut self, key: &Path) -> Option<Entry> {
+        let entry = self.entries.remove(key)?;
+        self.order.remove(&entry.tick);
+        self.bytes -= entry.response.value.len();
+        Some(entry)
+    }
+
+    fn insert(&mut self, policy: &CachePolicy, key: Path, response: GetResponse) {
+        self.remove(&key);
+        let size = response.value.len();
+        if !policy.admits(size) {
+            return;
+        }
+        while self.entries.len() >= policy.max_entries || self.bytes + size > policy.max_bytes {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            self.remove(&oldest);
+        }
+
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        self.bytes += size;
+        self.entries.insert(
+            key,
+            Entry {
+                response,
+                validated: Instant::now(),
+                tick: self.tick,
+            },
+        );
+    }
+
+    /// Look up an entry, marking it as most recently used
+    fn touch(&mut self, key: &Path) -> Option<&mut Entry> {
+        let entry = self.entries.get_mut(key)?;
+        self.order.remove(&entry.tick);
+        self.tick += 1;
+        entry.tick = self.tick;
+        self.order.insert(self.tick, key.clone());
+        Some(entry)
+    }
+}
+
+/// Serve a read from a cached object
+fn serve(request: &GetRequest, cached: &GetResponse) -> Result<Option<GetResponse>, Error> {
+    if request
+        .version
+        .as_ref()
+        .is_some_and(|v| *v != cached.version)
+    {
+        return Ok(None);
+    }
+    if request.if_version_not_matches.as_ref() == Some(&cached.version) {
+        return Err(Error::NotModified);
+    }
+    let mut response = cached.clone();
+    if let Some(range) = request.range {
+        let range = range.resolve(cached.size)?;
+        response.value = cached.value.slice(range.start as usize..range.end as usize);
+    }
+    Ok(Some(response))
+}
+
+impl<S: ObjectStore> CachingStore<S> {
+    /// Wrap a store with the default cache policy
+    pub fn new(inner: S) -> Self {
+        Self::with_policy(inner, CachePolicy::default())
+    }
+
+    /// Wrap a store with a custom cache policy
+    pub fn with_policy(inner: S, policy: CachePolicy) -> Self {
+        Self {
+            inner,
+            policy,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Get a reference to the wrapped store
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Drop the cached entry for a key
+    pub fn invalidate(&self, key: &Path) {
+        let mut state = self.state.lock().unwrap();
+        state.writes += 1;
+        state.remove(key);
+    }
+
+    /// A fresh cached copy of an object, or a stale one to revalidate
+    fn lookup(&self, key: &Path) -> Option<(GetResponse, bool)> {
+        let mut state = self.state.lock().unwrap();
+        let ttl = self.policy.ttl;
+        let entry = state.touch(key)?;
+        Some((entry.response.clone(), entry.validated.elapsed() < ttl))
+    }
+
+    /// Read the latest version of an object, through the cache
+    async fn read(&self, key: &Path) -> Result<Option<GetResponse>, Error> {
+        let cached = match self.lookup(key) {
+            Some((cached, true)) => return Ok(Some(cached)),
+            Some((cached, false)) => Some(cached),
+            None => None,
+        };
+
+        let writes = self.state.lock().unwrap().writes;
+        let result = self
+            .inner
+            .get(GetRequest {
+                key: key.clone(),
+                range: None,
+                version: None,
+                if_version_not_matches: cached.as_ref().map(|cached| cached.version.clone()),
+            })
+            .await;
+        let mut state = self.state.lock().unwrap();
+        match (result, cached) {
+            (Err(Error::NotModified), Some(cached)) => {
+                if let Some(entry) = state.entries.get_mut(key)
+                    && entry.response.version == cached.version
+                {
+                    entry.validated = Instant::now();
+                }
+                Ok(Some(cached))
+            }
+            (Ok(Some(response)), _) => {
+                if state.writes == writes {
+                    state.insert(&self.policy, key.clone(), response.clone());
+                }
+                Ok(Some(response))
+            }
+            (Ok(None), _) => {
+                state.remove(key);
+                Ok(None)
+            }
+            (Err(error), _) => Err(error),
+        }
+    }
+
+    /// The object a successful write of `value` produced, with its metadata
+    /// as the store reports it
+    ///
+    /// Returns `None` if the object cannot be read or has changed since, or
+    /// without reading it if it is too large to cache.
+    async fn written_object(
+        &self,
+        key: &Path,
+        value: Bytes,
+        version: &Version,
+    ) -> Option<GetResponse> {
+        if !self.policy.admits(value.len()) {
+            return None;
+        }
+        let head = self
+            .inner
+            .head(HeadRequest { key: key.clone() })
+            .await
+            .ok()??;
+        (head.version == *version && head.size == value.len() as u64).then(|| GetResponse {
+            value,
+            version: head.version,
+            metadata: head.metadata,
+            size: head.size,
+        })
+    }
+
+    /// Record a write, caching the object it produced or dropping the entry
+    /// if that is unknown (including when the write failed)
+    fn written(&self, key: &Path, cached: Option<GetResponse>) {
+        let mut state = self.state.lock().unwrap();
+        state.writes += 1;
+        match cached {
+            Some(response) => state.insert(&self.policy, key.clone(), response),
+            None => {
+                state.remove(key);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<S: ObjectStore> ObjectStore for CachingStore<S> {
+    async fn get(&self, request: GetRequest) -> Result<Option<GetResponse>, Error> {
+        if request.range.is_none() && request.version.is_none() {
+            return match self.read(&request.key).await? {
+                Some(response)
+                    if request.if_version_not_matches.as_ref() == Some(&response.version) =>
+                {
+                    Err(Error::NotModified)
+                }
+                response => Ok(response),
+            };
+        }
+        if let Some((cached, true)) = self.lookup(&request.key)
+            && request
+                .version
+                .as_ref()
+                .is_none_or(|v| *v == cached.version)
+        {
+            return serve(&request, &cached);
+        }
+        self.inner.get(request).await
+    }
+
+    async fn get_stream(&self, request: GetRequest) -> Result<Option<GetStreamResponse>, Error> {
+        if let Some((cached, true)) = self.lookup(&request.key)
+            && request
+                .version
+                .as_ref()
+                .is_none_or(|v| *v == cached.version)
+        {
+            return Ok(serve(&request, &cached)?.map(GetStreamResponse::from));
+        }
+        self.inner.get_stream(request).await
+    }
+
+    async fn head(&self, request: HeadRequest) -> Result<Option<HeadResponse>, Error> {
+        self.inner.head(request).await
+    }
+
+    async fn put(&self, request: PutRequest) -> Result<PutResponse, Error> {
+        let key = request.key.clone();
+        let value = request.value.clone();
+        let result = self.inner.put(request).await;
+        let cached = match &result {
+            Ok(response) => self.written_object(&key, value, &response.version).await,
+            Err(_) => None,
+        };
+        self.written(&key, cached);
+        result
+    }
+
+    async fn put_stream(&self, request: PutStreamRequest) -> Result<PutResponse, Error> {
+        let key = request.key.clone();
+        let result = self.inner.put_stream(request).await;
+        self.written(&key, None);
+        result
+    }
+
+    async fn patch(&self, request: PatchRequest) -> Result<PatchResponse, Error> {
+        let key = request.key.clone();
+        // Only a patch conditioned on the cached version is known to have
+        // kept the cached value
+        let previous = match &request.condition {
+            Some(Condition::IfVersionMatches(version)) => self
+                .lookup(&key)
+                .map(|(cached, _)| cached)
+                .filter(|cached| cached.version == *version),
+            _ => None,
+        };
+        let result = self.inner.patch(request).await;
+
+        let cached = match (&result, previous) {
+            (Ok(response), Some(previous)) => {
+                self.written_object(&key, previous.value, &response.version)
+                    .await
+            }
+            _ => None,
+        };
+        self.written(&key, cached);
+        result
+    }
+
+    async fn delete(&self, request: DeleteRequest) -> Result<DeleteResponse, Error> {
+        let key = request.key.clone();
+        let result = self.inner.delete(request).await;
+        self.written(&key, None);
+        result
+    }
+
+    async fn list(&self, request: ListRequest) -> Result<ListResponse, Error> {
+        self.inner.list(request).await
+    }
+
+    async fn copy(&self, request: CopyRequest) -> Result<CopyResponse, Error> {
+        let key = request.to.clone();
+        let result = self.inner.copy(request).await;
+        self.written(&key, None);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use kanso_client::{Client, GetRange, Metadata};
+    use kanso_inmemory::InMemoryStore;
+
+    use super::*;
+    use crate::{Fault, FaultyStore, Operation};
+
+    fn store(policy: CachePolicy) -> (Client, CachingStore<Client>) {
+        let inner: Client = Arc::new(InMemoryStore::new());
+        (inner.clone(), CachingStore::with_policy(inner, policy))
+    }
+
+    fn put(key: &str, value: &'static str) -> PutRequest {
+        PutRequest::new(key, Bytes::from(value)).unwrap()
+    }
+
+    async fn value(store: &impl ObjectStore, key: &str) -> Option<Bytes> {
+        let response = store.get(GetRequest::new(key).unwrap()).await.unwrap();
+        response.map(|response| response.value)
+    }
+
+    /// A store that adds an entry to the metadata of every write, as stores
+    /// that normalize or extend metadata do
+    struct Stamping(InMemoryStore);
+
+    #[async_trait]
+    impl ObjectStore for Stamping {
+        async fn get(&self, request: GetRequest) -> Result<Option<GetResponse>, Error> {
+            self.0.get(request).await
+        }
+
+        async fn head(&self, request: HeadRequest) -> Result<Option<HeadResponse>, Error> {
+            self.0.head(request).await
+        }
+
+        async fn put(&self, mut request: PutRequest) -> Result<PutResponse, Error> {
+            let mut metadata = request.metadata.unwrap_or_default();
+            metadata.insert("stamped", "yes");
+            request.metadata = Some(metadata);
+            self.0.put(request).await
+        }
+
+        async fn patch(&self, mut request: PatchRequest) -> Result<PatchResponse, Error> {
+            request.metadata.insert("stamped", "yes");
+            self.0.patch(request).await
+        }
+
+        async fn delete(&self, request: DeleteRequest) -> Result<DeleteResponse, Error> {
+            self.0.delete(request).await
+        }
+
+        async fn list(&self, request: ListRequest) -> Result<ListResponse, Error> {
+            self.0.list(request).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_compliance() {
+        let (_, cache) = store(CachePolicy::new());
+        let cache: Client = Arc::new(cache);
+        kanso_backends_test_suite::run_compliance_tests(&cache, "").await;
+    }
+
+    #[tokio::test]
+    async fn test_fresh_entries_skip_the_store_until_revalidated() {
+        let hour = Duration::from_secs(3600);
+        let (inner, cache) = store(CachePolicy::new().ttl(hour));
+        cache.put(put("key", "v1")).await.unwrap();
+
+        // A write by another client is not seen while the entry is fresh
+        inner.put(put("key", "v2")).await.unwrap();
+        assert_eq!(value(&cache, "key").await.unwrap(), "v1");
+        let range = GetRequest::new("key").unwrap().range(GetRange::From(1));
+        assert_eq!(cache.get(range).await.unwrap().unwrap().value, "1");
+
+        // With a zero TTL every read revalidates
+        let (inner, cache) = store(CachePolicy::new().ttl(Duration::ZERO));
+        let v1 = cache.put(put("key", "v1")).await.unwrap().version;
+        assert_eq!(value(&cache, "key").await.unwrap(), "v1");
+        let unchanged = GetRequest::new("key").unwrap().if_version_not_matches(v1);
+        assert!(matches!(
+            cache.get(unchanged.clone()).await,
+            Err(Error::NotModified)
+        ));
+        inner.put(put("key", "v2")).await.unwrap();
+        assert_eq!(value(&cache, "key").await.unwrap(), "v2");
+        assert_eq!(cache.get(unchanged).await.unwrap().unwrap().value, "v2");
+        inner
+            .delete(DeleteRequest::new("key").unwrap())
+            .await
+            .unwrap();
+        assert_eq!(value(&cache, "key").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_reads_see_own_writes() {
+        let hour = Duration::from_secs(3600);
+        let (_, cache) = store(CachePolicy::new().ttl(hour));
+
+        let v1 = cache.put(put("key", "v1")).await.unwrap().version;
+        assert_eq!(value(&cache, "key").await.unwrap(), "v1");
+
+        let patch = PatchRequest::new("key", Metadata::with("k", "v"))
+            .unwrap()
+            .if_version_matches(v1);
+        let v2 = cache.patch(patch).await.unwrap().version;
+        let response = cache
+            .get(GetRequest::new("key").unwrap())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(response.version, v2);
+        assert_eq!(response.value, "v1");
+        assert_eq!(response.metadata.get("k").unwrap(), "v");
+
+        // A failed conditional write drops the entry rather than trusting it
+        let stale = put("key", "x").if_version_matches(Version::new("0"));
+        assert!(cache.put(stale).await.is_err());
+        assert_eq!(value(&cache, "key").await.unwrap(), "v1");
+
+        cache
+            .delete(DeleteRequest::new("key").unwrap())
+            .await
+            .unwrap();
+        assert_eq!(value(&cache, "key").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_writes_cache_the_stored_metadata() {
+        let hour = Duration::from_secs(3600);
+        let cache =
+            CachingStore::with_policy(Stamping(InMemoryStore::new()), CachePolicy::new().ttl(hour));
+
+        let v1 = cache
+            .put(put("key", "v1").metadata(Metadata::with("k", "v1")))
+            .await
+            .unwrap()
+            .version;
+        let response = cache.get(GetRequest::new("key").unwrap()).await.unwrap();
+        let metadata = response.unwrap().metadata;
+        assert_eq!(metadata.get("k").unwrap(), "v1");
+        assert_eq!(metadata.get("stamped").unwrap(), "yes");
+
+        let patch = PatchRequest::new("key", Metadata::with("k", "v2"))
+            .unwrap()
+            .if_version_matches(v1);
+        let v2 = cache.patch(patch).await.unwrap().version;
+        let response = cache.get(GetRequest::new("key").unwrap()).await.unwrap();
+        let response = response.unwrap();
+        assert_eq!(response.version, v2);
+        assert_eq!(response.metadata.get("k").unwrap(), "v2");
+        assert_eq!(response.metadata.get("stamped").unwrap(), "yes");
+    }
+
+    #[tokio::test]
+    async fn test_oversized_writes_skip_the_metadata_read() {
+        let faulty = Arc::new(FaultyStore::new(Arc::new(InMemoryStore::new()), 0));
+        let cache = CachingStore::with_policy(faulty.clone(), CachePolicy::new().max_bytes(4));
+        faulty.script(Operation::Head, [Some(Fault::Latency(Duration::ZERO))]);
+
+        cache.put(put("large", "too large")).await.unwrap();
+        assert!(faulty.log().is_empty());
+
+        cache.put(put("small", "v1")).await.unwrap();
+        assert_eq!(faulty.log().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_entries_are_evicted_least_recently_used_first() {
+        let hour = Duration::from_secs(3600);
+        let (inner, cache) = store(CachePolicy::new().ttl(hour).max_entries(2).max_bytes(4));
+        for key in ["a", "b"] {
+            cache.put(put(key, "v1")).await.unwrap();
+            inner.put(put(key, "v2")).await.unwrap();
+        }
+
+        // Using "a" makes "b" the least recently used entry
+        assert_eq!(value(&cache, "a").await.unwrap(), "v1");
+        cache.put(put("c", "v1")).await.unwrap();
+        assert_eq!(value(&cache, "b").await.unwrap(), "v2");
+
+        // Values larger than the byte budget are never cached
+        cache.put(put("d", "large")).await.unwrap();
+        inner.put(put("d", "other")).await.unwrap();
+        assert_eq!(value(&cache, "d").await.unwrap(), "other");
+    }
+}
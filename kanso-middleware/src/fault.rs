@@ -0,0 +1,313 @@
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use kanso_client::{
+    Client, CopyRequest, CopyResponse, DeleteRequest, DeleteResponse, Error, GetRequest,
+    GetResponse, GetStreamResponse, HeadRequest, HeadResponse, ListRequest, ListResponse,
+    ObjectStore, PatchRequest, PatchResponse, Path, PutRequest, PutResponse, PutStreamRequest,
+};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Operation kinds that faults can target
+///
+/// Streaming gets and puts count as `Get` and `Put`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    Get,
+    Head,
+    Put,
+    Patch,
+    Delete,
+    List,
+    Copy,
+}
+
+/// Error returned by an injected failure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultError {
+    /// `Error::Transient`
+    Transient,
+    /// `Error::Timeout`
+    Timeout,
+    /// `Error::RateLimited` without a retry-after hint
+    RateLimited,
+    /// `Error::Backend` with the given status
+    Backend(u16),
+}
+
+impl FaultError {
+    fn to_error(self) -> Error {
+        match self {
+            FaultError::Transient => Error::Transient {
+                message: "injected fault".into(),
+                source: None,
+            },
+            FaultError::Timeout => Error::Timeout { source: None },
+            FaultError::RateLimited => Error::RateLimited { retry_after: None },
+            FaultError::Backend(status) => Error::Backend {
+                status,
+                body: "injected fault".into(),
+            },
+        }
+    }
+}
+
+/// A fault injected into a single operation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// Fail without reaching the wrapped store
+    Error(FaultError),
+    /// Wait before forwarding to the wrapped store
+    Latency(Duration),
+    /// Forward to the wrapped store, then report a transient error instead of
+    /// the response (a write commits but the client sees a failure)
+    DropResponse,
+    /// Serve a get from the value previously read through this store for the
+    /// key, ignoring writes since; other operations are forwarded unchanged
+    StaleRead,
+}
+
+struct FaultState {
+    rng: StdRng,
+    scripts: HashMap<Operation, VecDeque<Option<Fault>>>,
+    rules: Vec<(Operation, Fault, f64)>,
+    observed: HashMap<Path, GetResponse>,
+    log: Vec<(Operation, Fault)>,
+}
+
+/// ObjectStore wrapper that injects errors, latency, dropped responses and
+/// stale reads
+///
+/// Every operation first consumes the next entry of its script, if any (with
+/// `None` meaning no fault). Otherwise each probabilistic rule for the
+/// operation is rolled in the order added, and the first hit is injected.
+/// Rolls come from a seeded RNG, so a given seed and sequence of operations
+/// always injects the same faults.
+pub struct FaultyStore {
+    inner: Client,
+    state: Mutex<FaultState>,
+}
+
+impl FaultyStore {
+    /// Wrap a client, seeding the fault RNG
+    pub fn new(inner: Client, seed: u64) -> Self {
+        Self {
+            inner,
+            state: Mutex::new(FaultState {
+                rng: StdRng::seed_from_u64(seed),
+                scripts: HashMap::new(),
+                rules: Vec::new(),
+                observed: HashMap::new(),
+                log: Vec::new(),
+            }),
+        }
+    }
+
+    /// Get a reference to the wrapped client
+    pub fn inner(&self) -> &Client {
+        &self.inner
+    }
+
+    /// Queue faults for the next calls of an operation, one entry per call
+    pub fn script(&self, operation: Operation, faults: impl IntoIterator<Item = Option<Fault>>) {
+        let mut state = self.state.lock().unwrap();
+        state.scripts.entry(operation).or_default().extend(faults);
+    }
+
+    /// Inject a fault into calls of an operation with the given probability
+    pub fn inject(&self, operation: Operation, fault: Fault, probability: f64) {
+        let mut state = self.state.lock().unwrap();
+        state.rules.push((operation, fault, probability));
+    }
+
+    /// Remove all scripted and probabilistic faults
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.scripts.clear();
+        state.rules.clear();
+    }
+
+    /// Faults injected so far, in order
+    pub fn log(&self) -> Vec<(Operation, Fault)> {
+        self.state.lock().unwrap().log.clone()
+    }
+
+    fn next_fault(&self, operation: Operation) -> Option<Fault> {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        let fault = match state
+            .scripts
+            .get_mut(&operation)
+            .and_then(VecDeque::pop_front)
+        {
+            Some(fault) => fault,
+            None => {
+                // Roll every matching rule so the RNG sequence does not depend
+                // on which rule hits
+                let mut hit = None;
+                for (op, fault, probability) in &state.rules {
+                    if *op == operation && state.rng.random_bool(probability.clamp(0.0, 1.0)) {
+                        hit = hit.or_else(|| Some(fault.clone()));
+                    }
+                }
+                hit
+            }
+        };
+        if let Some(fault) = &fault {
+            state.log.push((operation, fault.clone()));
+        }
+        fault
+    }
+
+    /// Run an operation under the next fault for it
+    async fn run<T>(
+        &self,
+        fault: Option<Fault>,
+        op: impl Future<Output = Result<T, Error>>,
+    ) -> Result<T, Error> {
+        match fault {
+            Some(Fault::Error(error)) => Err(error.to_error()),
+            Some(Fault::Latency(latency)) => {
+                tokio::time::sleep(latency).await;
+                op.await
+            }
+            Some(Fault::DropResponse) => {
+                op.await?;
+                Err(Error::Transient {
+                    message: "injected fault: response dropped".into(),
+                    source: None,
+                })
+            }
+            Some(Fault::StaleRead) | None => op.await,
+        }
+    }
+
+    /// The value previously read for a full-object get, for stale reads
+    fn stale(&self, fault: &Option<Fault>, request: &GetRequest) -> Option<GetResponse> {
+        if *fault != Some(Fault::StaleRead) || !is_latest_full_read(request) {
+            return None;
+        }
+        let state = self.state.lock().unwrap();
+        state.observed.get(&request.key).cloned()
+    }
+
+    fn observe(&self, request: &GetRequest, response: &Option<GetResponse>) {
+        if let (true, Some(response)) = (is_latest_full_read(request), response) {
+            let mut state = self.state.lock().unwrap();
+            state.observed.insert(request.key.clone(), response.clone());
+        }
+    }
+}
+
+/// Whether a get unconditionally reads the latest full value, the only reads
+/// served stale
+fn is_latest_full_read(request: &GetRequest) -> bool {
+    request.range.is_none() && request.version.is_none() && request.if_version_not_matches.is_none()
+}
+
+#[async_trait]
+impl ObjectStore for FaultyStore {
+    async fn get(&self, request: GetRequest) -> Result<Option<GetResponse>, Error> {
+        let fault = self.next_fault(Operation::Get);
+        if let Some(stale) = self.stale(&fault, &request) {
+            return Ok(Some(stale));
+        }
+        let response = self.run(fault, self.inner.get(request.clone())).await?;
+        self.observe(&request, &response);
+        Ok(response)
+    }
+
+    async fn head(&self, request: HeadRequest) -> Result<Option<HeadResponse>, Error> {
+        let fault = self.next_fault(Operation::Head);
+        self.run(fault, self.inner.head(request)).await
+    }
+
+    async fn put(&self, request: PutRequest) -> Result<PutResponse, Error> {
+        let fault = self.next_fault(Operation::Put);
+        self.run(fault, self.inner.put(request)).await
+    }
+
+    async fn get_stream(&self, request: GetRequest) -> Result<Option<GetStreamResponse>, Error> {
+        let fault = self.next_fault(Operation::Get);
+        if let Some(stale) = self.stale(&fault, &request) {
+            return Ok(Some(stale.into()));
+        }
+        self.run(fault, self.inner.get_stream(request)).await
+    }
+
+    async fn put_stream(&self, request: PutStreamRequest) -> Result<PutResponse, Error> {
+        let fault = self.next_fault(Operation::Put);
+        self.run(fault, self.inner.put_stream(request)).await
+    }
+
+    async fn patch(&self, request: PatchRequest) -> Result<PatchResponse, Error> {
+        let fault = self.next_fault(Operation::Patch);
+        self.run(fault, self.inner.patch(request)).await
+    }
+
+    async fn delete(&self, request: DeleteRequest) -> Result<DeleteResponse, Error> {
+        let fault = self.next_fault(Operation::Delete);
+        self.run(fault, self.inner.delete(request)).await
+    }
+
+    async fn list(&self, request: ListRequest) -> Result<ListResponse, Error> {
+        let fault = self.next_fault(Operation::List);
+        self.run(fault, self.inner.list(request)).await
+    }
+
+    async fn copy(&self, request: CopyRequest) -> Result<CopyResponse, Error> {
+        let fault = self.next_fault(Operation::Copy);
+        self.run(fault, self.inner.copy(request)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+    use kanso_inmemory::InMemoryStore;
+
+    use super::*;
+
+    fn store(seed: u64) -> FaultyStore {
+        FaultyStore::new(Arc::new(InMemoryStore::new()), seed)
+    }
+
+    #[tokio::test]
+    async fn test_faults_are_deterministic_for_a_seed() {
+        let mut logs = Vec::new();
+        for _ in 0..2 {
+            let store = store(42);
+            store.inject(Operation::Get, Fault::Error(FaultError::Transient), 0.5);
+            for _ in 0..32 {
+                let _ = store.get(GetRequest::new("key").unwrap()).await;
+            }
+            logs.push(store.log());
+        }
+        assert!(!logs[0].is_empty() && logs[0].len() < 32);
+        assert_eq!(logs[0], logs[1]);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_responses_and_stale_reads() {
+        let store = store(0);
+        store.script(Operation::Put, [None, Some(Fault::DropResponse)]);
+        store.script(Operation::Get, [None, Some(Fault::StaleRead), None]);
+
+        let put = |value: &'static str| PutRequest::new("key", Bytes::from(value)).unwrap();
+        let get = || GetRequest::new("key").unwrap();
+
+        store.put(put("v1")).await.unwrap();
+        assert_eq!(store.get(get()).await.unwrap().unwrap().value, "v1");
+
+        // The write commits even though the client sees an error
+        assert!(store.put(put("v2")).await.unwrap_err().is_retryable());
+        assert_eq!(store.get(get()).await.unwrap().unwrap().value, "v1");
+        assert_eq!(store.get(get()).await.unwrap().unwrap().value, "v2");
+    }
+}
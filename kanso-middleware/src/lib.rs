@@ -0,0 +1,14 @@
+//! Composable wrappers that add behavior to any ObjectStore
+//!
+//! Each wrapper is itself an `ObjectStore`, so wrappers can be stacked and the
+//! result turned into a `Client` with `Arc::new`.
+
+mod cache;
+mod fault;
+mod prefix;
+mod retry;
+
+pub use cache::{CachePolicy, CachingStore};
+pub use fault::{Fault, FaultError, FaultyStore, Operation};
+pub use prefix::PrefixedStore;
+pub use retry::{RetryPolicy, RetryStore};
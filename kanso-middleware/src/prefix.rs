@@ -0,0 +1,203 @@
+use async_trait::async_trait;
+use kanso_client::{
+    CopyRequest, CopyResponse, DeleteRequest, DeleteResponse, Error, GetRequest, GetResponse,
+    GetStreamResponse, HeadRequest, HeadResponse, ListRequest, ListResponse, ObjectStore,
+    PatchRequest, PatchResponse, Path, PathPrefix, PutRequest, PutResponse, PutStreamRequest,
+};
+
+/// ObjectStore wrapper that places every key below a fixed prefix
+///
+/// Keys are joined onto the prefix before reaching the wrapped store, and
+/// listings strip it again, so callers see a store rooted at the prefix.
+/// Since keys are validated Paths without relative segments, no request can
+/// reach outside the prefix. With a bucket (or container) as the prefix, this
+/// binds a bucket-addressed store such as GcsStore to that one bucket.
+///
+/// Page tokens are passed through from the wrapped store unchanged.
+#[derive(Debug, Clone)]
+pub struct PrefixedStore<S> {
+    inner: S,
+    prefix: PathPrefix,
+}
+
+impl<S: ObjectStore> PrefixedStore<S> {
+    /// Wrap a store, placing every key below `prefix`
+    pub fn new(inner: S, prefix: PathPrefix) -> Self {
+        Self { inner, prefix }
+    }
+
+    /// Get a reference to the wrapped store
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Get the prefix keys are placed below
+    pub fn prefix(&self) -> &PathPrefix {
+        &self.prefix
+    }
+
+    fn key(&self, key: &Path) -> Path {
+        self.prefix.join_path(key)
+    }
+}
+
+#[async_trait]
+impl<S: ObjectStore> ObjectStore for PrefixedStore<S> {
+    async fn get(&self, mut request: GetRequest) -> Result<Option<GetResponse>, Error> {
+        request.key = self.key(&request.key);
+        self.inner.get(request).await
+    }
+
+    async fn get_stream(
+        &self,
+        mut request: GetRequest,
+    ) -> Result<Option<GetStreamResponse>, Error> {
+        request.key = self.key(&request.key);
+        self.inner.get_stream(request).await
+    }
+
+    async fn head(&self, mut request: HeadRequest) -> Result<Option<HeadResponse>, Error> {
+        request.key = self.key(&request.key);
+        self.inner.head(request).await
+    }
+
+    async fn put(&self, mut request: PutRequest) -> Result<PutResponse, Error> {
+        request.key = self.key(&request.key);
+        self.inner.put(request).await
+    }
+
+    async fn put_stream(&self, mut request: PutStreamRequest) -> Result<PutResponse, Error> {
+        request.key = self.key(&request.key);
+        self.inner.put_stream(request).await
+    }
+
+    async fn patch(&self, mut request: PatchRequest) -> Result<PatchResponse, Error> {
+        request.key = self.key(&request.key);
+        self.inner.patch(request).await
+    }
+
+    async fn delete(&self, mut request: DeleteRequest) -> Result<DeleteResponse, Error> {
+        request.key = self.key(&request.key);
+        self.inner.delete(request).await
+    }
+
+    async fn list(&self, mut request: ListRequest) -> Result<ListResponse, Error> {
+        let list_prefix = self.prefix.to_list_prefix();
+        request.prefix = format!("{list_prefix}{}", request.prefix);
+        request.start_after = request
+            .start_after
+            .map(|start_after| format!("{list_prefix}{start_after}"));
+
+        let mut response = self.inner.list(request).await?;
+        // Entries outside the prefix cannot match the listing; drop any a
+        // misbehaving store returns rather than exposing them
+        response
+            .objects
+            .retain_mut(|object| match object.key.strip_prefix(&self.prefix) {
+                Some(key) => {
+                    object.key = key;
+                    true
+                }
+                None => false,
+            });
+        response.common_prefixes = response
+            .common_prefixes
+            .into_iter()
+            .filter_map(|prefix| {
+                prefix
+                    .strip_prefix(&list_prefix)
+                    .filter(|rest| !rest.is_empty())
+                    .map(str::to_string)
+            })
+            .collect();
+        Ok(response)
+    }
+
+    async fn copy(&self, mut request: CopyRequest) -> Result<CopyResponse, Error> {
+        request.from = self.key(&request.from);
+        request.to = self.key(&request.to);
+        self.inner.copy(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+    use kanso_client::Client;
+    use kanso_inmemory::InMemoryStore;
+
+    use super::*;
+
+    fn store(prefix: &str) -> (Client, PrefixedStore<Client>) {
+        let inner: Client = Arc::new(InMemoryStore::new());
+        let prefix = PathPrefix::new(prefix).unwrap();
+        (inner.clone(), PrefixedStore::new(inner, prefix))
+    }
+
+    fn put(key: &str, value: &'static str) -> PutRequest {
+        PutRequest::new(key, Bytes::from(value)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_compliance() {
+        let (_, prefixed) = store("tenant/service");
+        let prefixed: Client = Arc::new(prefixed);
+        kanso_backends_test_suite::run_compliance_tests(&prefixed, "").await;
+    }
+
+    #[tokio::test]
+    async fn test_keys_are_placed_below_the_prefix() {
+        let (inner, prefixed) = store("tenant");
+        prefixed.put(put("a/b", "value")).await.unwrap();
+        let stored = inner.get(GetRequest::new("tenant/a/b").unwrap()).await;
+        assert_eq!(stored.unwrap().unwrap().value, "value");
+
+        // Keys outside the prefix, including its siblings, stay invisible
+        inner.put(put("a/b", "outside")).await.unwrap();
+        inner.put(put("tenant2/a", "sibling")).await.unwrap();
+        let resp = prefixed.get(GetRequest::new("a/b").unwrap()).await;
+        assert_eq!(resp.unwrap().unwrap().value, "value");
+
+        let copy = CopyRequest::new("a/b", "c").unwrap();
+        prefixed.copy(copy).await.unwrap();
+        let copied = inner.head(HeadRequest::new("tenant/c").unwrap()).await;
+        assert!(copied.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_list_strips_the_prefix() {
+        let (inner, prefixed) = store("tenant/");
+        for key in ["a", "b/1", "b/2"] {
+            prefixed.put(put(key, "value")).await.unwrap();
+        }
+        inner.put(put("tenant2/a", "sibling")).await.unwrap();
+
+        let page = prefixed
+            .list(ListRequest::new("").unwrap().delimiter('/'))
+            .await
+            .unwrap();
+        let keys: Vec<_> = page.objects.iter().map(|o| o.key.as_str()).collect();
+        assert_eq!(keys, ["a"]);
+        assert_eq!(page.common_prefixes, ["b/"]);
+
+        let page = prefixed
+            .list(ListRequest::new("b/").unwrap().start_after("b/1"))
+            .await
+            .unwrap();
+        let keys: Vec<_> = page.objects.iter().map(|o| o.key.as_str()).collect();
+        assert_eq!(keys, ["b/2"]);
+
+        let pages = prefixed
+            .list(ListRequest::new("").unwrap().max_results(1))
+            .await
+            .unwrap();
+        let next = ListRequest::new("")
+            .unwrap()
+            .max_results(1)
+            .page_token(pages.next_page_token.unwrap());
+        let page = prefixed.list(next).await.unwrap();
+        assert_eq!(page.objects[0].key.as_str(), "b/1");
+    }
+}
@@ -0,0 +1,470 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use kanso_client::{
+    CopyRequest, CopyResponse, DeleteRequest, DeleteResponse, Error, GetRequest, GetResponse,
+    GetStreamResponse, HeadRequest, HeadResponse, ListRequest, ListResponse, ObjectStore,
+    PatchRequest, PatchResponse, PutRequest, PutResponse, PutStreamRequest,
+};
+use rand::Rng;
+
+/// Policy controlling how RetryStore retries failed operations
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts, including the first
+    pub max_attempts: u32,
+    /// Upper bound of the backoff before the first retry
+    pub initial_backoff: Duration,
+    /// Upper bound of the backoff before any retry
+    pub max_backoff: Duration,
+    /// Total time after which no further retries are started
+    pub deadline: Option<Duration>,
+}
+
+impl RetryPolicy {
+    /// Create a policy with 5 attempts, 100ms initial and 10s max backoff
+    pub fn new() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            deadline: None,
+        }
+    }
+
+    /// Set the maximum number of attempts, including the first
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Set the upper bound of the backoff before the first retry
+    pub fn initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    /// Set the upper bound of the backoff before any retry
+    pub fn max_backoff(mut self, backoff: Duration) -> Self {
+        self.max_backoff = backoff;
+        self
+    }
+
+    /// Stop retrying once a retry would start after this much time
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Backoff before the given retry (1-based), with full jitter
+    fn backoff(&self, retry: u32) -> Duration {
+        let exp = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry - 1))
+            .min(self.max_backoff);
+        exp.mul_f64(rand::rng().random::<f64>())
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// ObjectStore wrapper that retries retryable errors with exponential backoff
+///
+/// Reads, unconditional writes and streaming reads are simply retried.
+/// Conditional writes (including copies) are retried too, but a retryable
+/// failure (other than rate limiting) may have hidden a committed write, which
+/// the next attempt then reports as a condition failure. In that case the
+/// object is re-read, and if it holds exactly what was written, the write is
+/// reported as a success with the current version. Deletes that find the
+/// object gone after such a failure likewise succeed. Streaming puts cannot be
+/// replayed and are never retried.
+///
+/// The check compares value and metadata only, so it cannot tell a committed
+/// write from another client's write of the same value and metadata (or a
+/// deletion by another client, for deletes) in between: that write is then
+/// reported as this one's success. Writers that must tell them apart should
+/// include something unique to the write, such as a request ID in the
+/// metadata.
+#[derive(Debug, Clone)]
+pub struct RetryStore<S> {
+    inner: S,
+    policy: RetryPolicy,
+}
+
+impl<S: ObjectStore> RetryStore<S> {
+    /// Wrap a store with the default retry policy
+    pub fn new(inner: S) -> Self {
+        Self::with_policy(inner, RetryPolicy::default())
+    }
+
+    /// Wrap a store with a custom retry policy
+    pub fn with_policy(inner: S, policy: RetryPolicy) -> Self {
+        Self { inner, policy }
+    }
+
+    /// Get a reference to the wrapped store
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Run an operation until it succeeds, fails permanently or the policy
+    /// is exhausted
+    ///
+    /// The operation is told whether an earlier attempt failed in a way that
+    /// may have committed it.
+    async fn retry<T, F, Fut>(&self, mut op: F) -> Result<T, Error>
+    where
+        F: FnMut(bool) -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let start = Instant::now();
+        let mut ambiguous = false;
+        let mut attempt = 1;
+        loop {
+            let error = match op(ambiguous).await {
+                Ok(value) => return Ok(value),
+                Err(error) if error.is_retryable() => error,
+                Err(error) => return Err(error),
+            };
+            if attempt >= self.policy.max_attempts {
+                return Err(error);
+            }
+
+            let mut backoff = self.policy.backoff(attempt);
+            if let Error::RateLimited {
+                retry_after: Some(retry_after),
+            } = &error
+            {
+                backoff = backoff.max(*retry_after);
+            }
+            if let Some(deadline) = self.policy.deadline
+                && start.elapsed() + backoff > deadline
+            {
+                return Err(error);
+            }
+
+            // Rate limiting rejects a request before it is applied
+            ambiguous |= !matches!(error, Error::RateLimited { .. });
+            attempt += 1;
+            tokio::time::sleep(backoff).await;
+        }
+    }
+}
+
+#[async_trait]
+impl<S: ObjectStore> ObjectStore for RetryStore<S> {
+    async fn get(&self, request: GetRequest) -> Result<Option<GetResponse>, Error> {
+        self.retry(|_| self.inner.get(request.clone())).await
+    }
+
+    async fn head(&self, request: HeadRequest) -> Result<Option<HeadResponse>, Error> {
+        self.retry(|_| self.inner.head(request.clone())).await
+    }
+
+    async fn put(&self, request: PutRequest) -> Result<PutResponse, Error> {
+        self.retry(|ambiguous| {
+            let request = request.clone();
+            async move {
+                match self.inner.put(request.clone()).await {
+                    Err(Error::ConditionFailed { condition }) if ambiguous => {
+                        // An earlier attempt may have committed: check whether
+                        // the object holds exactly what we wrote
+                        let current = self
+                            .inner
+                            .get(GetRequest {
+                                key: request.key,
+                                range: None,
+                                version: None,
+                                if_version_not_matches: None,
+                            })
+                            .await?;
+                        match current {
+                            Some(current)
+                                if current.value == request.value
+                                    && current.metadata == request.metadata.unwrap_or_default() =>
+                            {
+                                Ok(PutResponse {
+                                    version: current.version,
+                                })
+                            }
+                            _ => Err(Error::ConditionFailed { condition }),
+                        }
+                    }
+                    result => result,
+                }
+            }
+        })
+        .await
+    }
+
+    async fn get_stream(&self, request: GetRequest) -> Result<Option<GetStreamResponse>, Error> {
+        self.retry(|_| self.inner.get_stream(request.clone())).await
+    }
+
+    async fn put_stream(&self, request: PutStreamRequest) -> Result<PutResponse, Error> {
+        self.inner.put_stream(request).await
+    }
+
+    async fn patch(&self, request: PatchRequest) -> Result<PatchResponse, Error> {
+        self.retry(|ambiguous| {
+            let request = request.clone();
+            async move {
+                match self.inner.patch(request.clone()).await {
+                    Err(Error::ConditionFailed { condition }) if ambiguous => {
+                        // An earlier attempt may have committed: check whether
+                        // the object carries exactly the metadata we wrote
+                        let current = self.inner.head(HeadRequest { key: request.key }).await?;
+                        match current {
+                            Some(current) if current.metadata == request.metadata => {
+                                Ok(PatchResponse {
+                                    version: current.version,
+                                })
+                            }
+                            _ => Err(Error::ConditionFailed { condition }),
+                        }
+                    }
+                    result => result,
+                }
+            }
+        })
+        .await
+    }
+
+    async fn delete(&self, request: DeleteRequest) -> Result<DeleteResponse, Error> {
+        self.retry(|ambiguous| {
+            let request = request.clone();
+            async move {
+                match self.inner.delete(request).await {
+                    // An earlier attempt may have deleted the object
+                    Err(Error::NotFound) if ambiguous => Ok(DeleteResponse),
+                    result => result,
+                }
+            }
+        })
+        .await
+    }
+
+    async fn list(&self, request: ListRequest) -> Result<ListResponse, Error> {
+        self.retry(|_| self.inner.list(request.clone())).await
+    }
+
+    async fn copy(&self, request: CopyRequest) -> Result<CopyResponse, Error> {
+        self.retry(|ambiguous| {
+            let request = request.clone();
+            async move {
+                match self.inner.copy(request.clone()).await {
+                    Err(Error::ConditionFailed { condition }) if ambiguous => {
+                        // An earlier attempt may have committed: check whether
+                        // the destination holds exactly the copied source
+                        let read = |key, version| GetRequest {
+                            key,
+                            range: None,
+                            version,
+                            if_version_not_matches: None,
+                        };
+                        let source = self
+                            .inner
+                            .get(read(request.from, request.source_version))
+                            .await?;
+                        let current = self.inner.get(read(request.to, None)).await?;
+                        match (source, current) {
+                            (Some(source), Some(current))
+                                if current.value == source.value
+                                    && current.metadata
+                                        == request
+                                            .metadata_override
+                                            .unwrap_or_else(|| source.metadata.clone()) =>
+                            {
+                                Ok(CopyResponse {
+                                    version: current.version,
+                                })
+                            }
+                            _ => Err(Error::ConditionFailed { condition }),
+                        }
+                    }
+                    result => result,
+                }
+            }
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use bytes::Bytes;
+    use kanso_client::{Metadata, Version};
+    use kanso_inmemory::InMemoryStore;
+
+    use super::*;
+    use crate::{Fault, FaultyStore, Operation};
+
+    /// Store whose next writes fail with a transient error, optionally after
+    /// applying the write
+    struct Flaky {
+        inner: InMemoryStore,
+        failures: Mutex<Vec<bool>>,
+    }
+
+    impl Flaky {
+        fn new(failures: impl IntoIterator<Item = bool>) -> Self {
+            let mut failures: Vec<_> = failures.into_iter().collect();
+            failures.reverse();
+            Self {
+                inner: InMemoryStore::new(),
+                failures: Mutex::new(failures),
+            }
+        }
+
+        /// Pop the next scripted failure: `Some(commit)` if the call fails
+        fn next_failure(&self) -> Option<bool> {
+            self.failures.lock().unwrap().pop()
+        }
+    }
+
+    fn transient() -> Error {
+        Error::Transient {
+            message: "injected".into(),
+            source: None,
+        }
+    }
+
+    #[async_trait]
+    impl ObjectStore for Flaky {
+        async fn get(&self, request: GetRequest) -> Result<Option<GetResponse>, Error> {
+            match self.next_failure() {
+                Some(_) => Err(transient()),
+                None => self.inner.get(request).await,
+            }
+        }
+
+        async fn head(&self, request: HeadRequest) -> Result<Option<HeadResponse>, Error> {
+            self.inner.head(request).await
+        }
+
+        async fn put(&self, request: PutRequest) -> Result<PutResponse, Error> {
+            match self.next_failure() {
+                Some(true) => self.inner.put(request).await.and(Err(transient())),
+                Some(false) => Err(transient()),
+                None => self.inner.put(request).await,
+            }
+        }
+
+        async fn patch(&self, request: PatchRequest) -> Result<PatchResponse, Error> {
+            self.inner.patch(request).await
+        }
+
+        async fn delete(&self, request: DeleteRequest) -> Result<DeleteResponse, Error> {
+            match self.next_failure() {
+                Some(true) => self.inner.delete(request).await.and(Err(transient())),
+                Some(false) => Err(transient()),
+                None => self.inner.delete(request).await,
+            }
+        }
+
+        async fn list(&self, request: ListRequest) -> Result<ListResponse, Error> {
+            self.inner.list(request).await
+        }
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy::new()
+            .max_attempts(3)
+            .initial_backoff(Duration::from_millis(1))
+    }
+
+    #[tokio::test]
+    async fn test_retries_transient_errors() {
+        let store = RetryStore::with_policy(Flaky::new([false, false]), policy());
+        let request = PutRequest::new("key", Bytes::from("v")).unwrap();
+        store.put(request).await.unwrap();
+
+        // Three failures exhaust the three attempts
+        let store = RetryStore::with_policy(Flaky::new([false, false, false]), policy());
+        let request = GetRequest::new("key").unwrap();
+        assert!(matches!(
+            store.get(request).await,
+            Err(Error::Transient { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_reports_conflicts_after_transient_errors() {
+        let store = RetryStore::with_policy(Flaky::new([]), policy());
+        let request = PutRequest::new("key", Bytes::from("v")).unwrap();
+        store.put(request.if_absent()).await.unwrap();
+
+        // The retry after an uncommitted failure finds someone else's value
+        store.inner().failures.lock().unwrap().push(false);
+        let request = PutRequest::new("key", Bytes::from("w")).unwrap();
+        let result = store.put(request.if_absent()).await;
+        assert!(matches!(result, Err(Error::ConditionFailed { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_detects_committed_conditional_writes() {
+        // The first attempt commits but reports a transient error
+        let store = RetryStore::with_policy(Flaky::new([true]), policy());
+        let request = PutRequest::new("key", Bytes::from("v"))
+            .unwrap()
+            .metadata(Metadata::with("k", "v"))
+            .if_absent();
+        let version = store.put(request).await.unwrap().version;
+        let current = store
+            .inner()
+            .inner
+            .get(GetRequest::new("key").unwrap())
+            .await;
+        assert_eq!(current.unwrap().unwrap().version, version);
+
+        // So is a committed compare-and-swap, while a stale version fails
+        store.inner().failures.lock().unwrap().push(true);
+        let request = PutRequest::new("key", Bytes::from("w"))
+            .unwrap()
+            .if_version_matches(version);
+        store.put(request.clone()).await.unwrap();
+        let request = request.if_version_matches(Version::new("stale"));
+        let result = store.put(request).await;
+        assert!(matches!(result, Err(Error::ConditionFailed { .. })));
+
+        // A delete that committed before failing is reported as a success
+        store.inner().failures.lock().unwrap().push(true);
+        store
+            .delete(DeleteRequest::new("key").unwrap())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_detects_committed_conditional_copies() {
+        let faulty = FaultyStore::new(Arc::new(InMemoryStore::new()), 0);
+        let store = RetryStore::with_policy(faulty, policy());
+        let request = PutRequest::new("source", Bytes::from("v")).unwrap();
+        store.put(request).await.unwrap();
+
+        store
+            .inner()
+            .script(Operation::Copy, [Some(Fault::DropResponse)]);
+        let request = CopyRequest::new("source", "destination")
+            .unwrap()
+            .metadata(Metadata::with("k", "v"))
+            .if_absent();
+        let version = store.copy(request.clone()).await.unwrap().version;
+        let current = store
+            .inner()
+            .inner()
+            .get(GetRequest::new("destination").unwrap());
+        assert_eq!(current.await.unwrap().unwrap().version, version);
+
+        // Without a dropped response the destination is a genuine conflict
+        let result = store.copy(request).await;
+        assert!(matches!(result, Err(Error::ConditionFailed { .. })));
+    }
+}
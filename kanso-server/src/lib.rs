@@ -0,0 +1,479 @@
+//! HTTP gateway exposing any ObjectStore over REST
+//!
+//! Objects are addressed as `/objects/{key}`:
+//!
+//! - `GET` returns the value (honoring `Range`, and reading a past version
+//!   with `?version=`, or 304 if it matches `If-None-Match: <etag>`), `HEAD`
+//!   only its headers
+//! - `PUT` stores the request body, `PATCH` replaces the metadata
+//! - `DELETE` removes the object
+//!
+//! Versions are sent as ETags (the percent-encoded version in double quotes).
+//! Write conditions are sent as `If-None-Match: *` (absent), `If-Match: *`
+//! (exists), `If-Match: <etag>`, `If-None-Match: <etag>` or, for metadata
+//! conditions, one `x-kanso-if-meta-*` header per expected entry. Metadata
+//! travels in `x-kanso-meta-*` headers, so keys are case-insensitive and
+//! values must be valid header values.
+//!
+//! `GET /objects` lists objects, taking `prefix`, `delimiter`, `start_after`,
+//! `page_token` and `max_results` query parameters and returning a JSON page:
+//! `{"objects": [{"key", "version", "size", "metadata"}], "common_prefixes",
+//! "next_page_token"}`.
+//!
+//! Errors map onto status codes: 304 not modified, 404 not found, 412
+//! condition failed, 416 range not satisfiable, 400 invalid request, 401/403
+//! auth, 429 rate limited (with `Retry-After`), 504 timeout and 503 for other
+//! retryable failures.
+//!
+//! The gateway does not authenticate or authorize requests: any client that
+//! can reach it can read, overwrite and delete every object. Serve it only on
+//! a trusted network, or behind a proxy that authenticates clients, or wrap
+//! the `router` in an authorizing layer with `Router::layer`.
+
+use std::collections::HashMap;
+
+use axum::body::Body;
+use axum::extract::{self, Query, State};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use futures::TryStreamExt;
+use kanso_client::http::{self, IF_META_PREFIX, META_PREFIX, etag};
+use kanso_client::{
+    Client, Condition, DeleteRequest, Error, GetRange, GetRequest, HeadRequest, ListRequest,
+    Metadata, PatchRequest, Path, PutStreamRequest, Version,
+};
+use serde_json::json;
+
+/// Build a router serving the store
+///
+/// The router performs no authentication (see the crate documentation).
+pub fn router(client: Client) -> Router {
+    Router::new()
+        .route("/objects", get(list))
+        .route(
+            "/objects/{*key}",
+            get(get_object)
+                .head(head_object)
+                .put(put_object)
+                .patch(patch_object)
+                .delete(delete_object),
+        )
+        .with_state(client)
+}
+
+/// Serve the store on a listener until the server fails
+///
+/// Every client that can connect gets full access to the store.
+pub async fn serve(listener: tokio::net::TcpListener, client: Client) -> std::io::Result<()> {
+    axum::serve(listener, router(client)).await
+}
+
+/// An error rendered as a status code and message
+struct Failure(Error);
+
+impl From<Error> for Failure {
+    fn from(error: Error) -> Self {
+        Failure(error)
+    }
+}
+
+impl IntoResponse for Failure {
+    fn into_response(self) -> Response {
+        let status = match &self.0 {
+            Error::NotModified => return StatusCode::NOT_MODIFIED.into_response(),
+            Error::NotFound => StatusCode::NOT_FOUND,
+            Error::ConditionFailed { .. } => StatusCode::PRECONDITION_FAILED,
+            Error::RangeNotSatisfiable => StatusCode::RANGE_NOT_SATISFIABLE,
+            Error::InvalidRequest { .. } => StatusCode::BAD_REQUEST,
+            Error::Unauthorized { .. } => StatusCode::UNAUTHORIZED,
+            Error::PermissionDenied { .. } => StatusCode::FORBIDDEN,
+            Error::RateLimited { retry_after } => {
+                let mut response = StatusCode::TOO_MANY_REQUESTS.into_response();
+                if let Some(retry_after) = retry_after {
+                    response
+                        .headers_mut()
+                        .insert("retry-after", HeaderValue::from(retry_after.as_secs()));
+                }
+                return response;
+            }
+            Error::Timeout { .. } => StatusCode::GATEWAY_TIMEOUT,
+            error if error.is_retryable() => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status, self.0.to_string()).into_response()
+    }
+}
+
+type Result<T> = std::result::Result<T, Failure>;
+
+fn invalid(message: impl Into<String>) -> Failure {
+    Failure(Error::InvalidRequest {
+        message: message.into(),
+    })
+}
+
+fn object_key(key: String) -> Result<Path> {
+    Path::new(key).map_err(|e| invalid(format!("invalid key: {e}")))
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+fn header_value(value: impl AsRef<str>) -> Result<HeaderValue> {
+    HeaderValue::from_str(value.as_ref()).map_err(|e| invalid(format!("invalid header: {e}")))
+}
+
+/// Parse a version from an ETag
+fn parse_etag(etag: &str) -> Result<Version> {
+    http::parse_etag(etag).ok_or_else(|| invalid(format!("invalid ETag {etag}")))
+}
+
+/// Parse the condition carried by `If-None-Match`, `If-Match` or
+/// `x-kanso-if-meta-*` headers
+fn parse_condition(headers: &HeaderMap) -> Result<Option<Condition>> {
+    let metadata = parse_metadata(headers, IF_META_PREFIX)?;
+    match (
+        header(headers, "if-none-match"),
+        header(headers, "if-match"),
+        metadata.is_empty(),
+    ) {
+        (None, None, true) => Ok(None),
+        (Some("*"), None, true) => Ok(Some(Condition::IfAbsent)),
+        (Some(etag), None, true) => Ok(Some(Condition::IfVersionNotMatches(parse_etag(etag)?))),
+        (None, Some("*"), true) => Ok(Some(Condition::IfExists)),
+        (None, Some(etag), true) => Ok(Some(Condition::IfVersionMatches(parse_etag(etag)?))),
+        (None, None, false) => Ok(Some(Condition::IfMetadataMatches(metadata))),
+        _ => Err(invalid("unsupported combination of conditions")),
+    }
+}
+
+/// Collect metadata from headers named with a prefix
+fn parse_metadata(headers: &HeaderMap, prefix: &str) -> Result<Metadata> {
+    let mut metadata = Metadata::new();
+    for (name, value) in headers {
+        if let Some(key) = name.as_str().strip_prefix(prefix) {
+            let value = value
+                .to_str()
+                .map_err(|_| invalid(format!("invalid metadata value for '{key}'")))?;
+            metadata.insert(key, value);
+        }
+    }
+    Ok(metadata)
+}
+
+/// Add the ETag and `x-kanso-meta-*` headers describing an object
+fn describe(headers: &mut HeaderMap, version: &Version, metadata: &Metadata) -> Result<()> {
+    headers.insert("etag", header_value(etag(version))?);
+    for (k, v) in &metadata.headers {
+        let name = HeaderName::try_from(format!("{META_PREFIX}{k}"))
+            .map_err(|e| invalid(format!("invalid metadata key '{k}': {e}")))?;
+        headers.insert(name, header_value(v)?);
+    }
+    Ok(())
+}
+
+/// Parse a `Range` header value
+fn parse_range(value: &str) -> Result<GetRange> {
+    let range = value
+        .strip_prefix("bytes=")
+        .and_then(|v| v.split_once('-'))
+        .and_then(|(start, end)| match (start, end) {
+            ("", suffix) => Some(GetRange::Suffix(suffix.parse().ok()?)),
+            (start, "") => Some(GetRange::From(start.parse().ok()?)),
+            (start, end) => {
+                let (start, end): (u64, u64) = (start.parse().ok()?, end.parse().ok()?);
+                Some(GetRange::Bounded {
+                    offset: start,
+                    length: end.checked_sub(start)? + 1,
+                })
+            }
+        });
+    range.ok_or_else(|| invalid(format!("unsupported range {value}")))
+}
+
+async fn get_object(
+    State(client): State<Client>,
+    extract::Path(key): extract::Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let range = header(&headers, "range").map(parse_range).transpose()?;
+    let object = GetRequest {
+        key: object_key(key)?,
+        range,
+        version: params.get("version").map(Version::new),
+        if_version_not_matches: header(&headers, "if-none-match")
+            .map(parse_etag)
+            .transpose()?,
+    }
+    .execute_stream(&client)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    // The store already rejected unsatisfiable ranges
+    let resolved = range.map(|range| range.resolve(object.size)).transpose()?;
+    let (status, length) = match &resolved {
+        Some(resolved) => (StatusCode::PARTIAL_CONTENT, resolved.end - resolved.start),
+        None => (StatusCode::OK, object.size),
+    };
+    let mut response = (status, Body::from_stream(object.stream)).into_response();
+    let response_headers = response.headers_mut();
+    describe(response_headers, &object.version, &object.metadata)?;
+    response_headers.insert("content-length", HeaderValue::from(length));
+    if let Some(resolved) = resolved {
+        response_headers.insert(
+            "content-range",
+            header_value(format!(
+                "bytes {}-{}/{}",
+                resolved.start,
+                resolved.end - 1,
+                object.size
+            ))?,
+        );
+    }
+    Ok(response)
+}
+
+async fn head_object(
+    State(client): State<Client>,
+    extract::Path(key): extract::Path<String>,
+) -> Result<Response> {
+    let head = HeadRequest {
+        key: object_key(key)?,
+    }
+    .execute(&client)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    let mut response = StatusCode::OK.into_response();
+    let headers = response.headers_mut();
+    describe(headers, &head.version, &head.metadata)?;
+    headers.insert("content-length", HeaderValue::from(head.size));
+    if let Some(content_type) = &head.content_type {
+        headers.insert("content-type", header_value(content_type)?);
+    }
+    if let Some(last_modified) = head.last_modified {
+        headers.insert(
+            "last-modified",
+            header_value(httpdate::fmt_http_date(last_modified))?,
+        );
+    }
+    Ok(response)
+}
+
+async fn put_object(
+    State(client): State<Client>,
+    extract::Path(key): extract::Path<String>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response> {
+    let stream = body.into_data_stream().map_err(|e| Error::Transient {
+        message: format!("failed to read request body: {e}"),
+        source: Some(e.into()),
+    });
+    let metadata = parse_metadata(&headers, META_PREFIX)?;
+    let response = PutStreamRequest {
+        key: object_key(key)?,
+        stream: Box::pin(stream),
+        condition: parse_condition(&headers)?,
+        metadata: (!metadata.headers.is_empty()).then_some(metadata),
+    }
+    .execute(&client)
+    .await?;
+    Ok((
+        [("etag", header_value(etag(&response.version))?)],
+        StatusCode::OK,
+    )
+        .into_response())
+}
+
+async fn patch_object(
+    State(client): State<Client>,
+    extract::Path(key): extract::Path<String>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let response = PatchRequest {
+        key: object_key(key)?,
+        metadata: parse_metadata(&headers, META_PREFIX)?,
+        condition: parse_condition(&headers)?,
+    }
+    .execute(&client)
+    .await?;
+    Ok((
+        [("etag", header_value(etag(&response.version))?)],
+        StatusCode::OK,
+    )
+        .into_response())
+}
+
+async fn delete_object(
+    State(client): State<Client>,
+    extract::Path(key): extract::Path<String>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    DeleteRequest {
+        key: object_key(key)?,
+        condition: parse_condition(&headers)?,
+    }
+    .execute(&client)
+    .await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+async fn list(
+    State(client): State<Client>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Response> {
+    let mut request = ListRequest::new(params.get("prefix").map_or("", String::as_str))
+        .map_err(|e| invalid(format!("invalid prefix: {e}")))?;
+    if let Some(delimiter) = params.get("delimiter") {
+        let mut chars = delimiter.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => request = request.delimiter(c),
+            _ => return Err(invalid(format!("invalid delimiter '{delimiter}'"))),
+        }
+    }
+    if let Some(start_after) = params.get("start_after") {
+        request = request.start_after(start_after);
+    }
+    if let Some(token) = params.get("page_token") {
+        request = request.page_token(token);
+    }
+    if let Some(max_results) = params.get("max_results") {
+        let max_results = max_results
+            .parse()
+            .map_err(|_| invalid(format!("invalid max_results '{max_results}'")))?;
+        request = request.max_results(max_results);
+    }
+
+    let response = request.execute(&client).await?;
+    let objects: Vec<_> = response
+        .objects
+        .iter()
+        .map(|object| {
+            json!({
+                "key": object.key.as_str(),
+                "version": object.version.as_str(),
+                "size": object.size,
+                "metadata": object.metadata.headers,
+            })
+        })
+        .collect();
+    Ok(Json(json!({
+        "objects": objects,
+        "common_prefixes": response.common_prefixes,
+        "next_page_token": response.next_page_token,
+    }))
+    .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use kanso_inmemory::InMemoryStore;
+    use reqwest::Method;
+
+    use super::*;
+
+    /// Serve an empty store, returning the URL of `key`
+    async fn start(key: &str) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/objects/{key}", listener.local_addr().unwrap());
+        tokio::spawn(serve(listener, Arc::new(InMemoryStore::new())));
+        url
+    }
+
+    async fn send(url: &str, method: Method, headers: &[(&str, &str)]) -> reqwest::Response {
+        let mut request = reqwest::Client::new().request(method, url);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        request.body("value").send().await.unwrap()
+    }
+
+    fn etag_of(response: &reqwest::Response) -> String {
+        response.headers()["etag"].to_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_reads() {
+        let url = start("key").await;
+        let etag = etag_of(&send(&url, Method::PUT, &[]).await);
+
+        let response = send(&url, Method::GET, &[("if-none-match", &etag)]).await;
+        assert_eq!(response.status(), 304);
+        let response = send(&url, Method::GET, &[("if-none-match", "\"0\"")]).await;
+        assert_eq!(response.status(), 200);
+
+        let response = send(&url, Method::GET, &[("range", "bytes=1-2")]).await;
+        assert_eq!(response.status(), 206);
+        assert_eq!(response.headers()["content-range"], "bytes 1-2/5");
+        assert_eq!(response.text().await.unwrap(), "al");
+        let response = send(&url, Method::GET, &[("range", "bytes=5-")]).await;
+        assert_eq!(response.status(), 416);
+
+        let missing = url.replace("/key", "/missing");
+        assert_eq!(send(&missing, Method::GET, &[]).await.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_write_conditions() {
+        let url = start("key").await;
+
+        // If-Match: * needs an existing object, If-None-Match: * a missing one
+        let response = send(&url, Method::PUT, &[("if-match", "*")]).await;
+        assert_eq!(response.status(), 412);
+        let response = send(&url, Method::PUT, &[("if-none-match", "*")]).await;
+        assert_eq!(response.status(), 200);
+        let v1 = etag_of(&response);
+        let response = send(&url, Method::PUT, &[("if-none-match", "*")]).await;
+        assert_eq!(response.status(), 412);
+        let response = send(
+            &url,
+            Method::PUT,
+            &[("if-match", "*"), ("x-kanso-meta-owner", "a")],
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        let v2 = etag_of(&response);
+
+        // Version conditions compare ETags
+        let response = send(&url, Method::PATCH, &[("if-match", &v1)]).await;
+        assert_eq!(response.status(), 412);
+        let response = send(&url, Method::DELETE, &[("if-none-match", &v2)]).await;
+        assert_eq!(response.status(), 412);
+
+        // Metadata conditions compare the x-kanso-if-meta-* entries
+        let response = send(
+            &url,
+            Method::PATCH,
+            &[("x-kanso-if-meta-owner", "b"), ("x-kanso-meta-owner", "c")],
+        )
+        .await;
+        assert_eq!(response.status(), 412);
+        let response = send(
+            &url,
+            Method::PATCH,
+            &[("x-kanso-if-meta-owner", "a"), ("x-kanso-meta-owner", "b")],
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        let response = send(&url, Method::HEAD, &[]).await;
+        assert_eq!(response.headers()["x-kanso-meta-owner"], "b");
+        let response = send(&url, Method::DELETE, &[("x-kanso-if-meta-owner", "b")]).await;
+        assert_eq!(response.status(), 204);
+
+        // Conditions cannot be combined
+        let response = send(
+            &url,
+            Method::PUT,
+            &[("if-match", "*"), ("x-kanso-if-meta-owner", "a")],
+        )
+        .await;
+        assert_eq!(response.status(), 400);
+    }
+}
@@ -0,0 +1,64 @@
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use kanso_client::{Client, Error};
+use kanso_fs::FsStore;
+use kanso_inmemory::InMemoryStore;
+use kanso_sqlite::SqliteStore;
+
+const USAGE: &str = "usage: kanso-server <address> <backend>
+
+backends:
+  memory          in-memory store (lost on exit)
+  fs:<dir>        filesystem store rooted at <dir>
+  sqlite:<file>   SQLite database at <file>
+
+The server does not authenticate clients: bind it to a trusted address.";
+
+/// Open the store described by a backend argument
+fn open(backend: &str) -> Result<Client, Error> {
+    match backend.split_once(':') {
+        None if backend == "memory" => Ok(Arc::new(InMemoryStore::new())),
+        Some(("fs", dir)) => Ok(Arc::new(FsStore::new(dir)?)),
+        Some(("sqlite", file)) => Ok(Arc::new(SqliteStore::open(file)?)),
+        _ => Err(Error::InvalidRequest {
+            message: format!("unknown backend '{backend}'"),
+        }),
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [address, backend] = args.as_slice() else {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    };
+
+    let client = match open(backend) {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("failed to open backend: {e}\n\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let listener = match tokio::net::TcpListener::bind(address).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("failed to listen on {address}: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    eprintln!(
+        "serving {backend} on http://{}",
+        listener.local_addr().unwrap()
+    );
+    match kanso_server::serve(listener, client).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("server error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}